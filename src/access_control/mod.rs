@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+
+use prettytable::{Cell, Row, Table};
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::solver::environment::*;
+use crate::solver::explicit::*;
+use crate::solver::*;
+
+// The access-control queuing task from Example 10.2: customers of different priorities arrive at
+// a single queue, and the agent either accepts the customer at the head of the queue, assigning
+// it to a free server and getting the reward equal to the priority, or rejects it with reward 0.
+// Each busy server becomes free with a fixed probability on every step. The queue is never empty,
+// and the priorities of the customers are random. The task is continuing, so the goal is to
+// maximize the average reward.
+
+// Possible priorities (and rewards for accepting) of the customers.
+const PRIORITIES: [u32; 4] = [1, 2, 4, 8];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct State {
+    free_servers: u32,
+    // Priority of the customer at the head of the queue.
+    priority: u32,
+}
+
+impl State {
+    pub fn new(free_servers: u32, priority: u32) -> State {
+        assert!(PRIORITIES.contains(&priority));
+        State {
+            free_servers,
+            priority,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Action {
+    Accept,
+    Reject,
+}
+
+pub struct AccessControl {
+    server_count: u32,
+    // Probability that a busy server becomes free on a step.
+    free_probability: f64,
+}
+
+impl AccessControl {
+    // The task from the book: 10 servers, each freed with probability 0.06.
+    pub fn new() -> Self {
+        AccessControl {
+            server_count: 10,
+            free_probability: 0.06,
+        }
+    }
+
+    pub fn states(&self) -> Vec<State> {
+        (0..=self.server_count)
+            .flat_map(|free_servers| {
+                PRIORITIES
+                    .iter()
+                    .map(move |priority| State::new(free_servers, *priority))
+            })
+            .collect()
+    }
+
+    // Returns the number of free servers after the action, before the busy servers are freed, and
+    // the reward.
+    fn take_action(&self, state: &State, action: &Action) -> (u32, f64) {
+        match action {
+            Action::Accept => {
+                assert!(state.free_servers > 0);
+                (state.free_servers - 1, state.priority as f64)
+            }
+            Action::Reject => (state.free_servers, 0.0),
+        }
+    }
+
+    // The same task as an explicit MDP.
+    pub fn explicit_env(&self) -> Env<State, Action> {
+        let mut states = HashMap::new();
+        for state in self.states() {
+            let mut actions = HashMap::new();
+            for action in Environment::actions(self, &state) {
+                let (free_servers, reward) = self.take_action(&state, &action);
+                let busy_servers = self.server_count - free_servers;
+
+                // Any number of busy servers can be freed (binomial distribution), and the next
+                // customer has any priority with equal probability.
+                let mut dest_states = HashMap::new();
+                for freed in 0..=busy_servers {
+                    let freed_probability =
+                        binomial_probability(busy_servers, freed, self.free_probability);
+                    for priority in PRIORITIES.iter() {
+                        dest_states.insert(
+                            State::new(free_servers + freed, *priority),
+                            ActionDestination {
+                                probability: freed_probability / PRIORITIES.len() as f64,
+                                reward,
+                            },
+                        );
+                    }
+                }
+                actions.insert(action, ActionResult { dest_states });
+            }
+            states.insert(state, StateActions { actions });
+        }
+        Env { states }
+    }
+
+    // One-hot encoding of the state, for the linear state value methods.
+    pub fn state_features(&self, state: &State) -> Vec<f64> {
+        let priority_index = PRIORITIES
+            .iter()
+            .position(|p| *p == state.priority)
+            .unwrap();
+        let mut features = vec![0.0; (self.server_count as usize + 1) * PRIORITIES.len()];
+        features[state.free_servers as usize * PRIORITIES.len() + priority_index] = 1.0;
+        features
+    }
+
+    // One-hot encoding of the state-action pair, so that the linear methods are equivalent to the
+    // tabular ones.
+    pub fn features(&self, state: &State, action: &Action) -> Vec<f64> {
+        let priority_index = PRIORITIES
+            .iter()
+            .position(|p| *p == state.priority)
+            .unwrap();
+        let action_index = match action {
+            Action::Accept => 0,
+            Action::Reject => 1,
+        };
+        let mut features = vec![0.0; (self.server_count as usize + 1) * PRIORITIES.len() * 2];
+        features[(state.free_servers as usize * PRIORITIES.len() + priority_index) * 2
+            + action_index] = 1.0;
+        features
+    }
+}
+
+// Probability of k successes in n trials with success probability p.
+fn binomial_probability(n: u32, k: u32, p: f64) -> f64 {
+    let combinations = (0..k).fold(1.0, |c, i| c * (n - i) as f64 / (i + 1) as f64);
+    combinations * p.powi(k as i32) * (1.0 - p).powi((n - k) as i32)
+}
+
+impl Environment for AccessControl {
+    type State = State;
+    type Action = Action;
+
+    // Starts with all servers free.
+    fn reset<R: Rng>(&self, rng: &mut R) -> State {
+        State::new(self.server_count, *PRIORITIES.choose(rng).unwrap())
+    }
+
+    // Customers can only be rejected if there are no free servers.
+    fn actions(&self, state: &State) -> Vec<Action> {
+        if state.free_servers > 0 {
+            vec![Action::Accept, Action::Reject]
+        } else {
+            vec![Action::Reject]
+        }
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, rng: &mut R) -> (Option<State>, f64) {
+        let (free_servers, reward) = self.take_action(state, action);
+        let busy_servers = self.server_count - free_servers;
+        let freed = (0..busy_servers)
+            .filter(|_| rng.gen::<f64>() < self.free_probability)
+            .count() as u32;
+        let priority = *PRIORITIES.choose(rng).unwrap();
+        (Some(State::new(free_servers + freed, priority)), reward)
+    }
+}
+
+// Prints whether the policy accepts the customer for each priority and number of free servers.
+pub fn print_access_control_policy(env: &AccessControl, policy: &Policy<State, Action>) {
+    let mut table = Table::new();
+    let mut header = vec![Cell::new("Priority")];
+    header.extend((1..=env.server_count).map(|free_servers| Cell::new(&free_servers.to_string())));
+    table.add_row(Row::new(header));
+
+    for priority in PRIORITIES.iter().rev() {
+        let mut row = vec![Cell::new(&priority.to_string())];
+        row.extend((1..=env.server_count).map(|free_servers| {
+            let actions = &policy.states[&State::new(free_servers, *priority)].actions;
+            let accept = actions.get(&Action::Accept).is_some_and(|p| *p > 0.0);
+            Cell::new(if accept { "A" } else { "R" })
+        }));
+        table.add_row(Row::new(row));
+    }
+
+    table.printstd();
+}
+
+// Returns the greedy policy with respect to the action values learned by differential SARSA.
+fn greedy_policy<ActionValue>(
+    env: &AccessControl,
+    action_value: ActionValue,
+) -> Policy<State, Action>
+where
+    ActionValue: Fn(&State, &Action) -> f64,
+{
+    Policy {
+        states: env
+            .states()
+            .into_iter()
+            .map(|state| {
+                let best_action = Environment::actions(env, &state)
+                    .into_iter()
+                    .max_by(|a1, a2| {
+                        action_value(&state, a1)
+                            .partial_cmp(&action_value(&state, a2))
+                            .unwrap()
+                    })
+                    .unwrap();
+                let actions = std::iter::once((best_action, 1.0)).collect();
+                (state, PolicyState { actions })
+            })
+            .collect(),
+    }
+}
+
+pub fn run() {
+    let env = AccessControl::new();
+
+    let (policy, _, average_reward, report) =
+        relative_value_iteration(&env.explicit_env(), State::new(0, 1), 1e-9, 100000);
+    println!(
+        "Relative value iteration done in {} sweeps ({:?}), average reward {:.3}",
+        report.sweeps, report.elapsed, average_reward
+    );
+    print_access_control_policy(&env, &policy);
+
+    // Figure 10.5.
+    let mut rng = StdRng::seed_from_u64(0);
+    let (action_values, average_reward) =
+        td::find_action_values_differential_sarsa(&env, 0.01, 0.1, 0.01, 2000000, &mut rng);
+    println!(
+        "Differential SARSA done, average reward {:.3}",
+        average_reward
+    );
+    let policy = greedy_policy(&env, |s, a| {
+        *action_values
+            .get(s)
+            .map_or(&0.0, |av| av.get(a).unwrap_or(&0.0))
+    });
+    print_access_control_policy(&env, &policy);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra::DVector;
+
+    // Checks the properties of the optimal policy from Figure 10.5: high priority customers are
+    // always accepted, and low priority ones only when there are many free servers. The states
+    // with more than `max_free_servers` free servers are not checked, since they are rarely
+    // visited and the learned values for them are unreliable.
+    fn check_policy(
+        env: &AccessControl,
+        max_free_servers: u32,
+        action_value: impl Fn(&State, &Action) -> f64,
+    ) {
+        let accepts = |free_servers, priority| {
+            let state = State::new(free_servers, priority);
+            action_value(&state, &Action::Accept) > action_value(&state, &Action::Reject)
+        };
+        for free_servers in 1..=max_free_servers.min(env.server_count) {
+            assert!(accepts(free_servers, 8), "{}", free_servers);
+            assert!(accepts(free_servers, 4), "{}", free_servers);
+        }
+        assert!(!accepts(1, 1));
+        assert!(!accepts(2, 1));
+    }
+
+    // The same MDP, but customers are always accepted if there are free servers.
+    fn accept_only_env(explicit_env: &Env<State, Action>) -> Env<State, Action> {
+        let mut accept_env = explicit_env.clone();
+        for state_actions in accept_env.states.values_mut() {
+            if state_actions.actions.len() > 1 {
+                state_actions.actions.remove(&Action::Reject);
+            }
+        }
+        accept_env
+    }
+
+    #[test]
+    fn relative_value_iteration_test() {
+        let env = AccessControl::new();
+        let explicit_env = env.explicit_env();
+
+        let (policy, state_values, average_reward, report) =
+            relative_value_iteration(&explicit_env, State::new(0, 1), 1e-9, 100000);
+        println!("Average reward: {}", average_reward);
+        assert!(report.policy_stable);
+
+        check_policy(&env, env.server_count, |s, a| {
+            let action_result = &explicit_env.states[s].actions[a];
+            action_result
+                .dest_states
+                .iter()
+                .map(|(dest, d)| d.probability * (d.reward + state_values[dest]))
+                .sum()
+        });
+
+        // Rejecting the lowest priority customers must be better than always accepting.
+        let (_, _, accept_average_reward, _) = relative_value_iteration(
+            &accept_only_env(&explicit_env),
+            State::new(0, 1),
+            1e-9,
+            100000,
+        );
+        println!("Always accept average reward: {}", accept_average_reward);
+        assert!(average_reward > accept_average_reward + 0.1);
+        assert!(policy.states[&State::new(10, 8)].actions[&Action::Accept] > 0.0);
+    }
+
+    #[test]
+    fn differential_td_test() {
+        let env = AccessControl::new();
+
+        // The average reward of the policy that always accepts.
+        let (_, _, expected_average_reward, _) = relative_value_iteration(
+            &accept_only_env(&env.explicit_env()),
+            State::new(0, 1),
+            1e-9,
+            100000,
+        );
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let policy = |s: &State, _rng: &mut StdRng| {
+            if s.free_servers > 0 {
+                Action::Accept
+            } else {
+                Action::Reject
+            }
+        };
+        let (_, average_reward) =
+            td::evaluate_policy_differential_td(&env, &policy, 0.01, 0.01, 100000, &mut rng);
+        println!(
+            "Tabular: {}, expected {}",
+            average_reward, expected_average_reward
+        );
+        assert!((average_reward - expected_average_reward).abs() < 0.1 * expected_average_reward);
+
+        let features = |s: &State| env.state_features(s);
+        let (_, average_reward) = approximate::evaluate_policy_differential_semi_gradient_td(
+            &env, &features, &policy, 0.01, 0.01, 100000, &mut rng,
+        );
+        println!(
+            "Linear: {}, expected {}",
+            average_reward, expected_average_reward
+        );
+        assert!((average_reward - expected_average_reward).abs() < 0.1 * expected_average_reward);
+    }
+
+    #[test]
+    fn differential_sarsa_test() {
+        let env = AccessControl::new();
+        let (_, _, optimal_average_reward, _) =
+            relative_value_iteration(&env.explicit_env(), State::new(0, 1), 1e-9, 100000);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let (action_values, average_reward) =
+            td::find_action_values_differential_sarsa(&env, 0.01, 0.1, 0.01, 200000, &mut rng);
+        println!(
+            "Tabular: {}, optimal {}",
+            average_reward, optimal_average_reward
+        );
+        check_policy(&env, 8, |s, a| {
+            *action_values
+                .get(s)
+                .map_or(&0.0, |av| av.get(a).unwrap_or(&0.0))
+        });
+        // ε-greedy policy can't be as good as the optimal one.
+        assert!(average_reward < optimal_average_reward);
+        assert!(average_reward > 0.8 * optimal_average_reward);
+
+        let features = |s: &State, a: &Action| env.features(s, a);
+        let (w, average_reward) = approximate::find_action_values_differential_semi_gradient_sarsa(
+            &env, &features, 0.01, 0.1, 0.01, 200000, &mut rng,
+        );
+        println!(
+            "Linear: {}, optimal {}",
+            average_reward, optimal_average_reward
+        );
+        check_policy(&env, 8, |s, a| w.dot(&DVector::from_vec(features(s, a))));
+        assert!(average_reward < optimal_average_reward);
+        assert!(average_reward > 0.8 * optimal_average_reward);
+    }
+}
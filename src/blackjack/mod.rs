@@ -1,7 +1,9 @@
 use rand::prelude::*;
+use rand::rngs::StdRng;
 
 use prettytable::{Cell, Row, Table};
 
+use crate::solver::environment::*;
 use crate::solver::*;
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -30,6 +32,9 @@ pub struct State {
     player: Hand,
 }
 
+// Blackjack game against a dealer with an infinite deck.
+pub struct Blackjack;
+
 impl Card {
     pub fn is_ace(&self) -> bool {
         match self {
@@ -70,13 +75,13 @@ impl Hand {
         hand
     }
 
-    fn add_random_card(&self) -> Hand {
-        self.add_card(random_card())
+    fn add_random_card<R: Rng>(&self, rng: &mut R) -> Hand {
+        self.add_card(random_card(rng))
     }
 }
 
-fn random_card() -> Card {
-    let r = rand::random::<u32>() % 13 + 1;
+fn random_card<R: Rng>(rng: &mut R) -> Card {
+    let r = rng.gen_range(1..=13);
     match r {
         1 => Card::Ace,
         2..=10 => Card::Value(r),
@@ -86,13 +91,32 @@ fn random_card() -> Card {
 }
 
 // Creates an initial random state.
-pub fn start_state() -> State {
+pub fn start_state<R: Rng>(rng: &mut R) -> State {
     State {
-        dealer: random_card(),
-        player: Hand::default().add_random_card().add_random_card(),
+        dealer: random_card(rng),
+        player: Hand::default().add_random_card(rng).add_random_card(rng),
     }
 }
 
+// Creates a random state and action for Monte Carlo control with exploring starts. Player sums
+// below 12 are not interesting (the player always hits), so the sum is uniform in 12..=21, with or
+// without a usable ace; the dealer card and the action are random.
+pub fn exploring_start<R: Rng>(rng: &mut R) -> (State, Action) {
+    let state = State {
+        dealer: random_card(rng),
+        player: Hand {
+            value: rng.gen_range(12..=21),
+            usable_ace: rng.gen(),
+        },
+    };
+    let action = if rng.gen() {
+        Action::Hit
+    } else {
+        Action::Stick
+    };
+    (state, action)
+}
+
 // Creates the next state from the current state and action.
 // Cards are dealt in random as required per the selected action.
 // Returns:
@@ -100,14 +124,14 @@ pub fn start_state() -> State {
 //   cards until they reach 17.
 // * (None, -1) if the action is Hit and the player has gone bust after taking one more card.
 // * (Some(State), 0) if the action is Hit and the player didn't go over 21 yet.
-pub fn next_state(state: &State, action: &Action) -> (Option<State>, f64) {
+pub fn next_state<R: Rng>(state: &State, action: &Action, rng: &mut R) -> (Option<State>, f64) {
     if *action == Action::Stick {
         // Dealer takes cards until they reach 17.
         // Start with 2 cards: hidden (the one that should have been dealt at the beginning,
         // but we only deal it now) and the open card.
         let mut dealer = Hand::default().add_card(state.dealer);
         while dealer.value < 17 {
-            dealer = dealer.add_random_card();
+            dealer = dealer.add_random_card(rng);
         }
 
         if dealer.value > 21 {
@@ -125,7 +149,7 @@ pub fn next_state(state: &State, action: &Action) -> (Option<State>, f64) {
     }
 
     // Action is "Hit".
-    let player = state.player.add_random_card();
+    let player = state.player.add_random_card(rng);
     if player.value > 21 {
         // Player has gone bust.
         return (Option::None, -1.0);
@@ -140,16 +164,25 @@ pub fn next_state(state: &State, action: &Action) -> (Option<State>, f64) {
     )
 }
 
-pub fn random_action(state: &State) -> Action {
-    if rand::random::<f64>() < 0.5 {
-        Action::Hit
-    } else {
-        Action::Stick
+impl Environment for Blackjack {
+    type State = State;
+    type Action = Action;
+
+    fn reset<R: Rng>(&self, rng: &mut R) -> State {
+        start_state(rng)
+    }
+
+    fn actions(&self, _state: &State) -> Vec<Action> {
+        vec![Action::Hit, Action::Stick]
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, rng: &mut R) -> (Option<State>, f64) {
+        next_state(state, action, rng)
     }
 }
 
 // A policy that only sticks on 20 or higher.
-pub fn stick_at_20_policy(state: &State) -> Action {
+pub fn stick_at_20_policy<R: Rng>(state: &State, _rng: &mut R) -> Action {
     if state.player.value < 20 {
         return Action::Hit;
     } else {
@@ -213,8 +246,11 @@ pub fn print_policy(policy: &Policy<State, Action>) {
 }
 
 pub fn run() {
+    let mut rng = StdRng::seed_from_u64(0);
+
     // let state_values =
-    //     monte_carlo::evaluate_policy(start_state, stick_at_20_policy, next_state, 1.0, 10000000);
+    //     monte_carlo::evaluate_policy(&Blackjack, &stick_at_20_policy, &monte_carlo::Visits::First,
+    //         &monte_carlo::UpdateRule::SampleAverage, 1.0, 10000000, &mut rng);
     //
     // let mut states_and_values: Vec<(State, f64)> = state_values.into_iter().collect();
     // states_and_values.sort_by(|(k1, v1), (k2, v2)| v2.partial_cmp(v1).unwrap());
@@ -223,13 +259,15 @@ pub fn run() {
     //     println!("{:?}: {}", k, v);
     // }
 
-    let policy = monte_carlo::find_policy(
-        &start_state,
-        &random_action,
-        &next_state,
+    // Figure 5.2.
+    let policy = monte_carlo::find_policy_exploring_starts(
+        &Blackjack,
+        &exploring_start,
+        &monte_carlo::Visits::First,
+        &monte_carlo::UpdateRule::SampleAverage,
         1.0,
-        0.1,
         10000000,
+        &mut rng,
     );
     print_policy(&policy);
     let policy_functor = monte_carlo::policy_from_explicit(policy);
@@ -239,10 +277,9 @@ pub fn run() {
     let mut total_naive_returns = 0.0;
     let runs = 100000;
     for _ in 0..runs {
-        total_optimal_returns +=
-            monte_carlo::run_simulation(&start_state, &policy_functor, &next_state);
+        total_optimal_returns += monte_carlo::run_simulation(&Blackjack, &policy_functor, &mut rng);
         total_naive_returns +=
-            monte_carlo::run_simulation(&start_state, &stick_at_20_policy, &next_state);
+            monte_carlo::run_simulation(&Blackjack, &stick_at_20_policy, &mut rng);
     }
     println!(
         "Average naive returns: {}",
@@ -277,4 +314,206 @@ mod tests {
 
         assert_eq!(Hand::from_cards(&vec![C::Face, C::Face, C::Ace]).value, 21);
     }
+
+    // The single state from Example 5.4: dealer shows a deuce, player has 13 with a usable ace.
+    fn example_5_4_state() -> State {
+        State {
+            dealer: C::Value(2),
+            player: Hand {
+                value: 13,
+                usable_ace: true,
+            },
+        }
+    }
+
+    // Value of the example state under stick_at_20_policy, from the book.
+    const EXAMPLE_5_4_VALUE: f64 = -0.27726;
+
+    // Estimates the value of the example state under stick_at_20_policy from episodes following
+    // the random policy.
+    fn example_5_4_estimate(
+        sampling: &monte_carlo::ImportanceSampling,
+        episodes: u64,
+        rng: &mut StdRng,
+    ) -> f64 {
+        let env = ClosureEnvironment::new(
+            |_rng: &mut dyn RngCore| example_5_4_state(),
+            |state: &State| Blackjack.actions(state),
+            |state: &State, action: &Action, mut rng: &mut dyn RngCore| {
+                next_state(state, action, &mut rng)
+            },
+        );
+        let state_values = monte_carlo::evaluate_policy_off_policy(
+            &env,
+            &|state: &State, rng: &mut StdRng| env.random_action(state, rng),
+            &|_state: &State, _action: &Action| 0.5,
+            &|state: &State, action: &Action| {
+                // stick_at_20_policy is deterministic.
+                let hit = state.player.value < 20;
+                if hit == (*action == Action::Hit) {
+                    1.0
+                } else {
+                    0.0
+                }
+            },
+            &monte_carlo::Visits::First,
+            sampling,
+            1.0,
+            episodes,
+            rng,
+        );
+        state_values[&example_5_4_state()]
+    }
+
+    #[test]
+    fn off_policy_example_5_4_test() {
+        use monte_carlo::ImportanceSampling as IS;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for sampling in [IS::Ordinary, IS::Weighted].iter() {
+            let value = example_5_4_estimate(sampling, 100000, &mut rng);
+            assert!((value - EXAMPLE_5_4_VALUE).abs() < 0.02, "{}", value);
+        }
+    }
+
+    #[test]
+    fn off_policy_example_5_4_mse_test() {
+        use monte_carlo::ImportanceSampling as IS;
+
+        // Mean squared error of the estimate after the given number of episodes, over 100 runs.
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut mse = |sampling: &IS, episodes: u64| {
+            let runs = 100;
+            (0..runs)
+                .map(|_| {
+                    (example_5_4_estimate(sampling, episodes, &mut rng) - EXAMPLE_5_4_VALUE).powi(2)
+                })
+                .sum::<f64>()
+                / runs as f64
+        };
+
+        let ordinary: Vec<f64> = [10, 100, 1000]
+            .iter()
+            .map(|e| mse(&IS::Ordinary, *e))
+            .collect();
+        let weighted: Vec<f64> = [10, 100, 1000]
+            .iter()
+            .map(|e| mse(&IS::Weighted, *e))
+            .collect();
+        println!("Ordinary: {:.4?}, weighted: {:.4?}", ordinary, weighted);
+
+        // Both errors go down with more episodes, but weighted importance sampling has lower
+        // error early on.
+        for errors in [&ordinary, &weighted].iter() {
+            assert!(errors[0] > errors[1] && errors[1] > errors[2]);
+        }
+        assert!(weighted[0] < ordinary[0]);
+        assert!(weighted[1] < ordinary[1]);
+    }
+
+    #[test]
+    fn off_policy_find_policy_test() {
+        let policy = monte_carlo::find_policy_off_policy(
+            &Blackjack,
+            &|state: &State, rng: &mut StdRng| Blackjack.random_action(state, rng),
+            &|_state: &State, _action: &Action| 0.5,
+            &monte_carlo::Visits::First,
+            &monte_carlo::ImportanceSampling::Weighted,
+            1.0,
+            200000,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        // Always stick on 20 and 21, and always hit on 8-11 (lower sums are too rare to be learned).
+        for (state, policy_state) in policy.states.iter() {
+            let expected_action = match state.player.value {
+                20 | 21 => Action::Stick,
+                8..=11 => Action::Hit,
+                _ => continue,
+            };
+            assert_eq!(
+                policy_state.actions.get(&expected_action),
+                Some(&1.0),
+                "{:?}",
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn exploring_starts_figure_5_2_test() {
+        let policy = monte_carlo::find_policy_exploring_starts(
+            &Blackjack,
+            &exploring_start,
+            &monte_carlo::Visits::First,
+            &monte_carlo::UpdateRule::SampleAverage,
+            1.0,
+            500000,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        // The parts of Figure 5.2 that don't depend on the dealer card: with usable ace, hit on
+        // 12-16; always stick on 20 and 21.
+        for dealer in (2..=10)
+            .map(C::Value)
+            .chain([C::Ace, C::Face].iter().copied())
+        {
+            for usable_ace in [false, true].iter() {
+                for value in 12..=21 {
+                    let expected_action = match value {
+                        12..=16 if *usable_ace => Action::Hit,
+                        20 | 21 => Action::Stick,
+                        _ => continue,
+                    };
+                    let state = State {
+                        dealer,
+                        player: Hand {
+                            value,
+                            usable_ace: *usable_ace,
+                        },
+                    };
+                    assert_eq!(
+                        policy.states[&state].actions.get(&expected_action),
+                        Some(&1.0),
+                        "{:?}",
+                        state
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn epsilon_soft_policy_test() {
+        let exploration_fraction = 0.1;
+        let policy = monte_carlo::find_policy_epsilon_soft(
+            &Blackjack,
+            &monte_carlo::Visits::First,
+            &monte_carlo::UpdateRule::SampleAverage,
+            1.0,
+            exploration_fraction,
+            100000,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        // The policy itself is ε-soft: both actions are possible, the best one with probability
+        // 1 - ε + ε/2.
+        for (state, policy_state) in policy.states.iter() {
+            let mut probabilities: Vec<f64> = policy_state.actions.values().copied().collect();
+            probabilities.sort_by(|p1, p2| p1.partial_cmp(p2).unwrap());
+            assert_eq!(probabilities.len(), 2, "{:?}", state);
+            assert!((probabilities[0] - 0.05).abs() < 1e-9, "{:?}", state);
+            assert!((probabilities[1] - 0.95).abs() < 1e-9, "{:?}", state);
+        }
+
+        // Sticking on 21 is always the best.
+        let state = State {
+            dealer: C::Value(10),
+            player: Hand {
+                value: 21,
+                usable_ace: false,
+            },
+        };
+        assert!((policy.states[&state].actions[&Action::Stick] - 0.95).abs() < 1e-9);
+    }
 }
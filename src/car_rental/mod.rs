@@ -1,9 +1,12 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use factorial::Factorial;
 use prettytable::{Cell, Row, Table};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
-use crate::solver::{explicit::*, *};
+use crate::solver::{dense::*, explicit::*, *};
 
 const MAX_MOVES: i32 = 5;
 const RENT_REWARD: f64 = 10.0;
@@ -13,7 +16,7 @@ const RENTALS_LAMBDA2: f64 = 4.0;
 const RETURNS_LAMBDA1: f64 = 3.0;
 const RETURNS_LAMBDA2: f64 = 2.0;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct State {
     // Number of cars on location 1 and 2.
     l1: i32,
@@ -35,12 +38,10 @@ fn poisson_tail(lambda: f64, n: i32) -> f64 {
     1.0 - (0..n).map(|i| poisson_prob(lambda, i)).sum::<f64>()
 }
 
-fn new_action_result(
-    max_cars: i32,
-    l1_day: i32,
-    l2_day: i32,
-    transfer: i32,
-) -> ActionResult<State> {
+// Returns the results of a day that starts with l1_day and l2_day cars on the locations
+// (i.e. after the overnight transfer). The rewards don't include the transfer price, since the
+// result is shared by all transfers that lead to the same (l1_day, l2_day).
+fn new_action_result(max_cars: i32, l1_day: i32, l2_day: i32) -> ActionResult<State> {
     // Collect all paths leading to different destination states with their reward
     // and the probability of activating this path from 'state_id' with 'action_id'.
     let mut dest_states_paths: HashMap<State, Vec<(f64, f64)>> = HashMap::new();
@@ -94,8 +95,7 @@ fn new_action_result(
 
                     let probability = l1_out_prob * l1_in_prob * l2_out_prob * l2_in_prob;
 
-                    let reward = ((l1_out + l2_out) as f64) * RENT_REWARD
-                        - (transfer.abs() as f64) * TRANSFER_PRICE;
+                    let reward = ((l1_out + l2_out) as f64) * RENT_REWARD;
 
                     let dest_state_id = State::new(l1_end, l2_end);
 
@@ -134,10 +134,9 @@ fn new_action_result(
 
     assert!(
         (total_probability - 1.0).abs() < 0.1,
-        "{}_{}|{}: {}",
+        "{}_{}: {}",
         l1_day,
         l2_day,
-        transfer,
         total_probability
     );
 
@@ -146,6 +145,18 @@ fn new_action_result(
     }
 }
 
+// Returns the range of possible transfers from the state
+// (negative for moving cars from the second location to the first one).
+fn transfers(max_cars: i32, l1_start: i32, l2_start: i32) -> std::ops::RangeInclusive<i32> {
+    let min_transfers = -(l2_start.min(MAX_MOVES).min(max_cars - l1_start));
+    let max_transfers = l1_start.min(MAX_MOVES).min(max_cars - l2_start);
+    min_transfers..=max_transfers
+}
+
+fn transfer_reward(transfer: i32) -> f64 {
+    -(transfer.abs() as f64) * TRANSFER_PRICE
+}
+
 pub fn new_car_rental_env(max_cars: i32) -> Env<State, i32> {
     let mut states = HashMap::new();
 
@@ -162,18 +173,21 @@ pub fn new_car_rental_env(max_cars: i32) -> Env<State, i32> {
 
             // Number of cars moved from first to second location
             // (negative for the other way around).
-            let min_transfers = -(l2_start.min(MAX_MOVES).min(max_cars - l1_start));
-            let max_transfers = l1_start.min(MAX_MOVES).min(max_cars - l2_start);
-            for transfer in min_transfers..(max_transfers + 1) {
+            for transfer in transfers(max_cars, l1_start, l2_start) {
                 let l1_day = l1_start - transfer;
                 let l2_day = l2_start + transfer;
                 let action_result = actions_cache
                     .entry((l1_day, l2_day))
-                    .or_insert_with(|| new_action_result(max_cars, l1_day, l2_day, transfer));
-
-                state_actions
-                    .actions
-                    .insert(transfer, action_result.clone());
+                    .or_insert_with(|| new_action_result(max_cars, l1_day, l2_day));
+
+                // Env stores every action result by value, with the transfer price folded into
+                // the rewards, so each (state, transfer) needs its own copy. The shared form is
+                // new_car_rental_dense_env().
+                let mut action_result = action_result.clone();
+                for dest in action_result.dest_states.values_mut() {
+                    dest.reward += transfer_reward(transfer);
+                }
+                state_actions.actions.insert(transfer, action_result);
             }
 
             states.insert(state, state_actions);
@@ -183,17 +197,71 @@ pub fn new_car_rental_env(max_cars: i32) -> Env<State, i32> {
     Env { states: states }
 }
 
-pub fn new_car_rental_noop_policy(env: &Env<State, i32>) -> Policy<State, i32> {
+// Same as new_car_rental_env(), but in the compiled form, where every (l1_day, l2_day) result is
+// stored once.
+pub fn new_car_rental_dense_env(max_cars: i32) -> DenseEnv<State, i32> {
+    let mut builder = DenseEnvBuilder::new();
+
+    let mut results_cache = HashMap::new();
+
+    for l1_start in 0..(max_cars + 1) {
+        for l2_start in 0..(max_cars + 1) {
+            let state = State::new(l1_start, l2_start);
+            for transfer in transfers(max_cars, l1_start, l2_start) {
+                let l1_day = l1_start - transfer;
+                let l2_day = l2_start + transfer;
+                let result = *results_cache.entry((l1_day, l2_day)).or_insert_with(|| {
+                    let action_result = new_action_result(max_cars, l1_day, l2_day);
+                    builder.add_result(
+                        action_result
+                            .dest_states
+                            .into_iter()
+                            .map(|(s, dest)| (s, dest.probability, dest.reward)),
+                    )
+                });
+
+                builder.add_action(state, transfer, result, transfer_reward(transfer));
+            }
+        }
+    }
+
+    builder.build()
+}
+
+// Returns the average time per sweep, or zero if there were no sweeps.
+fn sweep_time(report: &ConvergenceReport) -> Duration {
+    if report.sweeps == 0 {
+        Duration::default()
+    } else {
+        report.elapsed / report.sweeps as u32
+    }
+}
+
+// Runs value iteration on the keyed and the dense forms of the environment and returns the
+// average time per sweep for each of them.
+fn compare_sweep_times(max_cars: i32, max_sweeps: usize) -> (Duration, Duration) {
+    let env = new_car_rental_env(max_cars);
+    let (_, _, report) = value_iteration(&env, 0.9, 0.0001, max_sweeps);
+
+    let dense_env = new_car_rental_dense_env(max_cars);
+    let (_, _, dense_report) = dense_env.value_iteration(0.9, 0.0001, max_sweeps);
+
+    (sweep_time(&report), sweep_time(&dense_report))
+}
+
+pub fn new_car_rental_noop_policy(max_cars: i32) -> Policy<State, i32> {
     let mut policy_states = HashMap::new();
-    for (state, _state_actions) in &env.states {
-        let mut policy_state_actions = HashMap::new();
-        policy_state_actions.insert(0, 1.0);
-        policy_states.insert(
-            *state,
-            PolicyState {
-                actions: policy_state_actions,
-            },
-        );
+    for l1 in 0..(max_cars + 1) {
+        for l2 in 0..(max_cars + 1) {
+            let mut policy_state_actions = HashMap::new();
+            policy_state_actions.insert(0, 1.0);
+            policy_states.insert(
+                State::new(l1, l2),
+                PolicyState {
+                    actions: policy_state_actions,
+                },
+            );
+        }
     }
 
     Policy {
@@ -232,29 +300,142 @@ pub fn print_car_rental_policy(policy: &Policy<State, i32>, max_cars: i32) {
 pub fn run() {
     // Create environment.
     println!("Creating environment");
-    let env = new_car_rental_env(20);
+    let env = new_car_rental_dense_env(20);
 
     // Create policy.
     println!("Creating intial policy");
-    let mut policy = new_car_rental_noop_policy(&env);
-    let mut state_values = HashMap::new();
+    let policy = env.policy_from_explicit(&new_car_rental_noop_policy(20));
+
+    println!("Running policy iteration");
+    let (policy, _state_values, report) = env.policy_iteration(policy, 0.9, 0.0001, 100000);
+    println!(
+        "done in {} sweeps ({:?}), policy stable: {}, last delta {}",
+        report.sweeps,
+        report.elapsed,
+        report.policy_stable,
+        report.deltas.last().unwrap_or(&0.0)
+    );
+    print_car_rental_policy(&env.policy_to_explicit(&policy), 20);
+
+    // Compare the sweep times of the keyed and the dense environments.
+    let (env_sweep_time, dense_sweep_time) = compare_sweep_times(20, 100);
+    println!(
+        "Value iteration sweep: {:?} with Env, {:?} with DenseEnv ({:.1}x faster)",
+        env_sweep_time,
+        dense_sweep_time,
+        env_sweep_time.as_secs_f64() / dense_sweep_time.as_secs_f64()
+    );
+
+    // Compare the number of sweeps needed by the in-place variants.
+    let env = new_car_rental_env(20);
+    let mut rng = StdRng::seed_from_u64(0);
+    for (name, order) in [
+        ("natural", StateOrder::Natural),
+        ("reverse", StateOrder::Reverse),
+        ("random", StateOrder::Random),
+    ]
+    .iter()
+    {
+        let (_, _, report) = policy_iteration_in_place(
+            &env,
+            new_car_rental_noop_policy(20),
+            order,
+            0.9,
+            0.0001,
+            100000,
+            &mut rng,
+        );
+        println!(
+            "In-place policy iteration ({} order) done in {} sweeps ({:?})",
+            name, report.sweeps, report.elapsed
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dense_env_matches_env_test() {
+        let max_cars = 5;
+        let env = new_car_rental_env(max_cars);
+        let dense_env = new_car_rental_dense_env(max_cars);
+
+        let policy = new_car_rental_noop_policy(max_cars);
+        let expected = linear::evaluate_policy_exact(&env, &policy, 0.9);
+        let actual = dense_env.state_values_to_map(
+            &dense_env.evaluate_policy_exact(&dense_env.policy_from_explicit(&policy), 0.9),
+        );
 
-    for i in 0..5 {
-        println!("Evaluating policy");
-        for i in 0..10000 {
+        assert_eq!(actual.len(), expected.len());
+        for (state, value) in expected.iter() {
+            assert!((actual[state] - value).abs() < 1e-6, "{:?}", state);
+        }
+    }
+
+    #[test]
+    fn dense_env_shares_results_test() {
+        let max_cars = 5;
+        let env = new_car_rental_env(max_cars);
+        let dense_env = new_car_rental_dense_env(max_cars);
+
+        // The keyed env copies the result for every (state, transfer), while the dense one
+        // stores it once for every (l1_day, l2_day), so a sweep reads far fewer destinations.
+        let env_destination_count: usize = env
+            .states
+            .values()
+            .flat_map(|state_actions| state_actions.actions.values())
+            .map(|action_result| action_result.dest_states.len())
+            .sum();
+        assert_eq!(
+            dense_env.result_count(),
+            ((max_cars + 1) * (max_cars + 1)) as usize
+        );
+        println!(
+            "Env: {} destinations, DenseEnv: {}",
+            env_destination_count,
+            dense_env.destination_count()
+        );
+        assert!(dense_env.destination_count() * 3 < env_destination_count);
+    }
+
+    #[test]
+    fn in_place_policy_evaluation_test() {
+        let max_cars = 5;
+        let env = new_car_rental_env(max_cars);
+        let policy = new_car_rental_noop_policy(max_cars);
+        let expected = linear::evaluate_policy_exact(&env, &policy, 0.9);
+
+        let mut sync_values = HashMap::new();
+        let mut sync_sweeps = 0;
+        loop {
             let (new_state_values, delta) =
-                evaluate_policy_iteration(&env, &policy, &state_values, 0.9);
-            state_values = new_state_values;
-            if i % 10 == 0 {
-                println!("{}: delta {}", i, delta);
-            }
-            if delta < 0.0001 {
+                evaluate_policy_iteration(&env, &policy, &sync_values, 0.9);
+            sync_values = new_state_values;
+            sync_sweeps += 1;
+            if delta < 1e-6 {
                 break;
             }
         }
-        println!("done!");
 
-        policy = make_greedy_policy(&env, &state_values, 0.9);
-        print_car_rental_policy(&policy, 20);
+        let mut rng = StdRng::seed_from_u64(0);
+        let states = order_states(&env, &StateOrder::Natural, &mut rng);
+        let mut in_place_values = HashMap::new();
+        let mut in_place_sweeps = 0;
+        while evaluate_policy_in_place(&env, &policy, &mut in_place_values, &states, 0.9) >= 1e-6 {
+            in_place_sweeps += 1;
+        }
+        in_place_sweeps += 1;
+
+        println!(
+            "Synchronous: {} sweeps, in place: {}",
+            sync_sweeps, in_place_sweeps
+        );
+        assert!(in_place_sweeps < sync_sweeps);
+        for (state, value) in expected.iter() {
+            assert!((sync_values[state] - value).abs() < 1e-4, "{:?}", state);
+            assert!((in_place_values[state] - value).abs() < 1e-4, "{:?}", state);
+        }
     }
 }
@@ -7,8 +7,10 @@ use plotlib::{
     view::ContinuousView,
 };
 use prettytable::{Cell, Row, Table};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
-use crate::solver::{explicit::*, *};
+use crate::solver::{explicit::*, lp::*, *};
 
 const LIMIT: i32 = 100;
 
@@ -115,22 +117,64 @@ pub fn run() {
     // Create environment.
     println!("Creating environment");
     let env = new_coin_env(0.49);
+    let mut rng = StdRng::seed_from_u64(0);
 
-    let mut state_values = HashMap::new();
-    for i in 0..100000 {
-        let (new_state_values, delta) = iterate_state_value(&env, &state_values, 1.0);
-        state_values = new_state_values;
-        println!("Delta: {}", delta);
-        if delta < 0.0000001 {
-            break;
-        }
+    let (optimal_policy, state_values, report) = value_iteration(&env, 1.0, 0.0000001, 100000);
+    println!(
+        "Value iteration done in {} sweeps ({:?}), policy stable: {}",
+        report.sweeps, report.elapsed, report.policy_stable
+    );
+
+    let (_, _, sweeping_report) = prioritized_sweeping(&env, 1.0, 0.0000001, 10000000);
+    println!(
+        "Prioritized sweeping done in {} backups ({:?}), value iteration used {}",
+        sweeping_report.backups, sweeping_report.elapsed, report.backups
+    );
+
+    for (name, order) in [
+        ("natural", StateOrder::Natural),
+        ("reverse", StateOrder::Reverse),
+        ("random", StateOrder::Random),
+    ]
+    .iter()
+    {
+        let (_, _, report) =
+            value_iteration_in_place(&env, order, 1.0, 0.0000001, 100000, &mut rng);
+        println!(
+            "In-place value iteration ({} order) done in {} sweeps ({:?})",
+            name, report.sweeps, report.elapsed
+        );
+    }
+    let (_, _, async_report) = value_iteration_async(&env, 1.0, 0.0000001, 100000, &mut rng);
+    println!(
+        "Asynchronous value iteration done in {} sweeps ({:?})",
+        async_report.sweeps, async_report.elapsed
+    );
+
+    let (_, lp_state_values) = linear_programming(&env, 1.0);
+    let max_difference = (1..LIMIT)
+        .map(|money| (lp_state_values[&money] - state_values[&money]).abs())
+        .fold(0.0, f64::max);
+    println!(
+        "Linear programming done, max difference from value iteration: {}",
+        max_difference
+    );
+
+    // Budget-limited game: the gambler can only flip the coin a given number of times.
+    for flips in [1, 2, 5, 10, 20, 50].iter() {
+        let solution = backward_induction(&env, 1.0, *flips);
+        println!(
+            "With {} flips, winning probability from 10: {:.5}, from 50: {:.5}",
+            flips,
+            solution.state_values(*flips)[&10],
+            solution.state_values(*flips)[&50]
+        );
     }
 
     print_coin_state_values(&state_values);
 
     let uniform_policy = make_uniform_policy(&env);
     let cautious_policy = make_cautious_policy();
-    let optimal_policy = make_greedy_policy(&env, &state_values, 1.0);
     print_coin_policy(&optimal_policy);
 
     let simulations = 100000;
@@ -139,10 +183,12 @@ pub fn run() {
     let mut optimal_reward = 0.0;
     let start_state = 10;
     for _ in 0..simulations {
-        uniform_reward = uniform_reward + run_simulation(&env, &uniform_policy, start_state, 1000);
+        uniform_reward =
+            uniform_reward + run_simulation(&env, &uniform_policy, start_state, 1000, &mut rng);
         cautious_reward =
-            cautious_reward + run_simulation(&env, &cautious_policy, start_state, 1000);
-        optimal_reward = optimal_reward + run_simulation(&env, &optimal_policy, start_state, 1000);
+            cautious_reward + run_simulation(&env, &cautious_policy, start_state, 1000, &mut rng);
+        optimal_reward =
+            optimal_reward + run_simulation(&env, &optimal_policy, start_state, 1000, &mut rng);
     }
     println!(
         "Average uniform reward: {}",
@@ -157,3 +203,137 @@ pub fn run() {
         optimal_reward / simulations as f64
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prioritized_sweeping_gambler_test() {
+        let env = new_coin_env(0.4);
+
+        let (_, vi_state_values, vi_report) = value_iteration(&env, 1.0, 1e-9, 100000);
+        let (_, ps_state_values, ps_report) = prioritized_sweeping(&env, 1.0, 1e-9, 10000000);
+
+        println!(
+            "Value iteration: {} backups, prioritized sweeping: {} backups",
+            vi_report.backups, ps_report.backups
+        );
+        assert!(ps_report.policy_stable);
+        assert!(ps_report.backups < vi_report.backups);
+        for money in 1..LIMIT {
+            assert!((vi_state_values[&money] - ps_state_values[&money]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn in_place_value_iteration_gambler_test() {
+        let env = new_coin_env(0.4);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let (_, sync_state_values, sync_report) = value_iteration(&env, 1.0, 1e-9, 100000);
+        // The values propagate from the goal downward, so the reverse order is the best one.
+        let (_, reverse_state_values, reverse_report) =
+            value_iteration_in_place(&env, &StateOrder::Reverse, 1.0, 1e-9, 100000, &mut rng);
+        let (_, async_state_values, async_report) =
+            value_iteration_async(&env, 1.0, 1e-9, 100000, &mut rng);
+
+        println!(
+            "Synchronous: {} sweeps, in place: {}, asynchronous: {}",
+            sync_report.sweeps, reverse_report.sweeps, async_report.sweeps
+        );
+        assert!(reverse_report.sweeps < sync_report.sweeps);
+        for money in 1..LIMIT {
+            assert!((sync_state_values[&money] - reverse_state_values[&money]).abs() < 1e-6);
+            assert!((sync_state_values[&money] - async_state_values[&money]).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn linear_programming_gambler_test() {
+        let env = new_coin_env(0.4);
+
+        let (lp_policy, lp_state_values) = linear_programming(&env, 1.0);
+        let (vi_policy, vi_state_values, _) = value_iteration(&env, 1.0, 1e-12, 100000);
+        let (pi_policy, pi_state_values, pi_report) =
+            policy_iteration(&env, make_cautious_policy(), 1.0, 1e-12, 100000);
+        assert!(pi_report.policy_stable);
+
+        for money in 1..LIMIT {
+            let lp_value = lp_state_values[&money];
+            assert!(
+                (lp_value - vi_state_values[&money]).abs() < 1e-6,
+                "{}",
+                money
+            );
+            assert!(
+                (lp_value - pi_state_values[&money]).abs() < 1e-6,
+                "{}",
+                money
+            );
+        }
+
+        // Many bets are optimal, so the methods pick different policies, but each of them must be
+        // greedy with respect to the values found by the others, and the greedy policy must
+        // include the bet chosen by LP.
+        for (policy, state_values) in [
+            (&lp_policy, &vi_state_values),
+            (&vi_policy, &lp_state_values),
+            (&pi_policy, &lp_state_values),
+            (&vi_policy, &pi_state_values),
+        ]
+        .iter()
+        {
+            assert!(is_greedy_policy(&env, policy, state_values, 1.0));
+        }
+        let greedy_policy = make_greedy_policy(&env, &lp_state_values, 1.0);
+        for (money, policy_state) in lp_policy.states.iter() {
+            let lp_bet = policy_state.actions.keys().next().unwrap();
+            assert!(
+                greedy_policy.states[money].actions[lp_bet] > 0.0,
+                "{}",
+                money
+            );
+        }
+    }
+
+    #[test]
+    fn budget_limited_gambler_test() {
+        let heads_prob = 0.4;
+        let env = new_coin_env(heads_prob);
+        let solution = backward_induction(&env, 1.0, 10);
+        let (_, unlimited_state_values, _) = value_iteration(&env, 1.0, 1e-12, 100000);
+
+        // With a single flip, only the states from 50 up can win, by betting everything needed.
+        let one_flip = solution.state_values(1);
+        for money in 1..LIMIT {
+            let expected = if money >= 50 { heads_prob } else { 0.0 };
+            assert!((one_flip[&money] - expected).abs() < 1e-12, "{}", money);
+        }
+        assert_eq!(solution.policy(1).states[&50].actions.len(), 1);
+        assert!(solution.policy(1).states[&50].actions.contains_key(&50));
+
+        // With two flips, 25 has to win twice, while 75 can bet 25 and, if it loses, bet 50.
+        let two_flips = solution.state_values(2);
+        assert!((two_flips[&25] - heads_prob * heads_prob).abs() < 1e-12);
+        let expected = heads_prob + (1.0 - heads_prob) * heads_prob;
+        assert!((two_flips[&75] - expected).abs() < 1e-12);
+
+        // More flips never hurt, but can't beat the unlimited game.
+        for flips in 1..=10 {
+            for money in 1..LIMIT {
+                let value = solution.state_values(flips)[&money];
+                assert!(value >= solution.state_values(flips - 1)[&money] - 1e-12);
+                assert!(value <= unlimited_state_values[&money] + 1e-9);
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let one_flip_solution = backward_induction(&env, 1.0, 1);
+        let simulations = 10000;
+        let wins: f64 = (0..simulations)
+            .map(|_| run_simulation_finite_horizon(&env, &one_flip_solution, 50, &mut rng))
+            .sum();
+        assert!((wins / simulations as f64 - heads_prob).abs() < 0.02);
+    }
+}
@@ -2,7 +2,9 @@ use std::collections::HashMap;
 use std::fmt;
 
 use prettytable::{Cell, Row, Table};
+use rand::Rng;
 
+use crate::solver::environment::*;
 use crate::solver::explicit::*;
 use crate::solver::*;
 
@@ -17,7 +19,7 @@ pub struct State {
     col: i32,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Action {
     Up,
     Down,
@@ -25,10 +27,90 @@ pub enum Action {
     Right,
 }
 
+// Grid of given size where the agent moves until it reaches the top left or the bottom right
+// corner. Every move yields reward -1.
+pub struct GridWorld {
+    rows: i32,
+    cols: i32,
+}
+
+// Cliff walking grid from Example 6.6: 4 rows by 12 columns, the agent starts in the bottom
+// left corner and has to reach the bottom right one. Every move yields reward -1, except for
+// stepping into the cliff along the bottom edge, which yields -100 and sends the agent back to
+// the start.
+pub struct CliffWalk;
+
+const CLIFF_ROWS: i32 = 4;
+const CLIFF_COLS: i32 = 12;
+
 impl State {
     pub fn new(r: i32, c: i32) -> State {
         State { row: r, col: c }
     }
+
+    pub fn row(&self) -> i32 {
+        self.row
+    }
+
+    pub fn col(&self) -> i32 {
+        self.col
+    }
+}
+
+impl GridWorld {
+    pub fn new(rows: i32, cols: i32) -> GridWorld {
+        // Two corners are final, so there must be at least one more cell to start from.
+        assert!(
+            rows > 0 && cols > 0 && rows * cols > 2,
+            "Grid {}x{} has no non-final cells",
+            rows,
+            cols
+        );
+        GridWorld { rows, cols }
+    }
+
+    fn is_final(&self, state: &State) -> bool {
+        (state.row == 0 && state.col == 0)
+            || (state.row == self.rows - 1 && state.col == self.cols - 1)
+    }
+
+    // Returns the state the action leads to. Moving into a wall leaves the state unchanged.
+    fn move_state(&self, state: &State, action: &Action) -> State {
+        match action {
+            Action::Up => State::new((state.row - 1).max(0), state.col),
+            Action::Down => State::new((state.row + 1).min(self.rows - 1), state.col),
+            Action::Left => State::new(state.row, (state.col - 1).max(0)),
+            Action::Right => State::new(state.row, (state.col + 1).min(self.cols - 1)),
+        }
+    }
+}
+
+impl Environment for GridWorld {
+    type State = State;
+    type Action = Action;
+
+    // Starts from a random non-final cell.
+    fn reset<R: Rng>(&self, rng: &mut R) -> State {
+        loop {
+            let state = State::new(rng.gen_range(0..self.rows), rng.gen_range(0..self.cols));
+            if !self.is_final(&state) {
+                return state;
+            }
+        }
+    }
+
+    fn actions(&self, _state: &State) -> Vec<Action> {
+        vec![Action::Up, Action::Down, Action::Left, Action::Right]
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, _rng: &mut R) -> (Option<State>, f64) {
+        let new_state = self.move_state(state, action);
+        if self.is_final(&new_state) {
+            (None, -1.0)
+        } else {
+            (Some(new_state), -1.0)
+        }
+    }
 }
 
 impl fmt::Display for State {
@@ -52,33 +134,57 @@ impl fmt::Display for Action {
     }
 }
 
+impl CliffWalk {
+    pub fn start_state() -> State {
+        State::new(CLIFF_ROWS - 1, 0)
+    }
+
+    pub fn goal_state() -> State {
+        State::new(CLIFF_ROWS - 1, CLIFF_COLS - 1)
+    }
+}
+
+impl Environment for CliffWalk {
+    type State = State;
+    type Action = Action;
+
+    fn reset<R: Rng>(&self, _rng: &mut R) -> State {
+        CliffWalk::start_state()
+    }
+
+    fn actions(&self, _state: &State) -> Vec<Action> {
+        vec![Action::Up, Action::Down, Action::Left, Action::Right]
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, _rng: &mut R) -> (Option<State>, f64) {
+        let new_state = GridWorld::new(CLIFF_ROWS, CLIFF_COLS).move_state(state, action);
+        if new_state == CliffWalk::goal_state() {
+            (None, -1.0)
+        } else if new_state.row == CLIFF_ROWS - 1 && new_state.col > 0 {
+            (Some(CliffWalk::start_state()), -100.0)
+        } else {
+            (Some(new_state), -1.0)
+        }
+    }
+}
+
 pub fn new_grid_env(rows: i32, cols: i32) -> Env<State, Action> {
+    let grid = GridWorld::new(rows, cols);
     let mut states = HashMap::new();
     for row in 0..rows {
         for col in 0..cols {
+            let state = State::new(row, col);
             let mut actions = HashMap::new();
 
             // Not a final state.
-            if (row != 0 || col != 0) && (row != rows - 1 || col != cols - 1) {
-                actions.insert(
-                    Action::Up,
-                    deterministic_action(State::new((row - 1).max(0), col), -1.0),
-                );
-                actions.insert(
-                    Action::Down,
-                    deterministic_action(State::new((row + 1).min(rows - 1), col), -1.0),
-                );
-                actions.insert(
-                    Action::Left,
-                    deterministic_action(State::new(row, (col - 1).max(0)), -1.0),
-                );
-                actions.insert(
-                    Action::Right,
-                    deterministic_action(State::new(row, (col + 1).min(cols - 1)), -1.0),
-                );
+            if !grid.is_final(&state) {
+                for action in grid.actions(&state) {
+                    let dest_state = grid.move_state(&state, &action);
+                    actions.insert(action, deterministic_action(dest_state, -1.0));
+                }
             }
 
-            states.insert(State::new(row, col), StateActions { actions: actions });
+            states.insert(state, StateActions { actions: actions });
         }
     }
     Env { states: states }
@@ -146,3 +252,172 @@ pub fn print_grid_policy(policy: &Policy<State, Action>, rows: i32, cols: i32) {
     }
     table.printstd();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::solver::{linear, monte_carlo, td};
+
+    // Returns the total reward collected by following the greedy policy from the start.
+    fn greedy_returns(action_values: &HashMap<State, HashMap<Action, f64>>) -> f64 {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut state = CliffWalk::start_state();
+        let mut returns = 0.0;
+        for _ in 0..100 {
+            let action = action_values[&state]
+                .iter()
+                .max_by(|(a1, v1), (a2, v2)| v1.partial_cmp(v2).unwrap().then(a1.cmp(a2)))
+                .unwrap()
+                .0;
+            let (new_state, reward) = CliffWalk.step(&state, action, &mut rng);
+            returns += reward;
+            match new_state {
+                Some(new_state) => state = new_state,
+                None => break,
+            }
+        }
+        returns
+    }
+
+    #[test]
+    fn cliff_walk_sarsa_vs_q_learning_test() {
+        let (discount, exploration_fraction, alpha, iterations) = (1.0, 0.1, 0.5, 500);
+
+        let q_learning_values = td::find_action_values_q_learning(
+            &CliffWalk,
+            discount,
+            exploration_fraction,
+            alpha,
+            iterations,
+            &mut StdRng::seed_from_u64(0),
+        );
+        let sarsa_values = td::find_action_values_sarsa(
+            &CliffWalk,
+            discount,
+            exploration_fraction,
+            alpha,
+            iterations,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        // Q-learning learns the optimal path right along the cliff.
+        assert_eq!(greedy_returns(&q_learning_values), -13.0);
+
+        // SARSA takes exploration into account and learns a safer, longer path.
+        let sarsa_returns = greedy_returns(&sarsa_values);
+        assert!(
+            sarsa_returns < -13.0 && sarsa_returns > -100.0,
+            "{}",
+            sarsa_returns
+        );
+    }
+
+    #[test]
+    fn monte_carlo_visits_and_update_rules_test() {
+        use monte_carlo::{UpdateRule, Visits};
+
+        // Exact values of the random policy.
+        let env = new_grid_env(4, 4);
+        let expected = linear::evaluate_policy_exact(&env, &new_grid_random_policy(&env), 1.0);
+
+        let grid_world = GridWorld::new(4, 4);
+        let policy = |s: &State, rng: &mut StdRng| grid_world.random_action(s, rng);
+
+        // Returns the RMS error of the estimates over all states and seeds, and the spread of
+        // the estimates across seeds (standard deviation, averaged over the states).
+        let evaluate = |visits: &Visits, update_rule: &UpdateRule| {
+            let runs: Vec<HashMap<State, f64>> = (0..10)
+                .map(|seed| {
+                    monte_carlo::evaluate_policy(
+                        &grid_world,
+                        &policy,
+                        visits,
+                        update_rule,
+                        1.0,
+                        5000,
+                        &mut StdRng::seed_from_u64(seed),
+                    )
+                })
+                .collect();
+
+            let mut squared_error = 0.0;
+            let mut spread = 0.0;
+            for (state, value) in expected.iter() {
+                let mean = runs.iter().map(|r| r[state]).sum::<f64>() / runs.len() as f64;
+                let variance =
+                    runs.iter().map(|r| (r[state] - mean).powi(2)).sum::<f64>() / runs.len() as f64;
+                spread += variance.sqrt();
+                squared_error += runs.iter().map(|r| (r[state] - value).powi(2)).sum::<f64>()
+                    / runs.len() as f64;
+            }
+            (
+                (squared_error / expected.len() as f64).sqrt(),
+                spread / expected.len() as f64,
+            )
+        };
+
+        for (update_rule, first_max_rms_error, every_max_rms_error) in [
+            (UpdateRule::SampleAverage, 0.6, 0.6),
+            (UpdateRule::ConstantStepSize(0.005), 1.0, 2.0),
+        ]
+        .iter()
+        {
+            let (first_rms_error, first_spread) = evaluate(&Visits::First, update_rule);
+            let (every_rms_error, every_spread) = evaluate(&Visits::Every, update_rule);
+            println!(
+                "{:?}: first visit RMS error {:.3}, spread {:.3}; every visit RMS error {:.3}, \
+                 spread {:.3}",
+                update_rule, first_rms_error, first_spread, every_rms_error, every_spread
+            );
+
+            assert!(first_rms_error < *first_max_rms_error);
+            assert!(every_rms_error < *every_max_rms_error);
+
+            // Returns from the repeated visits in an episode are correlated, so every-visit
+            // estimates vary more between runs. With constant step size, they also get more
+            // updates per episode, which acts as a larger α.
+            assert!(first_spread < every_spread);
+        }
+    }
+
+    #[test]
+    fn monte_carlo_off_policy_visits_test() {
+        use monte_carlo::{ImportanceSampling, UpdateRule, Visits};
+
+        // When the target policy is the behaviour policy, all the ratios are 1, so weighted
+        // importance sampling is the sample average, for either visit mode.
+        let grid_world = GridWorld::new(4, 4);
+        let policy = |s: &State, rng: &mut StdRng| grid_world.random_action(s, rng);
+        for visits in [Visits::First, Visits::Every].iter() {
+            let expected = monte_carlo::evaluate_policy(
+                &grid_world,
+                &policy,
+                visits,
+                &UpdateRule::SampleAverage,
+                1.0,
+                100,
+                &mut StdRng::seed_from_u64(0),
+            );
+            let actual = monte_carlo::evaluate_policy_off_policy(
+                &grid_world,
+                &policy,
+                &|_s: &State, _a: &Action| 0.25,
+                &|_s: &State, _a: &Action| 0.25,
+                visits,
+                &ImportanceSampling::Weighted,
+                1.0,
+                100,
+                &mut StdRng::seed_from_u64(0),
+            );
+
+            assert_eq!(actual.len(), expected.len());
+            for (state, value) in expected.iter() {
+                assert!((actual[state] - value).abs() < 1e-9, "{:?}", visits);
+            }
+        }
+    }
+}
@@ -1,7 +1,11 @@
+mod access_control;
 mod blackjack;
 mod car_rental;
 mod coin_bet;
 mod gridworld;
+mod maximization_bias;
+mod maze;
+mod mountain_car;
 mod solver;
 
 use std::collections::HashMap;
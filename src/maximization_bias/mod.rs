@@ -0,0 +1,274 @@
+use std::cell::RefCell;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::solver::environment::*;
+use crate::solver::*;
+
+// The MDP from Example 6.7 (maximization bias): from state A, going right ends the episode with
+// reward 0, going left leads to state B with reward 0. From state B, any of the many actions
+// ends the episode with a reward drawn from normal distribution with mean -0.1 and variance 1.
+// So going left is worse on average, but some of the B actions look good on a small sample.
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum State {
+    A,
+    B,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Action {
+    Left,
+    Right,
+    // One of the actions from state B.
+    Exit(u32),
+}
+
+pub struct MaximizationBias {
+    exit_count: u32,
+}
+
+// Wraps the MDP to record, for each episode, whether the agent went left from A.
+pub struct LeftActionRecorder<'a> {
+    env: &'a MaximizationBias,
+    left_actions: RefCell<Vec<bool>>,
+}
+
+const EXIT_REWARD_MEAN: f64 = -0.1;
+const EXIT_REWARD_STD_DEV: f64 = 1.0;
+
+impl MaximizationBias {
+    // Creates the MDP with the given number of actions from state B.
+    pub fn new(exit_count: u32) -> Self {
+        MaximizationBias { exit_count }
+    }
+}
+
+impl<'a> LeftActionRecorder<'a> {
+    pub fn new(env: &'a MaximizationBias) -> Self {
+        LeftActionRecorder {
+            env,
+            left_actions: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Returns, for each episode since creation, whether the agent went left from A.
+    pub fn left_actions(&self) -> Vec<bool> {
+        self.left_actions.borrow().clone()
+    }
+}
+
+impl Environment for MaximizationBias {
+    type State = State;
+    type Action = Action;
+
+    fn reset<R: Rng>(&self, _rng: &mut R) -> State {
+        State::A
+    }
+
+    fn actions(&self, state: &State) -> Vec<Action> {
+        match state {
+            State::A => vec![Action::Left, Action::Right],
+            State::B => (0..self.exit_count).map(Action::Exit).collect(),
+        }
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, rng: &mut R) -> (Option<State>, f64) {
+        match (state, action) {
+            (State::A, Action::Left) => (Some(State::B), 0.0),
+            (State::A, Action::Right) => (None, 0.0),
+            (State::B, Action::Exit(_)) => (
+                None,
+                sample_normal(EXIT_REWARD_MEAN, EXIT_REWARD_STD_DEV, rng),
+            ),
+            _ => panic!("Action {:?} is not possible from {:?}", action, state),
+        }
+    }
+}
+
+impl Environment for LeftActionRecorder<'_> {
+    type State = State;
+    type Action = Action;
+
+    fn reset<R: Rng>(&self, rng: &mut R) -> State {
+        self.left_actions.borrow_mut().push(false);
+        self.env.reset(rng)
+    }
+
+    fn actions(&self, state: &State) -> Vec<Action> {
+        self.env.actions(state)
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, rng: &mut R) -> (Option<State>, f64) {
+        if let (State::A, Action::Left) = (state, action) {
+            if let Some(left) = self.left_actions.borrow_mut().last_mut() {
+                *left = true;
+            }
+        }
+        self.env.step(state, action, rng)
+    }
+}
+
+// Runs the learning algorithm `runs` times for `episodes` episodes each, and returns the fraction
+// of runs that went left from A for each episode.
+pub fn left_action_fractions<Learn>(learn: Learn, runs: usize, episodes: u64, seed: u64) -> Vec<f64>
+where
+    Learn: Fn(&LeftActionRecorder, u64, &mut StdRng),
+{
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut left_counts = vec![0; episodes as usize];
+    let env = MaximizationBias::new(10);
+    for _ in 0..runs {
+        let recorder = LeftActionRecorder::new(&env);
+        learn(&recorder, episodes, &mut rng);
+        for (count, left) in left_counts.iter_mut().zip(recorder.left_actions()) {
+            if left {
+                *count += 1;
+            }
+        }
+    }
+
+    left_counts
+        .into_iter()
+        .map(|count| count as f64 / runs as f64)
+        .collect()
+}
+
+pub fn run() {
+    let (discount, exploration_fraction, alpha) = (1.0, 0.1, 0.1);
+    let q_learning = left_action_fractions(
+        |env, episodes, rng| {
+            td::find_action_values_q_learning(
+                env,
+                discount,
+                exploration_fraction,
+                alpha,
+                episodes,
+                rng,
+            );
+        },
+        10000,
+        300,
+        0,
+    );
+    let double_q_learning = left_action_fractions(
+        |env, episodes, rng| {
+            td::find_action_values_double_q_learning(
+                env,
+                discount,
+                exploration_fraction,
+                alpha,
+                episodes,
+                rng,
+            );
+        },
+        10000,
+        300,
+        0,
+    );
+
+    println!("Episode  Q-learning  Double Q-learning");
+    for episode in (0..300).step_by(10) {
+        println!(
+            "{:7}  {:10.3}  {:17.3}",
+            episode + 1,
+            q_learning[episode],
+            double_q_learning[episode]
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maximization_bias_test() {
+        let (discount, exploration_fraction, alpha) = (1.0, 0.1, 0.1);
+        let (runs, episodes) = (300, 100);
+
+        let q_learning = left_action_fractions(
+            |env, episodes, rng| {
+                td::find_action_values_q_learning(
+                    env,
+                    discount,
+                    exploration_fraction,
+                    alpha,
+                    episodes,
+                    rng,
+                );
+            },
+            runs,
+            episodes,
+            0,
+        );
+        let double_q_learning = left_action_fractions(
+            |env, episodes, rng| {
+                td::find_action_values_double_q_learning(
+                    env,
+                    discount,
+                    exploration_fraction,
+                    alpha,
+                    episodes,
+                    rng,
+                );
+            },
+            runs,
+            episodes,
+            0,
+        );
+
+        let double_expected_sarsa = left_action_fractions(
+            |env, episodes, rng| {
+                td::find_action_values_double_expected_sarsa(
+                    env,
+                    discount,
+                    exploration_fraction,
+                    alpha,
+                    episodes,
+                    rng,
+                );
+            },
+            runs,
+            episodes,
+            0,
+        );
+
+        // Average the fractions over the episodes 10-50, where the bias shows the most.
+        let average = |fractions: &[f64]| fractions[10..50].iter().sum::<f64>() / 40.0;
+        let q_learning_left = average(&q_learning);
+        let double_q_learning_left = average(&double_q_learning);
+        let double_expected_sarsa_left = average(&double_expected_sarsa);
+        println!(
+            "Left fraction: Q-learning {:.3}, Double Q-learning {:.3}, Double Expected SARSA {:.3}",
+            q_learning_left, double_q_learning_left, double_expected_sarsa_left
+        );
+
+        // Q-learning overestimates the value of B and goes left much more often than the optimal
+        // ε / 2 = 5%.
+        assert!(q_learning_left > 0.2);
+        assert!(double_q_learning_left < q_learning_left - 0.1);
+        assert!(double_expected_sarsa_left < q_learning_left - 0.1);
+    }
+
+    #[test]
+    fn double_expected_sarsa_values_test() {
+        // With the uniform random policy, B is visited in half of the episodes, and both tables
+        // should converge to the mean exit reward.
+        let mut rng = StdRng::seed_from_u64(0);
+        let env = MaximizationBias::new(10);
+        let (action_values_1, action_values_2) =
+            td::find_action_values_double_expected_sarsa(&env, 1.0, 1.0, 0.01, 20000, &mut rng);
+
+        let b_value = (0..10)
+            .map(|i| {
+                action_values_1[&State::B][&Action::Exit(i)]
+                    + action_values_2[&State::B][&Action::Exit(i)]
+            })
+            .sum::<f64>()
+            / 20.0;
+        println!("Q(B, ∙) = {:.3}", b_value);
+        assert!((b_value - EXIT_REWARD_MEAN).abs() < 0.05);
+    }
+}
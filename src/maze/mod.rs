@@ -0,0 +1,282 @@
+use std::cell::Cell;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::gridworld::{Action, State};
+use crate::solver::dyna;
+use crate::solver::environment::*;
+
+const MAZE_ROWS: i32 = 6;
+const MAZE_COLS: i32 = 9;
+
+// 6 by 9 maze from Chapter 8. The agent moves between the cells until it reaches the goal, which
+// yields reward 1; all other moves yield 0. Moving into a wall or out of the maze leaves the
+// agent in place. For the blocking and shortcut mazes the walls change after a given number of
+// steps (counted over all episodes), so a new maze must be created for each run.
+pub struct Maze {
+    start: State,
+    goal: State,
+    walls: Vec<State>,
+    // The number of steps after which the layout changes, and the walls after the change.
+    changed_walls: Option<(u64, Vec<State>)>,
+    steps: Cell<u64>,
+}
+
+// Returns the cells of a horizontal wall in the given row.
+fn wall_row(row: i32, cols: std::ops::RangeInclusive<i32>) -> Vec<State> {
+    cols.map(|col| State::new(row, col)).collect()
+}
+
+impl Maze {
+    // The Dyna maze from Example 8.1.
+    pub fn dyna() -> Maze {
+        Maze {
+            start: State::new(2, 0),
+            goal: State::new(0, 8),
+            walls: vec![
+                State::new(1, 2),
+                State::new(2, 2),
+                State::new(3, 2),
+                State::new(4, 5),
+                State::new(0, 7),
+                State::new(1, 7),
+                State::new(2, 7),
+            ],
+            changed_walls: None,
+            steps: Cell::new(0),
+        }
+    }
+
+    // The blocking maze from Example 8.2: after 1000 steps the short path along the right side
+    // is blocked, and a longer one opens on the left.
+    pub fn blocking() -> Maze {
+        Maze {
+            start: State::new(5, 3),
+            goal: State::new(0, 8),
+            walls: wall_row(3, 0..=7),
+            changed_walls: Some((1000, wall_row(3, 1..=8))),
+            steps: Cell::new(0),
+        }
+    }
+
+    // The shortcut maze from Example 8.3: after 3000 steps a shorter path opens on the right,
+    // while the old one stays open.
+    pub fn shortcut() -> Maze {
+        Maze {
+            start: State::new(5, 3),
+            goal: State::new(0, 8),
+            walls: wall_row(3, 1..=8),
+            changed_walls: Some((3000, wall_row(3, 1..=7))),
+            steps: Cell::new(0),
+        }
+    }
+
+    // Returns the walls at the current step.
+    fn walls(&self) -> &[State] {
+        match &self.changed_walls {
+            Some((change_step, changed_walls)) if self.steps.get() >= *change_step => changed_walls,
+            _ => &self.walls,
+        }
+    }
+}
+
+impl Environment for Maze {
+    type State = State;
+    type Action = Action;
+
+    fn reset<R: Rng>(&self, _rng: &mut R) -> State {
+        self.start
+    }
+
+    fn actions(&self, _state: &State) -> Vec<Action> {
+        vec![Action::Up, Action::Down, Action::Left, Action::Right]
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, _rng: &mut R) -> (Option<State>, f64) {
+        let (row, col) = (state.row(), state.col());
+        let new_state = match action {
+            Action::Up => State::new((row - 1).max(0), col),
+            Action::Down => State::new((row + 1).min(MAZE_ROWS - 1), col),
+            Action::Left => State::new(row, (col - 1).max(0)),
+            Action::Right => State::new(row, (col + 1).min(MAZE_COLS - 1)),
+        };
+        let new_state = if self.walls().contains(&new_state) {
+            *state
+        } else {
+            new_state
+        };
+        self.steps.set(self.steps.get() + 1);
+
+        if new_state == self.goal {
+            (None, 1.0)
+        } else {
+            (Some(new_state), 0.0)
+        }
+    }
+}
+
+// Returns the cumulative reward by each step, averaged over the runs, for Dyna-Q (κ = 0) or
+// Dyna-Q+ (κ > 0) on the mazes created by `new_maze`.
+pub fn average_cumulative_rewards<NewMaze>(
+    new_maze: NewMaze,
+    exploration_bonus: f64,
+    planning_steps: usize,
+    steps: u64,
+    runs: usize,
+    seed: u64,
+) -> Vec<f64>
+where
+    NewMaze: Fn() -> Maze,
+{
+    let (discount, exploration_fraction, alpha) = (0.95, 0.1, 1.0);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut total_rewards = vec![0.0; steps as usize];
+    for _ in 0..runs {
+        let maze = new_maze();
+        let result = if exploration_bonus > 0.0 {
+            dyna::find_action_values_dyna_q_plus(
+                &maze,
+                exploration_bonus,
+                planning_steps,
+                discount,
+                exploration_fraction,
+                alpha,
+                steps,
+                &mut rng,
+            )
+        } else {
+            dyna::find_action_values_dyna_q(
+                &maze,
+                planning_steps,
+                discount,
+                exploration_fraction,
+                alpha,
+                steps,
+                &mut rng,
+            )
+        };
+        for (total, reward) in total_rewards.iter_mut().zip(result.cumulative_rewards) {
+            *total += reward;
+        }
+    }
+
+    total_rewards
+        .into_iter()
+        .map(|total| total / runs as f64)
+        .collect()
+}
+
+pub fn run() {
+    // Figures 8.4 and 8.5.
+    for (name, new_maze, steps) in [
+        ("Blocking", Maze::blocking as fn() -> Maze, 3000),
+        ("Shortcut", Maze::shortcut as fn() -> Maze, 6000),
+    ]
+    .iter()
+    {
+        let dyna_q = average_cumulative_rewards(new_maze, 0.0, 10, *steps, 20, 0);
+        let dyna_q_plus = average_cumulative_rewards(new_maze, 1e-3, 10, *steps, 20, 0);
+
+        println!("{} maze", name);
+        println!("   Step  Dyna-Q  Dyna-Q+");
+        for step in (0..*steps as usize).step_by(500) {
+            println!(
+                "{:7}  {:6.1}  {:7.1}",
+                step + 1,
+                dyna_q[step],
+                dyna_q_plus[step]
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dyna_maze_planning_test() {
+        // Fraction of steps that reach the goal in the later part of the run. The first episode
+        // is a random walk that can take thousands of steps.
+        let efficiency = |planning_steps| {
+            let rewards = average_cumulative_rewards(Maze::dyna, 0.0, planning_steps, 3000, 5, 0);
+            (rewards[2999] - rewards[1999]) / 1000.0
+        };
+
+        // The shortest path takes 14 steps. Without planning the agent is still learning it,
+        // while with planning it's found quickly (and only lengthened by exploration).
+        let no_planning = efficiency(0);
+        let planning = efficiency(50);
+        println!("No planning: {:.4}, planning: {:.4}", no_planning, planning);
+        assert!(planning > no_planning);
+        assert!(planning > 1.0 / 20.0);
+    }
+
+    #[test]
+    fn blocking_maze_test() {
+        let dyna_q = average_cumulative_rewards(Maze::blocking, 0.0, 10, 3000, 5, 0);
+        let dyna_q_plus = average_cumulative_rewards(Maze::blocking, 1e-3, 10, 3000, 5, 0);
+
+        // Dyna-Q+ finds the new path after the layout change faster.
+        println!(
+            "Dyna-Q: {:.1} -> {:.1}, Dyna-Q+: {:.1} -> {:.1}",
+            dyna_q[999], dyna_q[2999], dyna_q_plus[999], dyna_q_plus[2999]
+        );
+        assert!(dyna_q_plus[2999] - dyna_q_plus[999] > 50.0);
+        assert!(dyna_q_plus[2999] - dyna_q_plus[999] > dyna_q[2999] - dyna_q[999]);
+    }
+
+    #[test]
+    fn shortcut_maze_test() {
+        let dyna_q = average_cumulative_rewards(Maze::shortcut, 0.0, 10, 6000, 5, 0);
+        let dyna_q_plus = average_cumulative_rewards(Maze::shortcut, 1e-3, 10, 6000, 5, 0);
+
+        // Dyna-Q never finds the shortcut, so after the change it collects the reward at the same
+        // rate as before.
+        let rate_before = |rewards: &[f64]| (rewards[2999] - rewards[1999]) / 1000.0;
+        let rate_after = |rewards: &[f64]| (rewards[5999] - rewards[4999]) / 1000.0;
+        println!(
+            "Dyna-Q: {:.4} -> {:.4}, Dyna-Q+: {:.4} -> {:.4}",
+            rate_before(&dyna_q),
+            rate_after(&dyna_q),
+            rate_before(&dyna_q_plus),
+            rate_after(&dyna_q_plus)
+        );
+        assert!(rate_after(&dyna_q_plus) > rate_before(&dyna_q_plus) * 1.2);
+        assert!(rate_after(&dyna_q_plus) > rate_after(&dyna_q));
+    }
+
+    #[test]
+    fn prioritized_sweeping_maze_test() {
+        // Same measure as in dyna_maze_planning_test.
+        let mut rng = StdRng::seed_from_u64(0);
+        let runs = 5;
+        let mut efficiency = 0.0;
+        for _ in 0..runs {
+            let result = dyna::find_action_values_prioritized_sweeping(
+                &Maze::dyna(),
+                5,
+                1e-4,
+                0.95,
+                0.1,
+                1.0,
+                3000,
+                &mut rng,
+            );
+            let rewards = result.cumulative_rewards;
+            efficiency += (rewards[2999] - rewards[1999]) / 1000.0 / runs as f64;
+        }
+        let dyna_q = average_cumulative_rewards(Maze::dyna, 0.0, 5, 3000, runs, 0);
+        let dyna_q_efficiency = (dyna_q[2999] - dyna_q[1999]) / 1000.0;
+
+        // With the same number of planning updates, prioritized sweeping finds the shortest path
+        // faster than Dyna-Q.
+        println!(
+            "Prioritized sweeping: {:.4}, Dyna-Q: {:.4}",
+            efficiency, dyna_q_efficiency
+        );
+        assert!(efficiency > 1.0 / 25.0);
+        assert!(efficiency > dyna_q_efficiency);
+    }
+}
@@ -0,0 +1,460 @@
+use nalgebra::DVector;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::solver::approximate::SparseFeatures;
+use crate::solver::environment::*;
+use crate::solver::policy_gradient::*;
+use crate::solver::tile::*;
+use crate::solver::*;
+
+// The mountain car task from Example 10.1: an underpowered car must drive up a steep mountain
+// road. Gravity is stronger than the engine, so the car first has to move away from the goal, up
+// the opposite slope, to build up enough inertia. The reward is -1 on every step until the car
+// reaches the goal at the top of the right slope.
+
+const POSITION_MIN: f64 = -1.2;
+const POSITION_MAX: f64 = 0.5;
+const VELOCITY_MAX: f64 = 0.07;
+
+// Number of tilings, and number of tiles per dimension in each tiling.
+const TILING_COUNT: usize = 8;
+const TILES_PER_DIMENSION: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct State {
+    position: f64,
+    velocity: f64,
+}
+
+// Throttle: full reverse, zero and full forward.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Action {
+    Reverse,
+    Zero,
+    Forward,
+}
+
+impl Action {
+    fn throttle(&self) -> f64 {
+        match self {
+            Action::Reverse => -1.0,
+            Action::Zero => 0.0,
+            Action::Forward => 1.0,
+        }
+    }
+}
+
+// Moves the car with the given throttle in [-1, 1].
+// Returns the next state, or None if the car has reached the goal.
+fn move_car(state: &State, throttle: f64) -> Option<State> {
+    let velocity = (state.velocity + 0.001 * throttle - 0.0025 * (3.0 * state.position).cos())
+        .clamp(-VELOCITY_MAX, VELOCITY_MAX);
+    let position = (state.position + velocity).max(POSITION_MIN);
+    if position >= POSITION_MAX {
+        return None;
+    }
+
+    // The car stops when it hits the left wall.
+    let velocity = if position == POSITION_MIN {
+        0.0
+    } else {
+        velocity
+    };
+    Some(State { position, velocity })
+}
+
+// Starts at a random position at the bottom of the valley, with zero velocity.
+fn start_state<R: Rng>(rng: &mut R) -> State {
+    State {
+        position: rng.gen_range(-0.6..-0.4),
+        velocity: 0.0,
+    }
+}
+
+// The task with three discrete actions.
+pub struct MountainCar {
+    max_steps: u64,
+}
+
+impl MountainCar {
+    // Episodes are truncated after `max_steps` steps, since a poor policy may never reach the
+    // goal.
+    pub fn new(max_steps: u64) -> Self {
+        MountainCar { max_steps }
+    }
+}
+
+impl Environment for MountainCar {
+    type State = State;
+    type Action = Action;
+
+    fn reset<R: Rng>(&self, rng: &mut R) -> State {
+        start_state(rng)
+    }
+
+    fn actions(&self, _state: &State) -> Vec<Action> {
+        vec![Action::Reverse, Action::Zero, Action::Forward]
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &Action, _rng: &mut R) -> (Option<State>, f64) {
+        (move_car(state, action.throttle()), -1.0)
+    }
+
+    fn max_steps(&self) -> Option<u64> {
+        Some(self.max_steps)
+    }
+}
+
+// The task with a continuous throttle. Actions outside of [-1, 1] are clipped.
+pub struct ContinuousMountainCar {
+    max_steps: u64,
+}
+
+impl ContinuousMountainCar {
+    pub fn new(max_steps: u64) -> Self {
+        ContinuousMountainCar { max_steps }
+    }
+}
+
+impl Environment for ContinuousMountainCar {
+    type State = State;
+    type Action = f64;
+
+    fn reset<R: Rng>(&self, rng: &mut R) -> State {
+        start_state(rng)
+    }
+
+    // The actions are continuous, so they can't be listed.
+    fn actions(&self, _state: &State) -> Vec<f64> {
+        Vec::new()
+    }
+
+    fn step<R: Rng>(&self, state: &State, action: &f64, _rng: &mut R) -> (Option<State>, f64) {
+        (move_car(state, action.clamp(-1.0, 1.0)), -1.0)
+    }
+
+    fn max_steps(&self) -> Option<u64> {
+        Some(self.max_steps)
+    }
+}
+
+// Tilings of the state space (and the actions, if `with_actions` is set). Every tiling has an
+// extra tile in each continuous dimension, since the tilings are offset to the right of the
+// lower bound, and still have to cover the whole range.
+pub fn tilings(with_actions: bool) -> TilingSet {
+    let dimension = |min: f64, max: f64| {
+        let tile_size = (max - min) / TILES_PER_DIMENSION as f64;
+        ContinuousDimension::new(min - tile_size, max, TILES_PER_DIMENSION + 1)
+    };
+    let action_dimensions = if with_actions {
+        vec![Bounds::new(0, 3)]
+    } else {
+        Vec::new()
+    };
+    TilingSet::from_dimensions(
+        &vec![
+            dimension(POSITION_MIN, POSITION_MAX),
+            dimension(-VELOCITY_MAX, VELOCITY_MAX),
+        ],
+        &action_dimensions,
+        TILING_COUNT,
+    )
+}
+
+fn binary_features(tilings: &TilingSet, pc: &[f64], pi: &[i32]) -> Vec<f64> {
+    let mut features = vec![0.0; tilings.tile_count()];
+    for i in tilings.get_tiles(pc, pi) {
+        features[i] = 1.0;
+    }
+    features
+}
+
+// Binary features x(s, a) of the active tiles. `tilings` must be created with actions.
+pub fn state_action_features(tilings: &TilingSet) -> impl Fn(&State, &Action) -> Vec<f64> + '_ {
+    move |s: &State, a: &Action| binary_features(tilings, &[s.position, s.velocity], &[*a as i32])
+}
+
+// Same as state_action_features(), but sparse.
+pub fn sparse_state_action_features(
+    tilings: &TilingSet,
+) -> impl Fn(&State, &Action) -> SparseFeatures + '_ {
+    move |s: &State, a: &Action| {
+        SparseFeatures::binary(tilings.get_tiles(&[s.position, s.velocity], &[*a as i32]))
+    }
+}
+
+// Binary features x(s) of the active tiles. `tilings` must be created without actions.
+pub fn state_features(tilings: &TilingSet) -> impl Fn(&State) -> Vec<f64> + '_ {
+    move |s: &State| binary_features(tilings, &[s.position, s.velocity], &[])
+}
+
+// Returns the action with the highest value q̂(s, a, w) = w∙x(s, a), breaking ties at random.
+fn greedy_action<StateActionFeatures, R: Rng>(
+    w: &DVector<f64>,
+    state_action_features: &StateActionFeatures,
+    state: &State,
+    rng: &mut R,
+) -> Action
+where
+    StateActionFeatures: Fn(&State, &Action) -> SparseFeatures,
+{
+    let actions = [Action::Reverse, Action::Zero, Action::Forward];
+    let values: Vec<f64> = actions
+        .iter()
+        .map(|a| state_action_features(state, a).dot(w))
+        .collect();
+    let best_value = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let best_actions: Vec<Action> = actions
+        .iter()
+        .zip(&values)
+        .filter(|(_, v)| **v == best_value)
+        .map(|(a, _)| *a)
+        .collect();
+    *best_actions.choose(rng).unwrap()
+}
+
+// Returns the average number of steps to the goal (or to truncation) over the episodes.
+fn average_steps<E, Policy>(env: &E, policy: &Policy, episodes: usize, rng: &mut StdRng) -> f64
+where
+    E: Environment<State = State>,
+    Policy: Fn(&State, &mut StdRng) -> E::Action,
+{
+    let total: f64 = (0..episodes)
+        .map(|_| -monte_carlo::run_simulation(env, policy, rng))
+        .sum();
+    total / episodes as f64
+}
+
+pub fn run() {
+    let env = MountainCar::new(10000);
+    let state_action_tilings = tilings(true);
+    let state_tilings = tilings(false);
+    let x_sa = state_action_features(&state_action_tilings);
+    let sparse_x_sa = sparse_state_action_features(&state_action_tilings);
+    let x_s = state_features(&state_tilings);
+    let alpha = 0.5 / TILING_COUNT as f64;
+    let mut rng = StdRng::seed_from_u64(0);
+
+    println!("Episodes  Semi-gradient SARSA  Actor-critic  Actor-critic(λ)");
+    for episodes in [20, 50, 100, 200].iter() {
+        let w = approximate::find_action_values_episodic_semi_gradient_sarsa_sparse(
+            &env,
+            state_action_tilings.tile_count(),
+            &sparse_x_sa,
+            1.0,
+            0.0,
+            alpha,
+            *episodes,
+            &mut rng,
+        );
+        let sarsa_steps = average_steps(
+            &env,
+            &|s: &State, rng: &mut StdRng| greedy_action(&w, &sparse_x_sa, s, rng),
+            100,
+            &mut rng,
+        );
+
+        let policy = SoftmaxPolicy::new(&x_sa);
+        let (theta, _) = actor_critic(&env, &policy, &x_s, 1.0, 0.1, alpha, *episodes, &mut rng);
+        let actor_critic_steps = average_steps(
+            &env,
+            &|s: &State, rng: &mut StdRng| policy.choose_action(&theta, s, &env.actions(s), rng),
+            100,
+            &mut rng,
+        );
+
+        let (theta, _) = actor_critic_lambda(
+            &env, &policy, &x_s, 0.9, 0.9, 1.0, 0.01, alpha, *episodes, &mut rng,
+        );
+        let actor_critic_lambda_steps = average_steps(
+            &env,
+            &|s: &State, rng: &mut StdRng| policy.choose_action(&theta, s, &env.actions(s), rng),
+            100,
+            &mut rng,
+        );
+
+        println!(
+            "{:8}  {:19.1}  {:12.1}  {:15.1}",
+            episodes, sarsa_steps, actor_critic_steps, actor_critic_lambda_steps
+        );
+    }
+
+    // Gaussian policy with the continuous throttle.
+    let env = ContinuousMountainCar::new(10000);
+    let policy = GaussianPolicy::new(&x_s);
+    let (theta, _) = actor_critic(&env, &policy, &x_s, 1.0, 0.001, alpha, 200, &mut rng);
+    let steps = average_steps(
+        &env,
+        &|s: &State, rng: &mut StdRng| policy.choose_action(&theta, s, &[], rng),
+        100,
+        &mut rng,
+    );
+    println!("Gaussian actor-critic after 200 episodes: {:.1}", steps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_car_test() {
+        // The car can't climb the right slope from the bottom by just going forward.
+        let mut state = State {
+            position: -0.5,
+            velocity: 0.0,
+        };
+        for _ in 0..1000 {
+            state = move_car(&state, 1.0).unwrap();
+        }
+        assert!(state.position < 0.0);
+
+        // The left wall stops the car.
+        let state = move_car(
+            &State {
+                position: -1.19,
+                velocity: -0.05,
+            },
+            -1.0,
+        )
+        .unwrap();
+        assert_eq!(state.position, POSITION_MIN);
+        assert_eq!(state.velocity, 0.0);
+
+        // The goal ends the episode.
+        assert!(move_car(
+            &State {
+                position: 0.45,
+                velocity: 0.06,
+            },
+            1.0
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn tilings_test() {
+        let tilings = tilings(true);
+        assert_eq!(tilings.count(), TILING_COUNT);
+        let features = state_action_features(&tilings);
+
+        // Exactly one tile per tiling is active, and the actions don't share tiles.
+        let state = State {
+            position: -0.5,
+            velocity: 0.01,
+        };
+        let forward = features(&state, &Action::Forward);
+        let reverse = features(&state, &Action::Reverse);
+        assert_eq!(forward.iter().sum::<f64>(), TILING_COUNT as f64);
+        assert_eq!(
+            forward
+                .iter()
+                .zip(&reverse)
+                .map(|(x, y)| x * y)
+                .sum::<f64>(),
+            0.0
+        );
+    }
+
+    // With sparse features, semi-gradient SARSA is fast enough to learn for 100 episodes.
+    #[test]
+    fn sparse_semi_gradient_sarsa_test() {
+        let env = MountainCar::new(5000);
+        let tilings = tilings(true);
+        let x_sa = sparse_state_action_features(&tilings);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // The greedy policy needs more episodes than actor-critic to become reliable.
+        let w = approximate::find_action_values_episodic_semi_gradient_sarsa_sparse(
+            &env,
+            tilings.tile_count(),
+            &x_sa,
+            1.0,
+            0.0,
+            0.5 / TILING_COUNT as f64,
+            100,
+            &mut rng,
+        );
+        let steps = average_steps(
+            &env,
+            &|s: &State, rng: &mut StdRng| greedy_action(&w, &x_sa, s, rng),
+            10,
+            &mut rng,
+        );
+        println!("SARSA: {:.1}", steps);
+        assert!(steps < 300.0);
+    }
+
+    // A random policy needs thousands of steps to reach the goal, while the optimal one needs
+    // about 100. Semi-gradient SARSA needs many more episodes to get there.
+    #[test]
+    fn actor_critic_test() {
+        let env = MountainCar::new(5000);
+        let state_action_tilings = tilings(true);
+        let state_tilings = tilings(false);
+        let x_sa = state_action_features(&state_action_tilings);
+        let x_s = state_features(&state_tilings);
+        let alpha = 0.5 / TILING_COUNT as f64;
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let policy = SoftmaxPolicy::new(&x_sa);
+        let softmax_steps = |theta: &DVector<f64>, rng: &mut StdRng| {
+            average_steps(
+                &env,
+                &|s: &State, rng: &mut StdRng| policy.choose_action(theta, s, &env.actions(s), rng),
+                10,
+                rng,
+            )
+        };
+        let (theta, _) = actor_critic(&env, &policy, &x_s, 1.0, 0.1, alpha, 20, &mut rng);
+        let actor_critic_steps = softmax_steps(&theta, &mut rng);
+
+        // Semi-gradient SARSA after the same number of episodes.
+        let sparse_x_sa = sparse_state_action_features(&state_action_tilings);
+        let w = approximate::find_action_values_episodic_semi_gradient_sarsa_sparse(
+            &env,
+            state_action_tilings.tile_count(),
+            &sparse_x_sa,
+            1.0,
+            0.0,
+            alpha,
+            20,
+            &mut rng,
+        );
+        let sarsa_steps = average_steps(
+            &env,
+            &|s: &State, rng: &mut StdRng| greedy_action(&w, &sparse_x_sa, s, rng),
+            10,
+            &mut rng,
+        );
+
+        let (theta, _) = actor_critic_lambda(
+            &env, &policy, &x_s, 0.9, 0.9, 1.0, 0.01, alpha, 20, &mut rng,
+        );
+        let actor_critic_lambda_steps = softmax_steps(&theta, &mut rng);
+
+        let env = ContinuousMountainCar::new(5000);
+        let policy = GaussianPolicy::new(&x_s);
+        let (theta, _) = actor_critic(&env, &policy, &x_s, 1.0, 0.001, alpha, 20, &mut rng);
+        let gaussian_steps = average_steps(
+            &env,
+            &|s: &State, rng: &mut StdRng| policy.choose_action(&theta, s, &[], rng),
+            10,
+            &mut rng,
+        );
+
+        println!(
+            "Actor-critic: {:.1}, actor-critic(λ): {:.1}, Gaussian actor-critic: {:.1}, \
+             SARSA: {:.1}",
+            actor_critic_steps, actor_critic_lambda_steps, gaussian_steps, sarsa_steps
+        );
+        assert!(actor_critic_steps < 300.0);
+        assert!(actor_critic_lambda_steps < 300.0);
+        assert!(gaussian_steps < 300.0);
+
+        // After 20 episodes, the greedy policy of semi-gradient SARSA still can't reach the goal
+        // reliably.
+        assert!(actor_critic_steps < sarsa_steps / 2.0);
+        assert!(actor_critic_lambda_steps < sarsa_steps / 2.0);
+    }
+}
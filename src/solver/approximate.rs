@@ -1,102 +1,191 @@
 use nalgebra::DVector;
 
+use crate::solver::environment::*;
 use crate::solver::*;
 
-fn soft_greedy_action<S, A, I, StateActionFeatures>(
-    actions: &Vec<A>,
-    w: &DVector<f64>,
-    state_action_features: &StateActionFeatures,
-    state: &S,
-    action_indices: I,
+// Sparse feature vector: the indices of the non-zero features, optionally with their values.
+// Without values, the features are binary, i.e. all the active features are 1 (as with tile
+// coding). Lets the linear methods touch only the active weights, which matters when there are
+// many more features than active ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseFeatures {
+    indices: Vec<usize>,
+    values: Option<Vec<f64>>,
+}
+
+impl SparseFeatures {
+    // Binary features with the given active indices.
+    pub fn binary(indices: Vec<usize>) -> Self {
+        SparseFeatures {
+            indices,
+            values: None,
+        }
+    }
+
+    // Features with the given values at the given indices (all other features are 0).
+    pub fn with_values(indices: Vec<usize>, values: Vec<f64>) -> Self {
+        assert_eq!(indices.len(), values.len());
+        SparseFeatures {
+            indices,
+            values: Some(values),
+        }
+    }
+
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    // Returns the active (index, value) pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.indices.iter().enumerate().map(move |(i, index)| {
+            let value = self.values.as_ref().map_or(1.0, |values| values[i]);
+            (*index, value)
+        })
+    }
+
+    // Returns w∙x.
+    pub fn dot(&self, w: &DVector<f64>) -> f64 {
+        self.iter().map(|(index, value)| w[index] * value).sum()
+    }
+
+    // Adds scale∙x to w.
+    pub fn add_scaled_to(&self, w: &mut DVector<f64>, scale: f64) {
+        for (index, value) in self.iter() {
+            w[index] += scale * value;
+        }
+    }
+
+    // Returns the dense feature vector of the given length.
+    pub fn to_dense(&self, len: usize) -> Vec<f64> {
+        let mut features = vec![0.0; len];
+        for (index, value) in self.iter() {
+            features[index] += value;
+        }
+        features
+    }
+}
+
+// Determines the next action from given state following an ε-greedy policy derived from the
+// given action values.
+fn soft_greedy_action_by_value<A, R, ActionValue>(
+    actions: &[A],
+    action_value: ActionValue,
     exploration_fraction: f64,
-) -> usize
+    rng: &mut R,
+) -> A
 where
-    I: Iterator<Item = usize>,
-    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+    A: Clone,
+    R: Rng,
+    ActionValue: Fn(&A) -> f64,
 {
+    assert!(!actions.is_empty());
+
     // If we pass the exploration check, choose the action at random.
-    if rand::random::<f64>() <= exploration_fraction {
-        let all_action_indices: Vec<usize> = action_indices.collect();
-        return all_action_indices[rand::random::<usize>() % all_action_indices.len()].clone();
+    if rng.gen::<f64>() <= exploration_fraction {
+        return actions[rng.gen_range(0..actions.len())].clone();
     }
 
     // Go over the actions and find the "best" ones (ones having maximum value).
-    let mut best_action_indices = Vec::new();
+    let mut best_actions = Vec::new();
     let mut best_value = f64::NEG_INFINITY;
-    for a in action_indices {
-        let features = DVector::from_vec(state_action_features(state, &actions[a]));
-        let value = w.dot(&features);
+    for a in actions {
+        let value = action_value(a);
         if value > best_value {
-            best_action_indices.clear();
-            best_action_indices.push(a);
+            best_actions.clear();
+            best_actions.push(a);
             best_value = value;
+        } else if value == best_value {
+            best_actions.push(a);
         }
     }
 
     // Now choose at random between all "best" actions (trivial if there is only one).
-    if best_action_indices.len() == 1 {
-        best_action_indices[0]
+    if best_actions.len() == 1 {
+        best_actions[0].clone()
     } else {
-        return best_action_indices[rand::random::<usize>() % best_action_indices.len()].clone();
+        best_actions[rng.gen_range(0..best_actions.len())].clone()
     }
 }
 
-pub fn find_action_values_episodic_semi_gradient_sarsa<
-    S,
-    A: Eq + Hash + Clone,
-    StartState,
-    StateActionFeatures,
-    IsActionPossible,
-    NextState,
->(
-    actions: &Vec<A>,
-    start_state: &StartState,
+// Determines the next action from given state following an ε-greedy policy derived from the
+// approximated action values.
+fn soft_greedy_action<S, A, R, StateActionFeatures>(
+    actions: &[A],
+    w: &DVector<f64>,
+    state_action_features: &StateActionFeatures,
+    state: &S,
+    exploration_fraction: f64,
+    rng: &mut R,
+) -> A
+where
+    A: Clone,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    soft_greedy_action_by_value(
+        actions,
+        |a| w.dot(&DVector::from_vec(state_action_features(state, a))),
+        exploration_fraction,
+        rng,
+    )
+}
+
+// Determines state features count by creating a dummy start state.
+pub(crate) fn feature_count<S, A, E, R, StateActionFeatures>(
+    env: &E,
+    state_action_features: &StateActionFeatures,
+    rng: &mut R,
+) -> usize
+where
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let start_state = env.reset(rng);
+    let action = env.random_action(&start_state, rng);
+    state_action_features(&start_state, &action).len()
+}
+
+pub fn find_action_values_episodic_semi_gradient_sarsa<S, A, E, R, StateActionFeatures>(
+    env: &E,
     state_action_features: &StateActionFeatures,
-    is_action_possible: &IsActionPossible,
-    next_state: &NextState,
     discount: f64,
     exploration_fraction: f64,
     alpha: f64,
     iterations: usize,
+    rng: &mut R,
 ) -> DVector<f64>
 where
-    StartState: Fn() -> S,
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
     StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
-    IsActionPossible: Fn(&S, &A) -> bool,
-    NextState: Fn(&S, &A) -> (Option<S>, f64),
 {
-    // Determine state features count by creating a dummy start state.
-    let state_feature_count = {
-        let start_state = start_state();
-        let action = actions
-            .iter()
-            .find(|a| is_action_possible(&start_state, a))
-            .unwrap();
-        state_action_features(&start_state, action).len()
-    };
-
-    // Store weights independently for each action (using action index as a key).
-    let mut w = DVector::repeat(state_feature_count, 0.0);
+    // All actions share the same weights, the action is encoded in the features.
+    let mut w = DVector::repeat(feature_count(env, state_action_features, rng), 0.0);
 
     for _ in 0..iterations {
         // Generate a single episode.
 
         // Generate the starting state and action from it.
-        let mut state = start_state();
-        let mut action_index = soft_greedy_action(
-            actions,
+        let mut state = env.reset(rng);
+        let mut action = soft_greedy_action(
+            &env.actions(&state),
             &w,
             state_action_features,
             &state,
-            (0..actions.len()).filter(|i| is_action_possible(&state, &actions[*i])),
             exploration_fraction,
+            rng,
         );
-        let mut features = DVector::from_vec(state_action_features(&state, &actions[action_index]));
+        let mut features = DVector::from_vec(state_action_features(&state, &action));
+        let mut steps = 0;
 
         // Go to the next state until a final state is reached.
         loop {
             // Take the action and determine the next state and the reward.
-            let (maybe_next_state, reward) = next_state(&state, &actions[action_index]);
+            let (maybe_next_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
 
             // Update the state action value approximation q̂(S, A, w):
             //   w ← w + α∙[R + γ∙q̂(S₊₁, A₊₁, w) - q̂(S, A, w)]∙∇q̂(S, A, w).
@@ -117,18 +206,15 @@ where
             let next_state = maybe_next_state.unwrap();
 
             // Determine next action to compute the expected returns.
-            let next_action_index = soft_greedy_action(
-                actions,
+            let next_action = soft_greedy_action(
+                &env.actions(&next_state),
                 &w,
                 state_action_features,
                 &next_state,
-                (0..actions.len()).filter(|i| is_action_possible(&next_state, &actions[*i])),
                 exploration_fraction,
+                rng,
             );
-            let next_features = DVector::from_vec(state_action_features(
-                &next_state,
-                &actions[next_action_index],
-            ));
+            let next_features = DVector::from_vec(state_action_features(&next_state, &next_action));
 
             // Compute expected returns.
             let expected_returns = reward + discount * w.dot(&next_features);
@@ -136,81 +222,454 @@ where
             // Update the approximation weights.
             w = w + alpha * (expected_returns - prev_action_value) * features;
 
+            if is_truncated(env, steps) {
+                break;
+            }
+            state = next_state;
+            features = next_features;
+            action = next_action;
+        }
+    }
+
+    w
+}
+
+// Same as find_action_values_episodic_semi_gradient_sarsa(), but with sparse features, so that
+// each step only touches the weights of the active features. `feature_count` is the total number
+// of features (e.g. TilingSet::tile_count()).
+pub fn find_action_values_episodic_semi_gradient_sarsa_sparse<S, A, E, R, StateActionFeatures>(
+    env: &E,
+    feature_count: usize,
+    state_action_features: &StateActionFeatures,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: usize,
+    rng: &mut R,
+) -> DVector<f64>
+where
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> SparseFeatures,
+{
+    let mut w = DVector::repeat(feature_count, 0.0);
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut action = soft_greedy_action_by_value(
+            &env.actions(&state),
+            |a| state_action_features(&state, a).dot(&w),
+            exploration_fraction,
+            rng,
+        );
+        let mut features = state_action_features(&state, &action);
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            let (maybe_next_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+            let action_value = features.dot(&w);
+
+            // If this is a final state, q̂(S₊₁, A₊₁, w) = 0.
+            let next_state = match maybe_next_state {
+                Some(next_state) => next_state,
+                None => {
+                    features.add_scaled_to(&mut w, alpha * (reward - action_value));
+                    break;
+                }
+            };
+
+            let next_action = soft_greedy_action_by_value(
+                &env.actions(&next_state),
+                |a| state_action_features(&next_state, a).dot(&w),
+                exploration_fraction,
+                rng,
+            );
+            let next_features = state_action_features(&next_state, &next_action);
+            let expected_returns = reward + discount * next_features.dot(&w);
+            features.add_scaled_to(&mut w, alpha * (expected_returns - action_value));
+
+            if is_truncated(env, steps) {
+                break;
+            }
+            state = next_state;
+            features = next_features;
+            action = next_action;
+        }
+    }
+
+    w
+}
+
+// Semi-gradient TD(λ) for action values (SARSA(λ) with accumulating traces): learns the weights
+// following ε-greedy policy, with the eligibility trace vector z accumulating the gradients:
+//   z ← γ∙λ∙z + ∇q̂(S, A, w),
+//   δ = R + γ∙q̂(S₊₁, A₊₁, w) - q̂(S, A, w),
+//   w ← w + α∙δ∙z.
+pub fn find_action_values_semi_gradient_td_lambda<S, A, E, R, StateActionFeatures>(
+    env: &E,
+    state_action_features: &StateActionFeatures,
+    lambda: f64,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: usize,
+    rng: &mut R,
+) -> DVector<f64>
+where
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let mut w = DVector::repeat(feature_count(env, state_action_features, rng), 0.0);
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut action = soft_greedy_action(
+            &env.actions(&state),
+            &w,
+            state_action_features,
+            &state,
+            exploration_fraction,
+            rng,
+        );
+        let mut features = DVector::from_vec(state_action_features(&state, &action));
+        let mut z = DVector::repeat(w.len(), 0.0);
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            let (maybe_next_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+
+            // For linear approximation, ∇q̂(S, A, w) = x(S, A).
+            z = discount * lambda * z + &features;
+            let action_value = w.dot(&features);
+
+            // If this is a final state, q̂(S₊₁, A₊₁, w) = 0.
+            let next_state = match maybe_next_state {
+                Some(next_state) => next_state,
+                None => {
+                    w += alpha * (reward - action_value) * z;
+                    break;
+                }
+            };
+
+            let next_action = soft_greedy_action(
+                &env.actions(&next_state),
+                &w,
+                state_action_features,
+                &next_state,
+                exploration_fraction,
+                rng,
+            );
+            let next_features = DVector::from_vec(state_action_features(&next_state, &next_action));
+            let td_error = reward + discount * w.dot(&next_features) - action_value;
+            w += alpha * td_error * &z;
+
+            if is_truncated(env, steps) {
+                break;
+            }
             state = next_state;
             features = next_features;
-            action_index = next_action_index;
+            action = next_action;
+        }
+    }
+
+    w
+}
+
+// True Online SARSA(λ): same as semi-gradient TD(λ) above, but with dutch traces and a correction
+// that makes the updates match the online λ-return algorithm exactly:
+//   z ← γ∙λ∙z + (1 - α∙γ∙λ∙z∙x)∙x,
+//   w ← w + α∙(δ + Q - Q_old)∙z - α∙(Q - Q_old)∙x,
+// where x = x(S, A), Q = w∙x and Q_old = w∙x computed with the weights before the previous step.
+pub fn find_action_values_true_online_sarsa_lambda<S, A, E, R, StateActionFeatures>(
+    env: &E,
+    state_action_features: &StateActionFeatures,
+    lambda: f64,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: usize,
+    rng: &mut R,
+) -> DVector<f64>
+where
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let mut w = DVector::repeat(feature_count(env, state_action_features, rng), 0.0);
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut action = soft_greedy_action(
+            &env.actions(&state),
+            &w,
+            state_action_features,
+            &state,
+            exploration_fraction,
+            rng,
+        );
+        let mut features = DVector::from_vec(state_action_features(&state, &action));
+        let mut z = DVector::repeat(w.len(), 0.0);
+        let mut old_action_value = 0.0;
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            let (maybe_next_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+
+            // Determine the next action. For a final state, the features are all 0.
+            let next = maybe_next_state.map(|next_state| {
+                let next_action = soft_greedy_action(
+                    &env.actions(&next_state),
+                    &w,
+                    state_action_features,
+                    &next_state,
+                    exploration_fraction,
+                    rng,
+                );
+                let next_features =
+                    DVector::from_vec(state_action_features(&next_state, &next_action));
+                (next_state, next_action, next_features)
+            });
+
+            let action_value = w.dot(&features);
+            let next_action_value = next
+                .as_ref()
+                .map_or(0.0, |(_, _, next_features)| w.dot(next_features));
+            let td_error = reward + discount * next_action_value - action_value;
+
+            z = discount * lambda * &z
+                + (1.0 - alpha * discount * lambda * z.dot(&features)) * &features;
+            w += alpha * (td_error + action_value - old_action_value) * &z
+                - alpha * (action_value - old_action_value) * &features;
+            old_action_value = next_action_value;
+
+            match next {
+                Some((next_state, next_action, next_features)) if !is_truncated(env, steps) => {
+                    state = next_state;
+                    action = next_action;
+                    features = next_features;
+                }
+                _ => break,
+            }
         }
     }
 
     w
 }
 
+// Differential semi-gradient TD(0) for continuing tasks: learns the weights of the differential
+// state values of the policy, together with the average reward R̄:
+//   δ = R - R̄ + v̂(S₊₁, w) - v̂(S, w),
+//   R̄ ← R̄ + β∙δ,
+//   w ← w + α∙δ∙∇v̂(S, w).
+// Runs for the given number of steps, starting a new episode only if a final state is reached.
+// Returns the weights and the average reward.
+pub fn evaluate_policy_differential_semi_gradient_td<S, A, E, R, StateFeatures, Policy>(
+    env: &E,
+    state_features: &StateFeatures,
+    policy: &Policy,
+    beta: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> (DVector<f64>, f64)
+where
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateFeatures: Fn(&S) -> Vec<f64>,
+    Policy: Fn(&S, &mut R) -> A,
+{
+    let mut state = env.reset(rng);
+    let mut features = DVector::from_vec(state_features(&state));
+    let mut w = DVector::repeat(features.len(), 0.0);
+    let mut average_reward = 0.0;
+
+    for _ in 0..steps {
+        let action = policy(&state, rng);
+        let (maybe_next_state, reward) = env.step(&state, &action, rng);
+
+        // If the final state is reached, v̂(S₊₁, w) = 0 and the next state is taken from a new
+        // episode.
+        let (next_state, next_value_factor) = match maybe_next_state {
+            Some(next_state) => (next_state, 1.0),
+            None => (env.reset(rng), 0.0),
+        };
+        let next_features = DVector::from_vec(state_features(&next_state));
+
+        // For linear approximation, ∇v̂(S, w) = x(S).
+        let td_error =
+            reward - average_reward + next_value_factor * w.dot(&next_features) - w.dot(&features);
+        average_reward += beta * td_error;
+        w += alpha * td_error * &features;
+
+        state = next_state;
+        features = next_features;
+    }
+
+    (w, average_reward)
+}
+
+// Differential semi-gradient SARSA for continuing tasks: learns the weights of the differential
+// action values following ε-greedy policy, together with the average reward R̄:
+//   δ = R - R̄ + q̂(S₊₁, A₊₁, w) - q̂(S, A, w),
+//   R̄ ← R̄ + β∙δ,
+//   w ← w + α∙δ∙∇q̂(S, A, w).
+// Runs for the given number of steps, starting a new episode only if a final state is reached.
+// Returns the weights and the average reward.
+pub fn find_action_values_differential_semi_gradient_sarsa<S, A, E, R, StateActionFeatures>(
+    env: &E,
+    state_action_features: &StateActionFeatures,
+    beta: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> (DVector<f64>, f64)
+where
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let mut w = DVector::repeat(feature_count(env, state_action_features, rng), 0.0);
+    let mut average_reward = 0.0;
+
+    let mut state = env.reset(rng);
+    let mut action = soft_greedy_action(
+        &env.actions(&state),
+        &w,
+        state_action_features,
+        &state,
+        exploration_fraction,
+        rng,
+    );
+    let mut features = DVector::from_vec(state_action_features(&state, &action));
+
+    for _ in 0..steps {
+        let (maybe_next_state, reward) = env.step(&state, &action, rng);
+
+        // If the final state is reached, q̂(S₊₁, A₊₁, w) = 0 and the next action is taken from a
+        // new episode.
+        let (next_state, next_value_factor) = match maybe_next_state {
+            Some(next_state) => (next_state, 1.0),
+            None => (env.reset(rng), 0.0),
+        };
+        let next_action = soft_greedy_action(
+            &env.actions(&next_state),
+            &w,
+            state_action_features,
+            &next_state,
+            exploration_fraction,
+            rng,
+        );
+        let next_features = DVector::from_vec(state_action_features(&next_state, &next_action));
+
+        // For linear approximation, ∇q̂(S, A, w) = x(S, A).
+        let td_error =
+            reward - average_reward + next_value_factor * w.dot(&next_features) - w.dot(&features);
+        average_reward += beta * td_error;
+        w += alpha * td_error * &features;
+
+        state = next_state;
+        action = next_action;
+        features = next_features;
+    }
+
+    (w, average_reward)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use rand::rngs::StdRng;
+    use rand::RngCore;
+
     #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
     enum RandomWalkAction {
         Left,
         Right,
     }
 
-    #[test]
-    fn episodic_semi_gradient_sarsa_random_walk_test() {
-        use RandomWalkAction as A;
+    const STATE_COUNT: usize = 100; // States 0-99.
 
-        let state_count = 100; // States 0-99.
-
-        // Create 3 tilings for the state space.
-        let tiling = tile::TilingSet::from_dimensions(
-            &vec![tile::ContinuousDimension::new(0.0, 100.0, 10)],
-            &vec![tile::Bounds::new(0, 2)],
-            5,
-        );
-        let state_action_features = |s: &usize, a: &A| {
-            let mut v = vec![0.0; tiling.tile_count()];
-            let tiles = tiling.get_tiles(&[*s as f64], &[*a as i32]);
-            tiles.iter().for_each(|i| v[*i] = 1.0);
-            v
-        };
+    // Walk over states 0-99 with random start, reward -1 per step, and the episode ends when
+    // stepping right from the last state.
+    fn random_walk_env() -> impl Environment<State = usize, Action = RandomWalkAction> {
+        use RandomWalkAction as A;
 
-        let start_state = || rand::random::<usize>() % state_count;
-        let is_action_possible = |s: &usize, a: &A| match a {
-            A::Left => *s > 0,
-            A::Right => true,
+        let start_state = |rng: &mut dyn RngCore| rng.gen_range(0..STATE_COUNT);
+        let actions = |s: &usize| {
+            if *s > 0 {
+                vec![A::Left, A::Right]
+            } else {
+                vec![A::Right]
+            }
         };
-        let next_state = |s: &usize, a: &A| match a {
+        let next_state = |s: &usize, a: &A, _rng: &mut dyn RngCore| match a {
             A::Left => {
                 assert!(*s > 0);
                 (Some(*s - 1), -1.0)
             }
             A::Right => {
-                if *s >= (state_count - 1) {
+                if *s >= (STATE_COUNT - 1) {
                     (None, 0.0)
                 } else {
                     (Some(*s + 1), -1.0)
                 }
             }
         };
+        ClosureEnvironment::new(start_state, actions, next_state)
+    }
 
-        let discount = 1.0;
-        let exploration_fraction = 0.2;
-        let alpha = 0.1;
-        let iterations = 500;
-        let w = find_action_values_episodic_semi_gradient_sarsa(
-            &vec![A::Left, A::Right],
-            &start_state,
-            &state_action_features,
-            &is_action_possible,
-            &next_state,
-            discount,
-            exploration_fraction,
-            alpha,
-            iterations,
-        );
+    // Create 5 tilings for the state space.
+    fn random_walk_tiling() -> tile::TilingSet {
+        tile::TilingSet::from_dimensions(
+            &vec![tile::ContinuousDimension::new(0.0, 100.0, 10)],
+            &vec![tile::Bounds::new(0, 2)],
+            5,
+        )
+    }
+
+    fn random_walk_features(
+        tiling: &tile::TilingSet,
+    ) -> impl Fn(&usize, &RandomWalkAction) -> Vec<f64> + '_ {
+        move |s: &usize, a: &RandomWalkAction| {
+            let mut v = vec![0.0; tiling.tile_count()];
+            let tiles = tiling.get_tiles(&[*s as f64], &[*a as i32]);
+            tiles.iter().for_each(|i| v[*i] = 1.0);
+            v
+        }
+    }
 
-        for s in 0..100 {
-            let features = state_action_features(&s, &A::Right);
+    fn random_walk_sparse_features(
+        tiling: &tile::TilingSet,
+    ) -> impl Fn(&usize, &RandomWalkAction) -> SparseFeatures + '_ {
+        move |s: &usize, a: &RandomWalkAction| {
+            SparseFeatures::binary(tiling.get_tiles(&[*s as f64], &[*a as i32]))
+        }
+    }
+
+    fn check_random_walk_values<StateActionFeatures>(
+        w: &DVector<f64>,
+        state_action_features: &StateActionFeatures,
+    ) where
+        StateActionFeatures: Fn(&usize, &RandomWalkAction) -> Vec<f64>,
+    {
+        for s in 0..STATE_COUNT {
+            let features = state_action_features(&s, &RandomWalkAction::Right);
             let value = w.dot(&DVector::from_vec(features));
             let expected_value = (s as f64) - 100.0;
             let delta = value - expected_value;
@@ -225,4 +684,144 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn episodic_semi_gradient_sarsa_random_walk_test() {
+        let tiling = random_walk_tiling();
+        let state_action_features = random_walk_features(&tiling);
+
+        let discount = 1.0;
+        let exploration_fraction = 0.2;
+        let alpha = 0.1;
+        let iterations = 500;
+        let w = find_action_values_episodic_semi_gradient_sarsa(
+            &random_walk_env(),
+            &state_action_features,
+            discount,
+            exploration_fraction,
+            alpha,
+            iterations,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        check_random_walk_values(&w, &state_action_features);
+    }
+
+    #[test]
+    fn semi_gradient_td_lambda_random_walk_test() {
+        let tiling = random_walk_tiling();
+        let state_action_features = random_walk_features(&tiling);
+
+        let w = find_action_values_semi_gradient_td_lambda(
+            &random_walk_env(),
+            &state_action_features,
+            0.9,
+            1.0,
+            0.2,
+            0.02,
+            500,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        check_random_walk_values(&w, &state_action_features);
+    }
+
+    #[test]
+    fn true_online_sarsa_lambda_random_walk_test() {
+        let tiling = random_walk_tiling();
+        let state_action_features = random_walk_features(&tiling);
+
+        let w = find_action_values_true_online_sarsa_lambda(
+            &random_walk_env(),
+            &state_action_features,
+            0.9,
+            1.0,
+            0.2,
+            0.02,
+            500,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        check_random_walk_values(&w, &state_action_features);
+    }
+
+    #[test]
+    fn sparse_features_test() {
+        let w = DVector::from_vec(vec![1.0, 2.0, 3.0, 4.0]);
+        let binary = SparseFeatures::binary(vec![1, 3]);
+        assert_eq!(binary.dot(&w), 6.0);
+        assert_eq!(binary.to_dense(4), vec![0.0, 1.0, 0.0, 1.0]);
+
+        let weighted = SparseFeatures::with_values(vec![0, 2], vec![0.5, -1.0]);
+        assert_eq!(weighted.dot(&w), -2.5);
+        let mut w = w;
+        weighted.add_scaled_to(&mut w, 2.0);
+        assert_eq!(w, DVector::from_vec(vec![2.0, 2.0, 1.0, 4.0]));
+    }
+
+    #[test]
+    fn episodic_semi_gradient_sarsa_sparse_random_walk_test() {
+        let tiling = random_walk_tiling();
+        let state_action_features = random_walk_features(&tiling);
+        let sparse_state_action_features = random_walk_sparse_features(&tiling);
+
+        // The dense version draws a start state and an action to count the features, so do the
+        // same to get the same random sequence.
+        let env = random_walk_env();
+        let mut rng = StdRng::seed_from_u64(0);
+        let start_state = env.reset(&mut rng);
+        env.random_action(&start_state, &mut rng);
+
+        let w = find_action_values_episodic_semi_gradient_sarsa_sparse(
+            &env,
+            tiling.tile_count(),
+            &sparse_state_action_features,
+            1.0,
+            0.2,
+            0.1,
+            500,
+            &mut rng,
+        );
+        check_random_walk_values(&w, &state_action_features);
+
+        // The sparse features are the same as the dense ones, so the learned weights must match
+        // the ones learned with the dense features.
+        let dense_w = find_action_values_episodic_semi_gradient_sarsa(
+            &random_walk_env(),
+            &state_action_features,
+            1.0,
+            0.2,
+            0.1,
+            500,
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert!((w - dense_w).amax() < 1e-9);
+    }
+
+    #[test]
+    fn episodic_semi_gradient_sarsa_sparse_many_features_test() {
+        // 100 tilings with 1000 tiles per action each, i.e. 200000 features, of which only 100
+        // are active at a time.
+        let tiling = tile::TilingSet::from_dimensions(
+            &vec![tile::ContinuousDimension::new(0.0, 100.0, 1000)],
+            &vec![tile::Bounds::new(0, 2)],
+            100,
+        );
+        assert_eq!(tiling.tile_count(), 200000);
+        let state_action_features = random_walk_sparse_features(&tiling);
+
+        let w = find_action_values_episodic_semi_gradient_sarsa_sparse(
+            &random_walk_env(),
+            tiling.tile_count(),
+            &state_action_features,
+            1.0,
+            0.2,
+            0.005,
+            200,
+            &mut StdRng::seed_from_u64(0),
+        );
+        check_random_walk_values(&w, &|s: &usize, a: &RandomWalkAction| {
+            state_action_features(s, a).to_dense(tiling.tile_count())
+        });
+    }
 }
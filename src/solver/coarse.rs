@@ -0,0 +1,88 @@
+use crate::solver::approximate::SparseFeatures;
+use crate::solver::features::*;
+use crate::solver::tile::Bounds;
+
+// Coarse coding from section 9.5.3: binary features for overlapping circles (spheres) in the state
+// space normalized into [0, 1] in each dimension. A feature is 1 if the state is within the
+// radius from the circle's center. The centers form a regular grid, including the bounds.
+pub struct CoarseCoding {
+    bounds: Vec<Bounds<f64>>,
+    centers: Vec<Vec<f64>>,
+    radius: f64,
+}
+
+impl CoarseCoding {
+    // Creates `centers_per_dimension` centers in each dimension, with the radius relative to the
+    // normalized range.
+    pub fn new(bounds: Vec<Bounds<f64>>, centers_per_dimension: usize, radius: f64) -> Self {
+        let centers = grid_centers(bounds.len(), centers_per_dimension);
+        CoarseCoding {
+            bounds,
+            centers,
+            radius,
+        }
+    }
+
+    // Returns the indices of the circles that contain the point.
+    fn active_features(&self, point: &[f64]) -> Vec<usize> {
+        let s = normalize(&self.bounds, point);
+        self.centers
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| {
+                let distance2: f64 = s.iter().zip(*c).map(|(x, c)| (x - c) * (x - c)).sum();
+                distance2 <= self.radius * self.radius
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+impl FeatureExtractor for CoarseCoding {
+    fn feature_count(&self) -> usize {
+        self.centers.len()
+    }
+
+    fn features(&self, point: &[f64]) -> Vec<f64> {
+        let mut features = vec![0.0; self.centers.len()];
+        for i in self.active_features(point) {
+            features[i] = 1.0;
+        }
+        features
+    }
+
+    fn sparse_features(&self, point: &[f64]) -> SparseFeatures {
+        SparseFeatures::binary(self.active_features(point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coarse_coding_test() {
+        // Centers at 0, 0.25, 0.5, 0.75 and 1 in each dimension.
+        let coding = CoarseCoding::new(vec![Bounds::new(0.0, 4.0), Bounds::new(0.0, 4.0)], 5, 0.3);
+        assert_eq!(coding.feature_count(), 25);
+
+        // The point (0.5, 0.5) is within the circles around (0.5, 0.5) and its 4 neighbours,
+        // but not the diagonal ones.
+        assert_eq!(
+            coding.sparse_features(&[2.0, 2.0]).indices(),
+            &[7, 11, 12, 13, 17]
+        );
+        let features = coding.features(&[2.0, 2.0]);
+        assert_eq!(features.iter().sum::<f64>(), 5.0);
+        assert_eq!(features[12], 1.0);
+        assert_eq!(features[6], 0.0);
+
+        // The points close to each other share more features than the points far apart.
+        let shared = |a: &[f64], b: &[f64]| {
+            let (x, y) = (coding.features(a), coding.features(b));
+            x.iter().zip(&y).map(|(x, y)| x * y).sum::<f64>()
+        };
+        assert!(shared(&[2.0, 2.0], &[2.3, 2.0]) > shared(&[2.0, 2.0], &[3.0, 2.0]));
+        assert_eq!(shared(&[0.0, 0.0], &[4.0, 4.0]), 0.0);
+    }
+}
@@ -0,0 +1,570 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::Instant;
+
+use nalgebra::DVector;
+
+use crate::solver::explicit::*;
+use crate::solver::linear::*;
+use crate::solver::*;
+
+// Compiled form of Env, where states and actions are interned to indices and transitions are
+// stored in flat (CSR-style) arrays.
+//
+// Every state has a range of entries, one entry per available action. Every entry refers to an
+// action result, which can be shared between entries, plus an extra reward that is added to
+// every destination of the result (e.g. the cost of the action itself). Every action result has
+// a range of destinations.
+//
+// State values and policies are stored as vectors indexed by state (resp. entry) indices.
+// Values of final states (ones without entries) are always 0.
+pub struct DenseEnv<S, A> {
+    states: Vec<S>,
+    state_indices: HashMap<S, usize>,
+    actions: Vec<A>,
+
+    // Entries of state i are state_starts[i]..state_starts[i + 1].
+    state_starts: Vec<usize>,
+    entry_actions: Vec<usize>,
+    entry_results: Vec<usize>,
+    entry_rewards: Vec<f64>,
+
+    // Destinations of result k are result_starts[k]..result_starts[k + 1].
+    result_starts: Vec<usize>,
+    dest_states: Vec<usize>,
+    dest_probabilities: Vec<f64>,
+    dest_rewards: Vec<f64>,
+}
+
+// Incrementally builds a DenseEnv.
+pub struct DenseEnvBuilder<S, A> {
+    states: Vec<S>,
+    state_indices: HashMap<S, usize>,
+    actions: Vec<A>,
+    action_indices: HashMap<A, usize>,
+    // (action, result, reward) for each state.
+    state_entries: Vec<Vec<(usize, usize, f64)>>,
+    result_starts: Vec<usize>,
+    dest_states: Vec<usize>,
+    dest_probabilities: Vec<f64>,
+    dest_rewards: Vec<f64>,
+}
+
+impl<S, A> DenseEnvBuilder<S, A>
+where
+    S: Copy + Eq + Hash,
+    A: Copy + Eq + Hash,
+{
+    pub fn new() -> Self {
+        DenseEnvBuilder {
+            states: Vec::new(),
+            state_indices: HashMap::new(),
+            actions: Vec::new(),
+            action_indices: HashMap::new(),
+            state_entries: Vec::new(),
+            result_starts: vec![0],
+            dest_states: Vec::new(),
+            dest_probabilities: Vec::new(),
+            dest_rewards: Vec::new(),
+        }
+    }
+
+    fn state_index(&mut self, state: S) -> usize {
+        let states = &mut self.states;
+        let state_entries = &mut self.state_entries;
+        *self.state_indices.entry(state).or_insert_with(|| {
+            states.push(state);
+            state_entries.push(Vec::new());
+            states.len() - 1
+        })
+    }
+
+    fn action_index(&mut self, action: A) -> usize {
+        let actions = &mut self.actions;
+        *self.action_indices.entry(action).or_insert_with(|| {
+            actions.push(action);
+            actions.len() - 1
+        })
+    }
+
+    // Adds a state. States without actions are final.
+    // States are also added implicitly when actions are added from or lead to them.
+    pub fn add_state(&mut self, state: S) {
+        self.state_index(state);
+    }
+
+    // Adds an action result with the given (destination state, probability, reward) triples.
+    // Returns its ID, which can be passed to add_action() any number of times.
+    pub fn add_result<I: IntoIterator<Item = (S, f64, f64)>>(&mut self, dests: I) -> usize {
+        for (state, probability, reward) in dests {
+            let state_index = self.state_index(state);
+            self.dest_states.push(state_index);
+            self.dest_probabilities.push(probability);
+            self.dest_rewards.push(reward);
+        }
+        self.result_starts.push(self.dest_states.len());
+        self.result_starts.len() - 2
+    }
+
+    // Makes the action available in the state. Taking it yields the result with given ID,
+    // with `reward` added to the reward of every destination.
+    pub fn add_action(&mut self, state: S, action: A, result: usize, reward: f64) {
+        assert!(result + 1 < self.result_starts.len());
+        let state_index = self.state_index(state);
+        let action_index = self.action_index(action);
+        self.state_entries[state_index].push((action_index, result, reward));
+    }
+
+    pub fn build(self) -> DenseEnv<S, A> {
+        let mut state_starts = Vec::with_capacity(self.states.len() + 1);
+        let mut entry_actions = Vec::new();
+        let mut entry_results = Vec::new();
+        let mut entry_rewards = Vec::new();
+
+        state_starts.push(0);
+        for entries in self.state_entries {
+            for (action, result, reward) in entries {
+                entry_actions.push(action);
+                entry_results.push(result);
+                entry_rewards.push(reward);
+            }
+            state_starts.push(entry_actions.len());
+        }
+
+        DenseEnv {
+            states: self.states,
+            state_indices: self.state_indices,
+            actions: self.actions,
+            state_starts,
+            entry_actions,
+            entry_results,
+            entry_rewards,
+            result_starts: self.result_starts,
+            dest_states: self.dest_states,
+            dest_probabilities: self.dest_probabilities,
+            dest_rewards: self.dest_rewards,
+        }
+    }
+}
+
+impl<S, A> DenseEnv<S, A>
+where
+    S: Copy + Eq + Hash + Debug,
+    A: Copy + Eq + Hash,
+{
+    // Compiles the environment. Every action result is stored separately; use DenseEnvBuilder
+    // directly to share results between state-action pairs.
+    pub fn from_env(env: &Env<S, A>) -> Self {
+        let mut builder = DenseEnvBuilder::new();
+        for (state, state_actions) in env.states.iter() {
+            builder.add_state(*state);
+            for (action, action_result) in state_actions.actions.iter() {
+                let result = builder.add_result(
+                    action_result
+                        .dest_states
+                        .iter()
+                        .map(|(s, dest)| (*s, dest.probability, dest.reward)),
+                );
+                builder.add_action(*state, *action, result, 0.0);
+            }
+        }
+        builder.build()
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    // Returns the number of stored action results. Shared results are counted once.
+    pub fn result_count(&self) -> usize {
+        self.result_starts.len() - 1
+    }
+
+    // Returns the number of stored destinations over all action results.
+    pub fn destination_count(&self) -> usize {
+        self.dest_states.len()
+    }
+
+    pub fn state(&self, index: usize) -> S {
+        self.states[index]
+    }
+
+    pub fn state_index(&self, state: &S) -> Option<usize> {
+        self.state_indices.get(state).copied()
+    }
+
+    // Returns the range of entries (available actions) of the state.
+    fn entries(&self, state: usize) -> std::ops::Range<usize> {
+        self.state_starts[state]..self.state_starts[state + 1]
+    }
+
+    fn is_final(&self, state: usize) -> bool {
+        self.entries(state).is_empty()
+    }
+
+    fn non_final_state_count(&self) -> usize {
+        (0..self.states.len())
+            .filter(|s| !self.is_final(*s))
+            .count()
+    }
+
+    // Returns the action value of the entry given the state values.
+    fn action_value(&self, entry: usize, state_values: &[f64], discount: f64) -> f64 {
+        let result = self.entry_results[entry];
+        let extra_reward = self.entry_rewards[entry];
+        (self.result_starts[result]..self.result_starts[result + 1])
+            .map(|d| {
+                self.dest_probabilities[d]
+                    * (extra_reward
+                        + self.dest_rewards[d]
+                        + discount * state_values[self.dest_states[d]])
+            })
+            .sum()
+    }
+
+    fn max_action_value(&self, state: usize, state_values: &[f64], discount: f64) -> f64 {
+        self.entries(state)
+            .map(|e| self.action_value(e, state_values, discount))
+            .fold(f64::NEG_INFINITY, |a, b| a.max(b))
+    }
+
+    // Converts state values to a vector indexed by state. Missing states get value 0.
+    pub fn state_values_from_map(&self, state_values: &HashMap<S, f64>) -> Vec<f64> {
+        self.states
+            .iter()
+            .map(|s| *state_values.get(s).unwrap_or(&0.0))
+            .collect()
+    }
+
+    // Converts state values back to a map. Final states are omitted, like in the Env solvers.
+    pub fn state_values_to_map(&self, state_values: &[f64]) -> HashMap<S, f64> {
+        (0..self.states.len())
+            .filter(|i| !self.is_final(*i))
+            .map(|i| (self.states[i], state_values[i]))
+            .collect()
+    }
+
+    // Converts the policy to a vector of probabilities indexed by entry.
+    pub fn policy_from_explicit(&self, policy: &Policy<S, A>) -> Vec<f64> {
+        let mut probabilities = vec![0.0; self.entry_actions.len()];
+        for state in 0..self.states.len() {
+            if let Some(policy_state) = policy.states.get(&self.states[state]) {
+                for entry in self.entries(state) {
+                    let action = &self.actions[self.entry_actions[entry]];
+                    probabilities[entry] = *policy_state.actions.get(action).unwrap_or(&0.0);
+                }
+            }
+        }
+        probabilities
+    }
+
+    // Converts the policy back to the explicit form. Actions with zero probability are omitted.
+    pub fn policy_to_explicit(&self, policy: &[f64]) -> Policy<S, A> {
+        Policy {
+            states: (0..self.states.len())
+                .filter(|i| !self.is_final(*i))
+                .map(|i| {
+                    let policy_state = PolicyState {
+                        actions: self
+                            .entries(i)
+                            .filter(|e| policy[*e] > 0.0)
+                            .map(|e| (self.actions[self.entry_actions[e]], policy[e]))
+                            .collect(),
+                    };
+                    (self.states[i], policy_state)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn make_uniform_policy(&self) -> Vec<f64> {
+        let mut policy = vec![0.0; self.entry_actions.len()];
+        for state in 0..self.states.len() {
+            let entries = self.entries(state);
+            let probability = 1.0 / entries.len() as f64;
+            for entry in entries {
+                policy[entry] = probability;
+            }
+        }
+        policy
+    }
+
+    // Same as explicit::make_greedy_policy().
+    pub fn make_greedy_policy(&self, state_values: &[f64], discount: f64) -> Vec<f64> {
+        let mut policy = vec![0.0; self.entry_actions.len()];
+        for state in 0..self.states.len() {
+            if self.is_final(state) {
+                continue;
+            }
+
+            let max_action_value = self.max_action_value(state, state_values, discount);
+
+            // Don't require exact equality to forgive rounding errors.
+            let greedy_entries: Vec<usize> = self
+                .entries(state)
+                .filter(|e| {
+                    (self.action_value(*e, state_values, discount) - max_action_value).abs() < 1e-6
+                })
+                .collect();
+
+            let probability = 1.0 / greedy_entries.len() as f64;
+            for entry in greedy_entries {
+                policy[entry] = probability;
+            }
+        }
+        policy
+    }
+
+    // Same as explicit::is_greedy_policy().
+    pub fn is_greedy_policy(&self, policy: &[f64], state_values: &[f64], discount: f64) -> bool {
+        (0..self.states.len()).all(|state| {
+            let max_action_value = self.max_action_value(state, state_values, discount);
+            self.entries(state).filter(|e| policy[*e] > 0.0).all(|e| {
+                (self.action_value(e, state_values, discount) - max_action_value).abs() < 1e-6
+            })
+        })
+    }
+
+    // Same as explicit::evaluate_policy_iteration().
+    pub fn evaluate_policy_iteration(
+        &self,
+        policy: &[f64],
+        prev_state_values: &[f64],
+        discount: f64,
+    ) -> (Vec<f64>, f64) {
+        let mut new_state_values = vec![0.0; self.states.len()];
+        let mut max_delta: f64 = 0.0;
+
+        for state in 0..self.states.len() {
+            let state_value: f64 = self
+                .entries(state)
+                .filter(|e| policy[*e] > 0.0)
+                .map(|e| policy[e] * self.action_value(e, prev_state_values, discount))
+                .sum();
+            max_delta = max_delta.max((prev_state_values[state] - state_value).abs());
+            new_state_values[state] = state_value;
+        }
+
+        (new_state_values, max_delta)
+    }
+
+    // Same as explicit::iterate_state_value().
+    pub fn iterate_state_value(&self, prev_state_values: &[f64], discount: f64) -> (Vec<f64>, f64) {
+        let mut new_state_values = vec![0.0; self.states.len()];
+        let mut max_delta: f64 = 0.0;
+
+        for state in 0..self.states.len() {
+            if self.is_final(state) {
+                continue;
+            }
+
+            let best_action_value = self.max_action_value(state, prev_state_values, discount);
+            max_delta = max_delta.max((best_action_value - prev_state_values[state]).abs());
+            new_state_values[state] = best_action_value;
+        }
+
+        (new_state_values, max_delta)
+    }
+
+    // Same as linear::evaluate_policy_exact().
+    pub fn evaluate_policy_exact(&self, policy: &[f64], discount: f64) -> Vec<f64> {
+        let mut rows = Vec::with_capacity(self.states.len());
+        let mut rewards = DVector::zeros(self.states.len());
+        for state in 0..self.states.len() {
+            let mut row = vec![(state, 1.0)];
+            for entry in self.entries(state).filter(|e| policy[*e] > 0.0) {
+                let result = self.entry_results[entry];
+                for d in self.result_starts[result]..self.result_starts[result + 1] {
+                    let probability = policy[entry] * self.dest_probabilities[d];
+                    rewards[state] +=
+                        probability * (self.entry_rewards[entry] + self.dest_rewards[d]);
+                    row.push((self.dest_states[d], -discount * probability));
+                }
+            }
+            rows.push(row);
+        }
+
+        solve_linear_system(&SparseMatrix::from_rows(rows), &rewards)
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    // Same as explicit::policy_iteration().
+    pub fn policy_iteration(
+        &self,
+        initial_policy: Vec<f64>,
+        discount: f64,
+        tolerance: f64,
+        max_sweeps: usize,
+    ) -> (Vec<f64>, Vec<f64>, ConvergenceReport) {
+        let start_time = Instant::now();
+        let mut report = ConvergenceReport::default();
+        let mut policy = initial_policy;
+        let mut state_values = vec![0.0; self.states.len()];
+
+        while report.sweeps < max_sweeps {
+            while report.sweeps < max_sweeps {
+                let (new_state_values, delta) =
+                    self.evaluate_policy_iteration(&policy, &state_values, discount);
+                state_values = new_state_values;
+                report.sweeps += 1;
+                report.backups += self.non_final_state_count();
+                report.deltas.push(delta);
+                if delta < tolerance {
+                    break;
+                }
+            }
+
+            report.policy_stable = self.is_greedy_policy(&policy, &state_values, discount);
+            if report.policy_stable {
+                break;
+            }
+            policy = self.make_greedy_policy(&state_values, discount);
+        }
+
+        report.elapsed = start_time.elapsed();
+        (policy, state_values, report)
+    }
+
+    // Same as explicit::value_iteration().
+    pub fn value_iteration(
+        &self,
+        discount: f64,
+        tolerance: f64,
+        max_sweeps: usize,
+    ) -> (Vec<f64>, Vec<f64>, ConvergenceReport) {
+        let start_time = Instant::now();
+        let mut report = ConvergenceReport::default();
+        let mut prev_state_values = vec![0.0; self.states.len()];
+        let mut state_values = vec![0.0; self.states.len()];
+
+        while report.sweeps < max_sweeps {
+            let (new_state_values, delta) = self.iterate_state_value(&state_values, discount);
+            prev_state_values = std::mem::replace(&mut state_values, new_state_values);
+            report.sweeps += 1;
+            report.backups += self.non_final_state_count();
+            report.deltas.push(delta);
+            if delta < tolerance {
+                break;
+            }
+        }
+
+        let policy = self.make_greedy_policy(&state_values, discount);
+        let prev_policy = self.make_greedy_policy(&prev_state_values, discount);
+        report.policy_stable = self.is_greedy_policy(&prev_policy, &state_values, discount);
+
+        report.elapsed = start_time.elapsed();
+        (policy, state_values, report)
+    }
+
+    // Same as explicit::run_simulation().
+    pub fn run_simulation<R: Rng>(
+        &self,
+        policy: &[f64],
+        start_state: S,
+        max_steps: u32,
+        rng: &mut R,
+    ) -> f64 {
+        let mut state = self
+            .state_index(&start_state)
+            .unwrap_or_else(|| panic!("State {:?} not found", start_state));
+        let mut total_reward = 0.0;
+        for _ in 0..max_steps {
+            if self.is_final(state) {
+                break;
+            }
+
+            // Choose action stochastically.
+            let entries = self.entries(state);
+            let entry = entries.start + choose_random_index(&policy[entries], rng);
+
+            // Choose end state stochastically.
+            let result = self.entry_results[entry];
+            let dests = self.result_starts[result]..self.result_starts[result + 1];
+            let dest = dests.start + choose_random_index(&self.dest_probabilities[dests], rng);
+
+            total_reward += self.entry_rewards[entry] + self.dest_rewards[dest];
+            state = self.dest_states[dest];
+        }
+
+        total_reward
+    }
+}
+
+// Chooses an index at random, with probabilities proportional to the given weights.
+fn choose_random_index<R: Rng>(weights: &[f64], rng: &mut R) -> usize {
+    let total: f64 = weights.iter().sum();
+    let mut remaining = rng.gen::<f64>() * total;
+    for (i, w) in weights.iter().enumerate() {
+        if remaining <= *w {
+            return i;
+        }
+        remaining -= w;
+    }
+
+    // Can only get here due to rounding errors.
+    weights.iter().rposition(|w| *w > 0.0).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::solver::explicit::tests::corridor_env;
+
+    #[test]
+    fn dense_matches_explicit_test() {
+        let env = corridor_env();
+        let dense_env = DenseEnv::from_env(&env);
+
+        let policy = make_uniform_policy(&env);
+        let dense_policy = dense_env.policy_from_explicit(&policy);
+
+        let (expected, _) = evaluate_policy_iteration(&env, &policy, &HashMap::new(), 0.9);
+        let (actual, _) = dense_env.evaluate_policy_iteration(
+            &dense_policy,
+            &vec![0.0; dense_env.state_count()],
+            0.9,
+        );
+        assert_eq!(dense_env.state_values_to_map(&actual), expected);
+
+        let expected = linear::evaluate_policy_exact(&env, &policy, 0.9);
+        let actual =
+            dense_env.state_values_to_map(&dense_env.evaluate_policy_exact(&dense_policy, 0.9));
+        for (state, value) in expected.iter() {
+            assert!((actual[state] - value).abs() < 1e-9);
+        }
+
+        let (expected_policy, expected_values, _) = value_iteration(&env, 1.0, 1e-9, 100);
+        let (actual_policy, actual_values, report) = dense_env.value_iteration(1.0, 1e-9, 100);
+        assert!(report.policy_stable);
+        assert_eq!(
+            dense_env.state_values_to_map(&actual_values),
+            expected_values
+        );
+        assert_eq!(
+            dense_env.policy_to_explicit(&actual_policy).states[&2].actions,
+            expected_policy.states[&2].actions
+        );
+    }
+
+    #[test]
+    fn shared_results_test() {
+        // Two states share the same result, but one of them pays extra for the action.
+        let mut builder = DenseEnvBuilder::new();
+        let result = builder.add_result(vec![(2, 0.5, 1.0), (3, 0.5, 3.0)]);
+        builder.add_action(0, 'a', result, 0.0);
+        builder.add_action(1, 'a', result, -1.0);
+        let dense_env = builder.build();
+
+        assert_eq!(dense_env.state_count(), 4);
+        let state_values = dense_env.state_values_to_map(
+            &dense_env.evaluate_policy_exact(&dense_env.make_uniform_policy(), 1.0),
+        );
+        assert_eq!(state_values.len(), 2);
+        assert!((state_values[&0] - 2.0).abs() < 1e-12);
+        assert!((state_values[&1] - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashSet;
+
+use crate::solver::environment::*;
+use crate::solver::priority_queue::PriorityQueue;
+use crate::solver::td::*;
+use crate::solver::*;
+
+// Transition observed from a state-action pair.
+#[derive(Clone, Debug)]
+struct Transition<S> {
+    next_state: Option<S>,
+    reward: f64,
+    // Time step when the transition was last observed.
+    time: u64,
+}
+
+// Tabular model of a deterministic environment, learned from the observed transitions: for every
+// state-action pair it remembers the last next state and reward.
+pub struct Model<S, A> {
+    // State-action pairs in the order they were first observed, so that sampling only depends on
+    // the random generator.
+    state_actions: Vec<(S, A)>,
+    transitions: HashMap<(S, A), Transition<S>>,
+    states: HashSet<S>,
+    // State-action pairs that lead to each state, in the order they were observed.
+    predecessors: HashMap<S, Vec<(S, A)>>,
+}
+
+impl<S, A> Model<S, A>
+where
+    S: Eq + Hash + Clone,
+    A: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Model {
+            state_actions: Vec::new(),
+            transitions: HashMap::new(),
+            states: HashSet::new(),
+            predecessors: HashMap::new(),
+        }
+    }
+
+    pub fn contains_state(&self, state: &S) -> bool {
+        self.states.contains(state)
+    }
+
+    // Records the transition observed at the given time step.
+    pub fn update(&mut self, state: S, action: A, next_state: Option<S>, reward: f64, time: u64) {
+        let key = (state, action);
+        match self.transitions.get(&key) {
+            None => {
+                self.states.insert(key.0.clone());
+                self.state_actions.push(key.clone());
+            }
+            // The environment has changed, so the pair no longer leads to the old next state.
+            Some(Transition {
+                next_state: Some(old_next_state),
+                ..
+            }) if next_state.as_ref() != Some(old_next_state) => {
+                if let Some(predecessors) = self.predecessors.get_mut(old_next_state) {
+                    predecessors.retain(|p| *p != key);
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(next_state) = &next_state {
+            let predecessors = self.predecessors.entry(next_state.clone()).or_default();
+            if !predecessors.contains(&key) {
+                predecessors.push(key.clone());
+            }
+        }
+
+        self.transitions.insert(
+            key,
+            Transition {
+                next_state,
+                reward,
+                time,
+            },
+        );
+    }
+
+    // Returns the next state and the reward for the state-action pair, if it was observed.
+    pub fn next_state(&self, state: &S, action: &A) -> Option<(Option<S>, f64)> {
+        self.transitions
+            .get(&(state.clone(), action.clone()))
+            .map(|t| (t.next_state.clone(), t.reward))
+    }
+
+    // Returns the observed state-action pairs that lead to the state.
+    pub fn predecessors(&self, state: &S) -> &[(S, A)] {
+        self.predecessors.get(state).map_or(&[], |p| p.as_slice())
+    }
+
+    // Chooses one of the observed state-action pairs with equal probability. Returns the pair,
+    // its next state, reward and the time step when it was last observed.
+    fn sample<R: Rng>(&self, rng: &mut R) -> (&S, &A, &Transition<S>) {
+        let (state, action) = &self.state_actions[rng.gen_range(0..self.state_actions.len())];
+        let transition = &self.transitions[&(state.clone(), action.clone())];
+        (state, action, transition)
+    }
+}
+
+impl<S, A> Default for Model<S, A>
+where
+    S: Eq + Hash + Clone,
+    A: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        Model::new()
+    }
+}
+
+// Action values learned by a Dyna agent, and the total reward collected by each time step.
+pub struct DynaResult<S, A> {
+    pub action_values: ActionValues<S, A>,
+    pub cumulative_rewards: Vec<f64>,
+}
+
+// Returns max Q(S₊₁, ∙), or 0 for final state.
+fn max_action_value<S, A, E>(
+    env: &E,
+    action_values: &ActionValues<S, A>,
+    next_state: &Option<S>,
+) -> f64
+where
+    S: Eq + Hash,
+    A: Eq + Hash,
+    E: Environment<State = S, Action = A>,
+{
+    // Same as max_returns(&all_action_values(..)), but without building the map, as this is
+    // done for every planning step.
+    next_state.as_ref().map_or(0.0, |s| {
+        let state_action_values = action_values.get(s);
+        env.actions(s)
+            .iter()
+            .map(|a| state_action_values.map_or(0.0, |av| *av.get(a).unwrap_or(&0.0)))
+            .fold(f64::NEG_INFINITY, f64::max)
+    })
+}
+
+// Returns the change R + γ∙max Q(S₊₁, ∙) - Q(S, A) that Q-learning would make with α = 1.
+fn q_learning_error<S, A, E>(
+    env: &E,
+    action_values: &ActionValues<S, A>,
+    state: &S,
+    action: &A,
+    next_state: &Option<S>,
+    reward: f64,
+    discount: f64,
+) -> f64
+where
+    S: Eq + Hash,
+    A: Eq + Hash,
+    E: Environment<State = S, Action = A>,
+{
+    let state_action_value = action_values
+        .get(state)
+        .and_then(|av| av.get(action))
+        .unwrap_or(&0.0);
+    reward + discount * max_action_value(env, action_values, next_state) - state_action_value
+}
+
+// Q-learning update of Q(S, A) toward R + γ∙max Q(S₊₁, ∙). For final state, max Q(S₊₁, ∙) = 0.
+fn q_learning_update<S, A, E>(
+    env: &E,
+    action_values: &mut ActionValues<S, A>,
+    state: &S,
+    action: &A,
+    next_state: &Option<S>,
+    reward: f64,
+    discount: f64,
+    alpha: f64,
+) where
+    S: Eq + Hash + Clone,
+    A: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+{
+    let next_state_returns = max_action_value(env, action_values, next_state);
+    let state_action_value = action_values
+        .entry(state.clone())
+        .or_default()
+        .entry(action.clone())
+        .or_default();
+    *state_action_value += alpha * (reward + discount * next_state_returns - *state_action_value);
+}
+
+// Runs the Dyna agent for the given number of time steps (starting a new episode every time a
+// final state is reached). After each real step, the agent makes `planning_steps` Q-learning
+// updates from transitions sampled from the learned model, with the reward increased by
+// κ∙√τ, where κ is `exploration_bonus` and τ is the number of steps since the transition was
+// last tried for real.
+fn run_dyna<S, A, E, R>(
+    env: &E,
+    exploration_bonus: f64,
+    planning_steps: usize,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> DynaResult<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    let mut action_values: ActionValues<S, A> = HashMap::new();
+    let mut model = Model::new();
+    let mut cumulative_rewards = Vec::with_capacity(steps as usize);
+    let mut total_reward = 0.0;
+
+    let mut state = env.reset(rng);
+    let mut episode_steps = 0;
+    for time in 1..=steps {
+        // With exploration bonus, the actions that were never tried from a visited state are
+        // considered in planning too, as leading back to the same state with zero reward.
+        if exploration_bonus > 0.0 && !model.contains_state(&state) {
+            for action in env.actions(&state) {
+                model.update(state.clone(), action, Some(state.clone()), 0.0, 0);
+            }
+        }
+
+        // Take the real step following ε-greedy policy, and learn from it.
+        let action = soft_greedy_action(env, &action_values, &state, exploration_fraction, rng);
+        let (next_state, reward) = env.step(&state, &action, rng);
+        episode_steps += 1;
+        total_reward += reward;
+        cumulative_rewards.push(total_reward);
+
+        q_learning_update(
+            env,
+            &mut action_values,
+            &state,
+            &action,
+            &next_state,
+            reward,
+            discount,
+            alpha,
+        );
+        model.update(state, action, next_state.clone(), reward, time);
+
+        // Plan with the simulated experience.
+        for _ in 0..planning_steps {
+            let (s, a, transition) = model.sample(rng);
+            let bonus = exploration_bonus * ((time - transition.time) as f64).sqrt();
+            q_learning_update(
+                env,
+                &mut action_values,
+                s,
+                a,
+                &transition.next_state,
+                transition.reward + bonus,
+                discount,
+                alpha,
+            );
+        }
+
+        state = match next_state {
+            Some(next_state) if !is_truncated(env, episode_steps) => next_state,
+            _ => {
+                episode_steps = 0;
+                env.reset(rng)
+            }
+        };
+    }
+
+    DynaResult {
+        action_values,
+        cumulative_rewards,
+    }
+}
+
+// Dyna-Q: Q-learning from real experience plus `planning_steps` Q-learning updates from the
+// learned model after each real step.
+pub fn find_action_values_dyna_q<S, A, E, R>(
+    env: &E,
+    planning_steps: usize,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> DynaResult<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    run_dyna(
+        env,
+        0.0,
+        planning_steps,
+        discount,
+        exploration_fraction,
+        alpha,
+        steps,
+        rng,
+    )
+}
+
+// Dyna-Q+: Dyna-Q where the planning updates get the bonus reward κ∙√τ for the transitions not
+// tried for τ steps, encouraging to check whether the environment has changed.
+pub fn find_action_values_dyna_q_plus<S, A, E, R>(
+    env: &E,
+    exploration_bonus: f64,
+    planning_steps: usize,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> DynaResult<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    assert!(exploration_bonus > 0.0);
+    run_dyna(
+        env,
+        exploration_bonus,
+        planning_steps,
+        discount,
+        exploration_fraction,
+        alpha,
+        steps,
+        rng,
+    )
+}
+
+// Prioritized sweeping with a learned deterministic model (Example 8.4). Instead of sampling the
+// planning updates uniformly, the agent keeps a queue of the state-action pairs whose values would
+// change by more than `theta`, with the largest changes first. After a pair is updated, all the
+// pairs predicted to lead to its state are checked and queued, so the changes propagate backward.
+// Each real step is followed by at most `planning_steps` updates from the queue.
+pub fn find_action_values_prioritized_sweeping<S, A, E, R>(
+    env: &E,
+    planning_steps: usize,
+    theta: f64,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> DynaResult<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    let mut action_values: ActionValues<S, A> = HashMap::new();
+    let mut model = Model::new();
+    let mut queue = PriorityQueue::new();
+    let mut cumulative_rewards = Vec::with_capacity(steps as usize);
+    let mut total_reward = 0.0;
+
+    let mut state = env.reset(rng);
+    let mut episode_steps = 0;
+    for time in 1..=steps {
+        let action = soft_greedy_action(env, &action_values, &state, exploration_fraction, rng);
+        let (next_state, reward) = env.step(&state, &action, rng);
+        episode_steps += 1;
+        total_reward += reward;
+        cumulative_rewards.push(total_reward);
+
+        model.update(
+            state.clone(),
+            action.clone(),
+            next_state.clone(),
+            reward,
+            time,
+        );
+        let error = q_learning_error(
+            env,
+            &action_values,
+            &state,
+            &action,
+            &next_state,
+            reward,
+            discount,
+        )
+        .abs();
+        if error > theta {
+            queue.push((state, action), error);
+        }
+
+        // Plan with the simulated experience, most urgent updates first.
+        for _ in 0..planning_steps {
+            let (s, a) = match queue.pop() {
+                Some((key, _)) => key,
+                None => break,
+            };
+            let (s_next, r) = model.next_state(&s, &a).unwrap();
+            q_learning_update(env, &mut action_values, &s, &a, &s_next, r, discount, alpha);
+
+            for (prev_s, prev_a) in model.predecessors(&s) {
+                let (_, prev_r) = model.next_state(prev_s, prev_a).unwrap();
+                let error = q_learning_error(
+                    env,
+                    &action_values,
+                    prev_s,
+                    prev_a,
+                    &Some(s.clone()),
+                    prev_r,
+                    discount,
+                )
+                .abs();
+                if error > theta {
+                    queue.push((prev_s.clone(), prev_a.clone()), error);
+                }
+            }
+        }
+
+        state = match next_state {
+            Some(next_state) if !is_truncated(env, episode_steps) => next_state,
+            _ => {
+                episode_steps = 0;
+                env.reset(rng)
+            }
+        };
+    }
+
+    DynaResult {
+        action_values,
+        cumulative_rewards,
+    }
+}
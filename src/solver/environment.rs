@@ -0,0 +1,138 @@
+use std::marker::PhantomData;
+
+use rand::{Rng, RngCore};
+
+// Sample-based model of an environment, used by the solvers that learn from experience
+// (Monte Carlo, TD and approximate methods).
+pub trait Environment {
+    type State;
+    type Action: Clone;
+
+    // Creates the initial state of a new episode.
+    fn reset<R: Rng>(&self, rng: &mut R) -> Self::State;
+
+    // Returns the actions that can be taken from the given state.
+    // Must not be empty for states that are returned from `reset` or `step`, unless the actions
+    // are continuous and only chosen by a policy that doesn't need the list (such as
+    // policy_gradient::GaussianPolicy).
+    fn actions(&self, state: &Self::State) -> Vec<Self::Action>;
+
+    // Takes the action from the given state.
+    // Returns the next state and the reward. The next state is None if the episode has ended
+    // (i.e. the action led to a final state).
+    fn step<R: Rng>(
+        &self,
+        state: &Self::State,
+        action: &Self::Action,
+        rng: &mut R,
+    ) -> (Option<Self::State>, f64);
+
+    // Maximum number of steps in an episode. Solvers stop the episode after this many steps,
+    // treating the last state as non-final. None if episodes are never truncated.
+    fn max_steps(&self) -> Option<u64> {
+        None
+    }
+
+    // Chooses one of the possible actions from the given state with equal probability.
+    fn random_action<R: Rng>(&self, state: &Self::State, rng: &mut R) -> Self::Action {
+        let actions = self.actions(state);
+        assert!(!actions.is_empty());
+        actions[rng.gen_range(0..actions.len())].clone()
+    }
+}
+
+// Returns true if an episode that has already made `steps` steps must be truncated.
+pub fn is_truncated<E: Environment>(env: &E, steps: u64) -> bool {
+    env.max_steps().is_some_and(|max_steps| steps >= max_steps)
+}
+
+// Adapts a set of closures to the Environment trait.
+// The closures get the random number generator as a trait object, since closures can't be
+// generic over its type.
+pub struct ClosureEnvironment<S, A, StartState, Actions, NextState> {
+    start_state: StartState,
+    actions: Actions,
+    next_state: NextState,
+    max_steps: Option<u64>,
+    marker: PhantomData<fn() -> (S, A)>,
+}
+
+impl<S, A, StartState, Actions, NextState> ClosureEnvironment<S, A, StartState, Actions, NextState>
+where
+    A: Clone,
+    StartState: Fn(&mut dyn RngCore) -> S,
+    Actions: Fn(&S) -> Vec<A>,
+    NextState: Fn(&S, &A, &mut dyn RngCore) -> (Option<S>, f64),
+{
+    pub fn new(start_state: StartState, actions: Actions, next_state: NextState) -> Self {
+        ClosureEnvironment {
+            start_state,
+            actions,
+            next_state,
+            max_steps: None,
+            marker: PhantomData,
+        }
+    }
+
+    // Truncates episodes after the given number of steps.
+    pub fn with_max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+}
+
+impl<S, A, StartState, Actions, NextState> Environment
+    for ClosureEnvironment<S, A, StartState, Actions, NextState>
+where
+    A: Clone,
+    StartState: Fn(&mut dyn RngCore) -> S,
+    Actions: Fn(&S) -> Vec<A>,
+    NextState: Fn(&S, &A, &mut dyn RngCore) -> (Option<S>, f64),
+{
+    type State = S;
+    type Action = A;
+
+    fn reset<R: Rng>(&self, rng: &mut R) -> S {
+        (self.start_state)(rng)
+    }
+
+    fn actions(&self, state: &S) -> Vec<A> {
+        (self.actions)(state)
+    }
+
+    fn step<R: Rng>(&self, state: &S, action: &A, rng: &mut R) -> (Option<S>, f64) {
+        (self.next_state)(state, action, rng)
+    }
+
+    fn max_steps(&self) -> Option<u64> {
+        self.max_steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::solver::monte_carlo;
+
+    #[test]
+    fn closure_environment_truncation_test() {
+        // Endless corridor: every step yields -1 and never reaches a final state.
+        let env = ClosureEnvironment::new(
+            |_rng: &mut dyn RngCore| 0,
+            |_s: &i32| vec![1],
+            |s: &i32, a: &i32, _rng: &mut dyn RngCore| (Some(s + a), -1.0),
+        )
+        .with_max_steps(10);
+
+        let returns = monte_carlo::run_simulation(
+            &env,
+            &|_s: &i32, _rng: &mut StdRng| 1,
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert_eq!(returns, -10.0);
+    }
+}
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::time::{Duration, Instant};
 
+use crate::solver::priority_queue::PriorityQueue;
 use crate::solver::*;
 
 #[derive(Debug, Default, Clone)]
@@ -29,6 +31,23 @@ pub struct Env<S: Eq + Hash, A: Eq + Hash> {
     pub states: HashMap<S, StateActions<S, A>>,
 }
 
+// Describes how an iterative solver (policy or value iteration) has converged.
+#[derive(Debug, Default, Clone)]
+pub struct ConvergenceReport {
+    // Number of sweeps over the state space. For policy iteration, this is the total number of
+    // policy evaluation sweeps.
+    pub sweeps: usize,
+    // Maximum change in state values after each sweep.
+    pub deltas: Vec<f64>,
+    // Number of single state backups (sweeps times the number of non-final states, unless the
+    // solver backs up states selectively).
+    pub backups: usize,
+    // True if the last policy improvement didn't change the policy.
+    pub policy_stable: bool,
+    // Wall time spent in the solver.
+    pub elapsed: Duration,
+}
+
 // Returns the action value given the action results and state value function.
 fn get_action_value<S: Eq + Hash>(
     action: &ActionResult<S>,
@@ -44,6 +63,13 @@ fn get_action_value<S: Eq + Hash>(
         .sum()
 }
 
+fn non_final_state_count<S: Eq + Hash, A: Eq + Hash>(env: &Env<S, A>) -> usize {
+    env.states
+        .values()
+        .filter(|state_actions| !state_actions.actions.is_empty())
+        .count()
+}
+
 pub fn deterministic_action<S: Eq + Hash>(dest_state: S, reward: f64) -> ActionResult<S> {
     let mut dest_states = HashMap::new();
     dest_states.insert(
@@ -194,47 +220,945 @@ pub fn iterate_state_value<S: Copy + Eq + Hash, A: Eq + Hash>(
         new_state_values.insert(*state, best_action_value);
 
         let prev_state_value = prev_state_values.get(state).unwrap_or(&0.0);
-        max_delta = max_delta.max(best_action_value - prev_state_value);
+        max_delta = max_delta.max((best_action_value - prev_state_value).abs());
     }
 
     (new_state_values, max_delta)
 }
 
-pub fn run_simulation<S: Copy + Eq + Hash + Debug + Ord, A: Copy + Eq + Hash + Ord>(
+// Checks if the policy is greedy with respect to the given state-value function, i.e. if every
+// action the policy can take is among the best actions. Ties between the best actions are
+// ignored, so a policy that picks any of them is considered greedy.
+pub fn is_greedy_policy<S: Eq + Hash, A: Eq + Hash>(
+    env: &Env<S, A>,
+    policy: &Policy<S, A>,
+    state_values: &HashMap<S, f64>,
+    discount: f64,
+) -> bool {
+    env.states.iter().all(|(state, state_actions)| {
+        if state_actions.actions.is_empty() {
+            return true;
+        }
+
+        let policy_state = match policy.states.get(state) {
+            Some(policy_state) => policy_state,
+            None => return false,
+        };
+
+        let max_action_reward = state_actions
+            .actions
+            .values()
+            .map(|action_result| get_action_value(action_result, state_values, discount))
+            .fold(f64::NEG_INFINITY, |a, b| a.max(b));
+
+        policy_state
+            .actions
+            .iter()
+            .filter(|(_, probability)| **probability > 0.0)
+            .all(|(action, _)| match state_actions.actions.get(action) {
+                // Use the same tolerance as make_greedy_policy().
+                Some(action_result) => {
+                    (get_action_value(action_result, state_values, discount) - max_action_reward)
+                        .abs()
+                        < 1e-6
+                }
+                None => false,
+            })
+    })
+}
+
+// Finds the optimal policy by alternating policy evaluation and greedy policy improvement,
+// starting from the given policy.
+// Policy evaluation stops when the maximum change in state values drops below `tolerance`.
+// The whole process stops when the policy becomes stable or after `max_sweeps` policy
+// evaluation sweeps.
+pub fn policy_iteration<S: Copy + Eq + Hash + Debug, A: Copy + Eq + Hash>(
+    env: &Env<S, A>,
+    initial_policy: Policy<S, A>,
+    discount: f64,
+    tolerance: f64,
+    max_sweeps: usize,
+) -> (Policy<S, A>, HashMap<S, f64>, ConvergenceReport) {
+    let start_time = Instant::now();
+    let mut report = ConvergenceReport::default();
+    let mut policy = initial_policy;
+    let mut state_values = HashMap::new();
+
+    while report.sweeps < max_sweeps {
+        // Evaluate the current policy, starting from the values of the previous one.
+        while report.sweeps < max_sweeps {
+            let (new_state_values, delta) =
+                evaluate_policy_iteration(env, &policy, &state_values, discount);
+            state_values = new_state_values;
+            report.sweeps += 1;
+            report.backups += non_final_state_count(env);
+            report.deltas.push(delta);
+            if delta < tolerance {
+                break;
+            }
+        }
+
+        // Improve the policy. If the current policy is already greedy, we are done.
+        report.policy_stable = is_greedy_policy(env, &policy, &state_values, discount);
+        if report.policy_stable {
+            break;
+        }
+        policy = make_greedy_policy(env, &state_values, discount);
+    }
+
+    report.elapsed = start_time.elapsed();
+    (policy, state_values, report)
+}
+
+// Finds the optimal state-value function by iterating the Bellman optimality backup until the
+// maximum change in state values drops below `tolerance` or after `max_sweeps` sweeps.
+// Returns the greedy policy with respect to the final state values.
+pub fn value_iteration<S: Copy + Eq + Hash, A: Copy + Eq + Hash>(
+    env: &Env<S, A>,
+    discount: f64,
+    tolerance: f64,
+    max_sweeps: usize,
+) -> (Policy<S, A>, HashMap<S, f64>, ConvergenceReport) {
+    let start_time = Instant::now();
+    let mut report = ConvergenceReport::default();
+    let mut prev_state_values = HashMap::new();
+    let mut state_values = HashMap::new();
+
+    while report.sweeps < max_sweeps {
+        let (new_state_values, delta) = iterate_state_value(env, &state_values, discount);
+        prev_state_values = std::mem::replace(&mut state_values, new_state_values);
+        report.sweeps += 1;
+        report.backups += non_final_state_count(env);
+        report.deltas.push(delta);
+        if delta < tolerance {
+            break;
+        }
+    }
+
+    // The policy is stable if the last sweep didn't change the greedy policy.
+    let policy = make_greedy_policy(env, &state_values, discount);
+    let prev_policy = make_greedy_policy(env, &prev_state_values, discount);
+    report.policy_stable = is_greedy_policy(env, &prev_policy, &state_values, discount);
+
+    report.elapsed = start_time.elapsed();
+    (policy, state_values, report)
+}
+
+// Order in which in-place solvers visit the states during a sweep.
+#[derive(Debug, Clone)]
+pub enum StateOrder<S> {
+    // Sorted by state.
+    Natural,
+    // Sorted by state, in descending order.
+    Reverse,
+    // Shuffled before every sweep.
+    Random,
+    // The given order. States missing from the list, final states and states that aren't in the
+    // environment are not backed up.
+    Custom(Vec<S>),
+}
+
+// Returns the non-final states in the given order.
+pub fn order_states<S, A, R>(env: &Env<S, A>, order: &StateOrder<S>, rng: &mut R) -> Vec<S>
+where
+    S: Copy + Eq + Hash + Ord,
+    A: Eq + Hash,
+    R: Rng,
+{
+    if let StateOrder::Custom(states) = order {
+        return states
+            .iter()
+            .filter(|s| {
+                env.states
+                    .get(s)
+                    .is_some_and(|state_actions| !state_actions.actions.is_empty())
+            })
+            .copied()
+            .collect();
+    }
+
+    let mut states: Vec<S> = env
+        .states
+        .iter()
+        .filter(|(_, state_actions)| !state_actions.actions.is_empty())
+        .map(|(state, _)| *state)
+        .collect();
+    states.sort_unstable();
+    match order {
+        StateOrder::Reverse => states.reverse(),
+        StateOrder::Random => states.shuffle(rng),
+        _ => {}
+    }
+    states
+}
+
+// Same as evaluate_policy_iteration(), but updates the state values in place, visiting the states
+// in the given order, so that later states in the sweep already see the new values of the earlier
+// ones (Gauss-Seidel iteration).
+// Returns the maximum change in state-values.
+pub fn evaluate_policy_in_place<S: Copy + Eq + Hash + Debug, A: Eq + Hash>(
+    env: &Env<S, A>,
+    policy: &Policy<S, A>,
+    state_values: &mut HashMap<S, f64>,
+    states: &[S],
+    discount: f64,
+) -> f64 {
+    let mut max_delta: f64 = 0.0;
+
+    for state in states.iter() {
+        let state_policy = policy
+            .states
+            .get(state)
+            .unwrap_or_else(|| panic!("No policy for state {:?}", state));
+
+        let state_value = env.states[state]
+            .actions
+            .iter()
+            .map(|(action, action_result)| {
+                get_action_value(action_result, state_values, discount)
+                    * state_policy.actions.get(action).unwrap_or(&0.0)
+            })
+            .sum();
+
+        let prev_state_value = state_values.insert(*state, state_value).unwrap_or(0.0);
+        max_delta = max_delta.max((prev_state_value - state_value).abs());
+    }
+
+    max_delta
+}
+
+// Same as iterate_state_value(), but updates the state values in place, visiting the states in the
+// given order.
+// Returns the maximum change in state-values.
+pub fn iterate_state_value_in_place<S: Copy + Eq + Hash, A: Eq + Hash>(
+    env: &Env<S, A>,
+    state_values: &mut HashMap<S, f64>,
+    states: &[S],
+    discount: f64,
+) -> f64 {
+    let mut max_delta: f64 = 0.0;
+
+    for state in states.iter() {
+        let best_action_value = backup_state_value(&env.states[state], state_values, discount);
+        let prev_state_value = state_values
+            .insert(*state, best_action_value)
+            .unwrap_or(0.0);
+        max_delta = max_delta.max((best_action_value - prev_state_value).abs());
+    }
+
+    max_delta
+}
+
+// Same as policy_iteration(), but policy evaluation updates the state values in place, visiting
+// the states in the given order.
+pub fn policy_iteration_in_place<S, A, R>(
+    env: &Env<S, A>,
+    initial_policy: Policy<S, A>,
+    order: &StateOrder<S>,
+    discount: f64,
+    tolerance: f64,
+    max_sweeps: usize,
+    rng: &mut R,
+) -> (Policy<S, A>, HashMap<S, f64>, ConvergenceReport)
+where
+    S: Copy + Eq + Hash + Ord + Debug,
+    A: Copy + Eq + Hash,
+    R: Rng,
+{
+    let start_time = Instant::now();
+    let mut report = ConvergenceReport::default();
+    let mut policy = initial_policy;
+    let mut state_values = HashMap::new();
+    let mut states = order_states(env, order, rng);
+
+    while report.sweeps < max_sweeps {
+        while report.sweeps < max_sweeps {
+            if let StateOrder::Random = order {
+                states.shuffle(rng);
+            }
+            let delta =
+                evaluate_policy_in_place(env, &policy, &mut state_values, &states, discount);
+            report.sweeps += 1;
+            report.backups += states.len();
+            report.deltas.push(delta);
+            if delta < tolerance {
+                break;
+            }
+        }
+
+        report.policy_stable = is_greedy_policy(env, &policy, &state_values, discount);
+        if report.policy_stable {
+            break;
+        }
+        policy = make_greedy_policy(env, &state_values, discount);
+    }
+
+    report.elapsed = start_time.elapsed();
+    (policy, state_values, report)
+}
+
+// Same as value_iteration(), but the state values are updated in place, visiting the states in
+// the given order. With a good order (e.g. from the final states backward) the values propagate
+// much further in a single sweep.
+pub fn value_iteration_in_place<S, A, R>(
+    env: &Env<S, A>,
+    order: &StateOrder<S>,
+    discount: f64,
+    tolerance: f64,
+    max_sweeps: usize,
+    rng: &mut R,
+) -> (Policy<S, A>, HashMap<S, f64>, ConvergenceReport)
+where
+    S: Copy + Eq + Hash + Ord,
+    A: Copy + Eq + Hash,
+    R: Rng,
+{
+    let start_time = Instant::now();
+    let mut report = ConvergenceReport::default();
+    let mut prev_state_values = HashMap::new();
+    let mut state_values = HashMap::new();
+    let mut states = order_states(env, order, rng);
+
+    while report.sweeps < max_sweeps {
+        if let StateOrder::Random = order {
+            states.shuffle(rng);
+        }
+        prev_state_values.clone_from(&state_values);
+        let delta = iterate_state_value_in_place(env, &mut state_values, &states, discount);
+        report.sweeps += 1;
+        report.backups += states.len();
+        report.deltas.push(delta);
+        if delta < tolerance {
+            break;
+        }
+    }
+
+    let policy = make_greedy_policy(env, &state_values, discount);
+    let prev_policy = make_greedy_policy(env, &prev_state_values, discount);
+    report.policy_stable = is_greedy_policy(env, &prev_policy, &state_values, discount);
+
+    report.elapsed = start_time.elapsed();
+    (policy, state_values, report)
+}
+
+// Asynchronous value iteration: backs up one state at a time, chosen uniformly at random among
+// the non-final states, in place. Every batch of as many backups as there are non-final states
+// counts as a sweep. Since a batch may miss some states, convergence is checked only after all
+// the states have been backed up since the previous check: the iteration stops when the maximum
+// change since then drops below `tolerance`, or after `max_sweeps` sweeps.
+pub fn value_iteration_async<S, A, R>(
+    env: &Env<S, A>,
+    discount: f64,
+    tolerance: f64,
+    max_sweeps: usize,
+    rng: &mut R,
+) -> (Policy<S, A>, HashMap<S, f64>, ConvergenceReport)
+where
+    S: Copy + Eq + Hash + Ord,
+    A: Copy + Eq + Hash,
+    R: Rng,
+{
+    let start_time = Instant::now();
+    let mut report = ConvergenceReport::default();
+    let mut prev_state_values = HashMap::new();
+    let mut state_values = HashMap::new();
+    let states = order_states(env, &StateOrder::Natural, rng);
+
+    // Whether each state has been backed up since the last convergence check, and the maximum
+    // change since then.
+    let mut backed_up = vec![false; states.len()];
+    let mut check_delta: f64 = 0.0;
+
+    while report.sweeps < max_sweeps {
+        prev_state_values.clone_from(&state_values);
+        let mut delta: f64 = 0.0;
+        for _ in 0..states.len() {
+            let i = rng.gen_range(0..states.len());
+            let state = states[i];
+            let best_action_value =
+                backup_state_value(&env.states[&state], &state_values, discount);
+            let prev_state_value = state_values.insert(state, best_action_value).unwrap_or(0.0);
+            delta = delta.max((best_action_value - prev_state_value).abs());
+            backed_up[i] = true;
+        }
+        report.sweeps += 1;
+        report.backups += states.len();
+        report.deltas.push(delta);
+
+        // Only check for convergence once all the states have been backed up, since a change
+        // in a state that was missed could still propagate to the others.
+        check_delta = check_delta.max(delta);
+        if backed_up.iter().all(|b| *b) {
+            if check_delta < tolerance {
+                break;
+            }
+            backed_up.iter_mut().for_each(|b| *b = false);
+            check_delta = 0.0;
+        }
+    }
+
+    // States that have never been backed up keep value 0.
+    for state in states.iter() {
+        state_values.entry(*state).or_insert(0.0);
+    }
+
+    let policy = make_greedy_policy(env, &state_values, discount);
+    let prev_policy = make_greedy_policy(env, &prev_state_values, discount);
+    report.policy_stable = is_greedy_policy(env, &prev_policy, &state_values, discount);
+
+    report.elapsed = start_time.elapsed();
+    (policy, state_values, report)
+}
+
+// Fraction of the backup applied by relative_value_iteration() on each sweep. Mixing in the old
+// values makes the iteration converge for periodic MDPs too, without changing the fixed point.
+const RELATIVE_VALUE_ITERATION_STEP: f64 = 0.5;
+
+// Relative value iteration for continuing tasks without discounting (average reward setting).
+// Finds the differential state values h and the average reward g satisfying
+//   h(s) + g = max_a Σp(s'|s, a)∙[r + h(s')],
+// with h(`reference_state`) = 0. After every sweep the value of the reference state is subtracted
+// from all values, so that they stay bounded. The MDP must be unichain (all the policies visit the
+// reference state).
+// Stops when the maximum change in state values drops below `tolerance` or after `max_sweeps`
+// sweeps. Returns the greedy policy, the differential values and the average reward.
+pub fn relative_value_iteration<S: Copy + Eq + Hash, A: Copy + Eq + Hash>(
+    env: &Env<S, A>,
+    reference_state: S,
+    tolerance: f64,
+    max_sweeps: usize,
+) -> (Policy<S, A>, HashMap<S, f64>, f64, ConvergenceReport) {
+    assert!(!env.states[&reference_state].actions.is_empty());
+
+    let start_time = Instant::now();
+    let mut report = ConvergenceReport::default();
+    let mut prev_state_values = HashMap::new();
+    let mut state_values = HashMap::new();
+    let mut average_reward = 0.0;
+
+    while report.sweeps < max_sweeps {
+        let (backed_up_values, _) = iterate_state_value(env, &state_values, 1.0);
+        // The reference state has value 0, so its backup is the average reward estimate.
+        average_reward = backed_up_values[&reference_state];
+
+        let mut delta: f64 = 0.0;
+        let new_state_values: HashMap<S, f64> = backed_up_values
+            .into_iter()
+            .map(|(state, value)| {
+                let prev_value = *state_values.get(&state).unwrap_or(&0.0);
+                let new_value = prev_value
+                    + RELATIVE_VALUE_ITERATION_STEP * (value - prev_value - average_reward);
+                delta = delta.max((new_value - prev_value).abs());
+                (state, new_value)
+            })
+            .collect();
+        prev_state_values = std::mem::replace(&mut state_values, new_state_values);
+
+        report.sweeps += 1;
+        report.backups += non_final_state_count(env);
+        report.deltas.push(delta);
+        if delta < tolerance {
+            break;
+        }
+    }
+
+    let policy = make_greedy_policy(env, &state_values, 1.0);
+    let prev_policy = make_greedy_policy(env, &prev_state_values, 1.0);
+    report.policy_stable = is_greedy_policy(env, &prev_policy, &state_values, 1.0);
+
+    report.elapsed = start_time.elapsed();
+    (policy, state_values, average_reward, report)
+}
+
+// Returns the states from which some action can lead to the given state, for every state.
+pub fn predecessors<S: Copy + Eq + Hash, A: Eq + Hash>(env: &Env<S, A>) -> HashMap<S, Vec<S>> {
+    let mut predecessors: HashMap<S, Vec<S>> = HashMap::new();
+    for (state, state_actions) in env.states.iter() {
+        for action_result in state_actions.actions.values() {
+            for dest_state in action_result.dest_states.keys() {
+                let dest_predecessors = predecessors.entry(*dest_state).or_default();
+                if !dest_predecessors.contains(state) {
+                    dest_predecessors.push(*state);
+                }
+            }
+        }
+    }
+    predecessors
+}
+
+// Returns the new value of the state after a value iteration backup, i.e. the best action value.
+fn backup_state_value<S: Eq + Hash, A: Eq + Hash>(
+    state_actions: &StateActions<S, A>,
+    state_values: &HashMap<S, f64>,
+    discount: f64,
+) -> f64 {
+    state_actions
+        .actions
+        .values()
+        .map(|action_result| get_action_value(action_result, state_values, discount))
+        .fold(f64::NEG_INFINITY, f64::max)
+}
+
+// Finds the optimal policy with prioritized sweeping: instead of sweeping over all the states,
+// the states are backed up one at a time in the order of their Bellman error (how much their value
+// would change after a backup). After a state is backed up, its predecessors are checked, and the
+// ones whose value would change by more than `theta` are queued.
+// Stops when no state would change by more than `theta`, or after `max_backups` backups.
+pub fn prioritized_sweeping<S: Copy + Eq + Hash, A: Copy + Eq + Hash>(
+    env: &Env<S, A>,
+    discount: f64,
+    theta: f64,
+    max_backups: usize,
+) -> (Policy<S, A>, HashMap<S, f64>, ConvergenceReport) {
+    let start_time = Instant::now();
+    let mut report = ConvergenceReport::default();
+    let predecessors = predecessors(env);
+    let mut state_values = HashMap::new();
+
+    // Queue all the states that would change.
+    let mut queue = PriorityQueue::new();
+    for (state, state_actions) in env.states.iter() {
+        if state_actions.actions.is_empty() {
+            continue;
+        }
+        let error = backup_state_value(state_actions, &state_values, discount).abs();
+        if error > theta {
+            queue.push(*state, error);
+        }
+    }
+
+    while report.backups < max_backups {
+        let state = match queue.pop() {
+            Some((state, _)) => state,
+            None => break,
+        };
+
+        let new_state_value = backup_state_value(&env.states[&state], &state_values, discount);
+        let prev_state_value = state_values.insert(state, new_state_value).unwrap_or(0.0);
+        report.backups += 1;
+        report
+            .deltas
+            .push((new_state_value - prev_state_value).abs());
+
+        // Check the predecessors that could be affected by the change.
+        for predecessor in predecessors.get(&state).into_iter().flatten() {
+            let state_actions = &env.states[predecessor];
+            if state_actions.actions.is_empty() {
+                continue;
+            }
+            let error = (backup_state_value(state_actions, &state_values, discount)
+                - state_values.get(predecessor).unwrap_or(&0.0))
+            .abs();
+            if error > theta {
+                queue.push(*predecessor, error);
+            }
+        }
+    }
+
+    // States that have never been backed up keep value 0.
+    for (state, state_actions) in env.states.iter() {
+        if !state_actions.actions.is_empty() {
+            state_values.entry(*state).or_insert(0.0);
+        }
+    }
+
+    // There are no sweeps to compare, so the policy is stable if it stays greedy after one more
+    // sweep of value iteration.
+    let policy = make_greedy_policy(env, &state_values, discount);
+    let (next_state_values, _) = iterate_state_value(env, &state_values, discount);
+    report.policy_stable = is_greedy_policy(env, &policy, &next_state_values, discount);
+    report.elapsed = start_time.elapsed();
+    (policy, state_values, report)
+}
+
+// Takes a single step from the state following the policy. Returns the next state and the reward,
+// or None if the state is final.
+fn simulate_step<S, A, R>(
+    env: &Env<S, A>,
+    policy: &Policy<S, A>,
+    state: S,
+    rng: &mut R,
+) -> Option<(S, f64)>
+where
+    S: Copy + Eq + Hash + Debug + Ord,
+    A: Copy + Eq + Hash + Ord,
+    R: Rng,
+{
+    let state_actions = env
+        .states
+        .get(&state)
+        .unwrap_or_else(|| panic!("State {:?} not found", state));
+
+    // Final state.
+    if state_actions.actions.is_empty() {
+        return None;
+    }
+
+    // Choose action stochastically.
+    let policy_state = policy.states.get(&state).unwrap();
+    let action = choose_random_key(&policy_state.actions, rng, |v| *v);
+    let action_results = state_actions.actions.get(&action).unwrap();
+
+    // Choose end state stochastically.
+    let target_state = choose_random_key(&action_results.dest_states, rng, |v| v.probability);
+
+    let reward = action_results.dest_states[&target_state].reward;
+    Some((target_state, reward))
+}
+
+pub fn run_simulation<S, A, R>(
     env: &Env<S, A>,
     policy: &Policy<S, A>,
     start_state: S,
     max_steps: u32,
-) -> f64 {
+    rng: &mut R,
+) -> f64
+where
+    S: Copy + Eq + Hash + Debug + Ord,
+    A: Copy + Eq + Hash + Ord,
+    R: Rng,
+{
     let mut state = start_state;
     let mut total_reward = 0.0;
     for _ in 0..max_steps {
-        let state_actions = env
-            .states
-            .get(&state)
-            .expect(format!("State {:?} not found", state).as_str());
-
-        // Final state.
-        if state_actions.actions.is_empty() {
-            break;
+        match simulate_step(env, policy, state, rng) {
+            Some((target_state, reward)) => {
+                total_reward += reward;
+                state = target_state;
+            }
+            None => break,
         }
+    }
 
-        // Choose action stochastically.
-        let policy_state = policy.states.get(&state).unwrap();
-        let action = choose_random_key(&policy_state.actions, |v| *v);
-        let action_results = state_actions.actions.get(&action).unwrap();
+    total_reward
+}
+
+// Optimal non-stationary policy for a finite horizon: the best action depends on the number of
+// steps to go.
+pub struct FiniteHorizonSolution<S: Eq + Hash, A: Eq + Hash> {
+    // Greedy policies with 1, 2, ..., H steps to go.
+    policies: Vec<Policy<S, A>>,
+    // State values with 0, 1, ..., H steps to go. With 0 steps to go all values are 0.
+    state_values: Vec<HashMap<S, f64>>,
+}
+
+impl<S: Eq + Hash, A: Eq + Hash> FiniteHorizonSolution<S, A> {
+    pub fn horizon(&self) -> usize {
+        self.policies.len()
+    }
+
+    // Returns the policy to follow when there are `steps_to_go` steps left (1..=H).
+    pub fn policy(&self, steps_to_go: usize) -> &Policy<S, A> {
+        assert!(steps_to_go > 0 && steps_to_go <= self.horizon());
+        &self.policies[steps_to_go - 1]
+    }
+
+    // Returns the optimal state values when there are `steps_to_go` steps left (0..=H).
+    pub fn state_values(&self, steps_to_go: usize) -> &HashMap<S, f64> {
+        &self.state_values[steps_to_go]
+    }
+}
+
+// Finds the optimal policies for the horizon of H steps by backward induction: starting from
+// zero values with no steps to go, each step back is a single value iteration sweep
+//   V(k)(s) = max_a Σp(s'|s, a)∙[r + γ∙V(k-1)(s')],
+// and the policy with k steps to go is greedy with respect to V(k-1).
+pub fn backward_induction<S: Copy + Eq + Hash, A: Copy + Eq + Hash>(
+    env: &Env<S, A>,
+    discount: f64,
+    horizon: usize,
+) -> FiniteHorizonSolution<S, A> {
+    let zero_state_values: HashMap<S, f64> = env
+        .states
+        .iter()
+        .filter(|(_, state_actions)| !state_actions.actions.is_empty())
+        .map(|(state, _)| (*state, 0.0))
+        .collect();
 
-        // Choose end state stochastically.
-        let target_state = choose_random_key(&action_results.dest_states, |v| v.probability);
+    let mut policies = Vec::with_capacity(horizon);
+    let mut state_values = Vec::with_capacity(horizon + 1);
+    state_values.push(zero_state_values);
+    for _ in 0..horizon {
+        let prev_state_values = state_values.last().unwrap();
+        policies.push(make_greedy_policy(env, prev_state_values, discount));
+        state_values.push(iterate_state_value(env, prev_state_values, discount).0);
+    }
 
-        total_reward = total_reward
-            + action_results
-                .dest_states
-                .get(&target_state)
-                .unwrap()
-                .reward;
-        state = target_state;
+    FiniteHorizonSolution {
+        policies,
+        state_values,
+    }
+}
+
+// Same as run_simulation(), but follows the non-stationary policy for its horizon.
+pub fn run_simulation_finite_horizon<S, A, R>(
+    env: &Env<S, A>,
+    solution: &FiniteHorizonSolution<S, A>,
+    start_state: S,
+    rng: &mut R,
+) -> f64
+where
+    S: Copy + Eq + Hash + Debug + Ord,
+    A: Copy + Eq + Hash + Ord,
+    R: Rng,
+{
+    let mut state = start_state;
+    let mut total_reward = 0.0;
+    for steps_to_go in (1..=solution.horizon()).rev() {
+        match simulate_step(env, solution.policy(steps_to_go), state, rng) {
+            Some((target_state, reward)) => {
+                total_reward += reward;
+                state = target_state;
+            }
+            None => break,
+        }
     }
 
     total_reward
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+
+    // Corridor of 5 cells, where the rightmost cell is final. Every move yields -1. Action 2
+    // moves to the right just like action 1, so there are always ties between best actions.
+    pub(crate) fn corridor_env() -> Env<i32, i32> {
+        let mut states = HashMap::new();
+        for s in 0..4 {
+            let mut actions = HashMap::new();
+            actions.insert(-1, deterministic_action((s - 1).max(0), -1.0));
+            actions.insert(1, deterministic_action(s + 1, -1.0));
+            actions.insert(2, deterministic_action(s + 1, -1.0));
+            states.insert(s, StateActions { actions });
+        }
+        states.insert(4, StateActions::default());
+        Env { states }
+    }
+
+    #[test]
+    fn policy_iteration_corridor_test() {
+        let env = corridor_env();
+        let initial_policy = Policy {
+            states: (0..4)
+                .map(|s| {
+                    let policy_state = PolicyState {
+                        actions: vec![(-1, 0.1), (1, 0.9)].into_iter().collect(),
+                    };
+                    (s, policy_state)
+                })
+                .collect(),
+        };
+
+        let (policy, state_values, report) =
+            policy_iteration(&env, initial_policy, 1.0, 1e-9, 10000);
+
+        assert!(report.policy_stable);
+        assert!(report.sweeps < 10000);
+        assert_eq!(report.deltas.len(), report.sweeps);
+        for s in 0..4 {
+            assert!((state_values[&s] + (4 - s) as f64).abs() < 1e-6);
+            // Both "right" actions are optimal.
+            let actions = &policy.states[&s].actions;
+            assert_eq!(actions.len(), 2);
+            assert_eq!(actions[&1], 0.5);
+            assert_eq!(actions[&2], 0.5);
+        }
+    }
+
+    #[test]
+    fn value_iteration_corridor_test() {
+        let env = corridor_env();
+
+        let (policy, state_values, report) = value_iteration(&env, 1.0, 1e-9, 10000);
+
+        assert!(report.policy_stable);
+        // Values propagate from the final state one cell per sweep, plus one sweep to see that
+        // nothing changes anymore.
+        assert_eq!(report.sweeps, 5);
+        assert_eq!(*report.deltas.last().unwrap(), 0.0);
+        for s in 0..4 {
+            assert!((state_values[&s] + (4 - s) as f64).abs() < 1e-6);
+            assert!(!policy.states[&s].actions.contains_key(&-1));
+        }
+    }
+
+    #[test]
+    fn prioritized_sweeping_corridor_test() {
+        let env = corridor_env();
+
+        let predecessors = predecessors(&env);
+        let mut final_predecessors = predecessors[&4].clone();
+        final_predecessors.sort();
+        assert_eq!(final_predecessors, vec![3]);
+        let mut first_predecessors = predecessors[&0].clone();
+        first_predecessors.sort();
+        assert_eq!(first_predecessors, vec![0, 1]);
+
+        let (policy, state_values, report) = prioritized_sweeping(&env, 1.0, 1e-9, 10000);
+
+        assert!(report.policy_stable);
+        assert_eq!(report.deltas.len(), report.backups);
+        // Value iteration makes 5 sweeps over 4 states.
+        assert!(report.backups < 20);
+        for s in 0..4 {
+            assert!((state_values[&s] + (4 - s) as f64).abs() < 1e-6);
+            assert!(!policy.states[&s].actions.contains_key(&-1));
+        }
+    }
+
+    #[test]
+    fn value_iteration_in_place_order_test() {
+        // Corridor where the only action moves to the right.
+        let mut env = corridor_env();
+        for state_actions in env.states.values_mut() {
+            state_actions.actions.retain(|action, _| *action == 1);
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Sweeping toward the final state is the same as synchronous value iteration, while
+        // sweeping from it propagates the values through the whole corridor in one sweep.
+        let (_, natural_values, natural_report) =
+            value_iteration_in_place(&env, &StateOrder::Natural, 1.0, 1e-9, 10000, &mut rng);
+        let (_, reverse_values, reverse_report) =
+            value_iteration_in_place(&env, &StateOrder::Reverse, 1.0, 1e-9, 10000, &mut rng);
+        let (_, custom_values, custom_report) = value_iteration_in_place(
+            &env,
+            &StateOrder::Custom(vec![3, 2, 1, 0]),
+            1.0,
+            1e-9,
+            10000,
+            &mut rng,
+        );
+        assert_eq!(natural_report.sweeps, 5);
+        assert_eq!(reverse_report.sweeps, 2);
+        assert_eq!(custom_report.sweeps, 2);
+        assert_eq!(reverse_report.backups, 8);
+        assert!(reverse_report.policy_stable);
+
+        for s in 0..4 {
+            let expected = -(4 - s) as f64;
+            assert!((natural_values[&s] - expected).abs() < 1e-6);
+            assert!((reverse_values[&s] - expected).abs() < 1e-6);
+            assert!((custom_values[&s] - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn policy_iteration_in_place_corridor_test() {
+        let env = corridor_env();
+        let mut rng = StdRng::seed_from_u64(0);
+        let initial_policy = make_uniform_policy(&env);
+
+        let (_, sync_values, sync_report) =
+            policy_iteration(&env, initial_policy.clone(), 1.0, 1e-9, 10000);
+        let (policy, state_values, report) = policy_iteration_in_place(
+            &env,
+            initial_policy,
+            &StateOrder::Random,
+            1.0,
+            1e-9,
+            10000,
+            &mut rng,
+        );
+
+        println!(
+            "Synchronous: {} sweeps, in place: {}",
+            sync_report.sweeps, report.sweeps
+        );
+        assert!(report.policy_stable);
+        assert!(report.sweeps < sync_report.sweeps);
+        for s in 0..4 {
+            assert!((state_values[&s] - sync_values[&s]).abs() < 1e-6);
+            assert!(!policy.states[&s].actions.contains_key(&-1));
+        }
+    }
+
+    #[test]
+    fn value_iteration_async_corridor_test() {
+        let env = corridor_env();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let (policy, state_values, report) =
+            value_iteration_async(&env, 1.0, 1e-9, 10000, &mut rng);
+
+        assert!(report.policy_stable);
+        assert_eq!(report.backups, report.sweeps * 4);
+        for s in 0..4 {
+            assert!((state_values[&s] + (4 - s) as f64).abs() < 1e-6);
+            assert!(!policy.states[&s].actions.contains_key(&-1));
+        }
+
+        // The values only change in whole steps, so any tolerance below 1 must give the exact
+        // values, even if some batch misses the states that are still changing.
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (_, state_values, _) = value_iteration_async(&env, 1.0, 0.5, 10000, &mut rng);
+            for s in 0..4 {
+                assert_eq!(state_values[&s], -(4 - s) as f64, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn order_states_custom_test() {
+        let env = corridor_env();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // The final state 4 and the unknown state 7 are skipped.
+        let states = order_states(&env, &StateOrder::Custom(vec![7, 2, 4, 0]), &mut rng);
+        assert_eq!(states, vec![2, 0]);
+    }
+
+    #[test]
+    fn relative_value_iteration_test() {
+        // Continuing task: in state 0 action 0 stays and yields 1, action 1 moves to state 1 and
+        // yields 0, and from state 1 the only action returns to state 0 yielding 3. Going around
+        // gives 1.5 per step, which is better than staying. The cycle is periodic.
+        let mut states = HashMap::new();
+        let mut actions = HashMap::new();
+        actions.insert(0, deterministic_action(0, 1.0));
+        actions.insert(1, deterministic_action(1, 0.0));
+        states.insert(0, StateActions { actions });
+        let mut actions = HashMap::new();
+        actions.insert(0, deterministic_action(0, 3.0));
+        states.insert(1, StateActions { actions });
+        let env = Env { states };
+
+        let (policy, state_values, average_reward, report) =
+            relative_value_iteration(&env, 0, 1e-9, 10000);
+
+        assert!(report.policy_stable);
+        assert!((average_reward - 1.5).abs() < 1e-6);
+        // h(0) + 1.5 = 0 + h(1), h(1) + 1.5 = 3 + h(0).
+        assert!(state_values[&0].abs() < 1e-9);
+        assert!((state_values[&1] - 1.5).abs() < 1e-6);
+        assert_eq!(policy.states[&0].actions[&1], 1.0);
+    }
+
+    #[test]
+    fn backward_induction_corridor_test() {
+        let env = corridor_env();
+
+        let solution = backward_induction(&env, 1.0, 2);
+
+        assert_eq!(solution.horizon(), 2);
+        for s in 0..4 {
+            assert_eq!(solution.state_values(0)[&s], 0.0);
+            // Only the last cell reaches the final state in time.
+            let expected = if s == 3 { -1.0 } else { -2.0 };
+            assert_eq!(solution.state_values(2)[&s], expected);
+        }
+        // With one step to go all actions cost the same, while with two steps to go from the last
+        // cell moving right ends the episode right away.
+        assert_eq!(solution.policy(1).states[&3].actions.len(), 3);
+        assert!(!solution.policy(2).states[&3].actions.contains_key(&-1));
+
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(
+            run_simulation_finite_horizon(&env, &solution, 2, &mut rng),
+            -2.0
+        );
+    }
+}
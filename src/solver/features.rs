@@ -0,0 +1,200 @@
+use crate::solver::approximate::SparseFeatures;
+use crate::solver::tile::Bounds;
+
+// Constructs a feature vector x(s) for a point s of a continuous state space. Implemented by tile
+// coding (tile::TilingSet), polynomial and Fourier bases, radial basis functions and coarse coding.
+pub trait FeatureExtractor {
+    // Returns the number of features.
+    fn feature_count(&self) -> usize;
+
+    // Returns the features of the point, given by its coordinates in each dimension.
+    fn features(&self, point: &[f64]) -> Vec<f64>;
+
+    // Returns the non-zero features of the point.
+    fn sparse_features(&self, point: &[f64]) -> SparseFeatures {
+        let (indices, values) = self
+            .features(point)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, value)| *value != 0.0)
+            .unzip();
+        SparseFeatures::with_values(indices, values)
+    }
+}
+
+// Maps the coordinates of the point into [0, 1] in each dimension.
+pub(crate) fn normalize(bounds: &[Bounds<f64>], point: &[f64]) -> Vec<f64> {
+    assert_eq!(bounds.len(), point.len());
+    bounds
+        .iter()
+        .zip(point)
+        .map(|(b, x)| b.normalize(*x))
+        .collect()
+}
+
+// Returns all the vectors of the given dimension with integer components in 0..=order, with the
+// first component changing the fastest. These are the exponents of the polynomial basis and the
+// coefficients of the Fourier basis.
+pub(crate) fn integer_vectors(dimension_count: usize, order: u32) -> Vec<Vec<u32>> {
+    let mut vectors = vec![Vec::new()];
+    for _ in 0..dimension_count {
+        vectors = (0..=order)
+            .flat_map(|c| {
+                vectors.iter().map(move |v| {
+                    let mut v = v.clone();
+                    v.push(c);
+                    v
+                })
+            })
+            .collect();
+    }
+    vectors
+}
+
+// Returns the points of a regular grid with `count` points per dimension over [0, 1]. These are
+// the centers of the radial basis functions and of the coarse coding circles.
+pub(crate) fn grid_centers(dimension_count: usize, count: usize) -> Vec<Vec<f64>> {
+    assert!(count > 1);
+    integer_vectors(dimension_count, (count - 1) as u32)
+        .into_iter()
+        .map(|v| v.iter().map(|i| *i as f64 / (count - 1) as f64).collect())
+        .collect()
+}
+
+// Adapts a feature extractor over states to the state-action features used by the approximate
+// action value methods: the state features are copied into the block of the given action, and
+// the blocks of the other actions are 0. `coordinates` maps the state to a point.
+pub fn state_action_features<'a, S, A, E, Coordinates>(
+    extractor: &'a E,
+    actions: &'a [A],
+    coordinates: Coordinates,
+) -> impl Fn(&S, &A) -> Vec<f64> + 'a
+where
+    A: PartialEq,
+    E: FeatureExtractor,
+    Coordinates: Fn(&S) -> Vec<f64> + 'a,
+{
+    move |s: &S, a: &A| {
+        let n = extractor.feature_count();
+        let action_index = actions.iter().position(|b| b == a).expect("Unknown action");
+        let mut features = vec![0.0; n * actions.len()];
+        features[action_index * n..(action_index + 1) * n]
+            .copy_from_slice(&extractor.features(&coordinates(s)));
+        features
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra::DVector;
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+
+    use crate::solver::approximate::*;
+    use crate::solver::coarse::CoarseCoding;
+    use crate::solver::environment::*;
+    use crate::solver::fourier::FourierBasis;
+    use crate::solver::polynomial::PolynomialBasis;
+    use crate::solver::rbf::RadialBasis;
+    use crate::solver::tile::{ContinuousDimension, TilingSet};
+
+    #[test]
+    fn integer_vectors_test() {
+        assert_eq!(
+            integer_vectors(2, 1),
+            vec![vec![0, 0], vec![1, 0], vec![0, 1], vec![1, 1]]
+        );
+        assert_eq!(integer_vectors(3, 2).len(), 27);
+        assert_eq!(integer_vectors(0, 2), vec![Vec::<u32>::new()]);
+    }
+
+    #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+    enum CorridorAction {
+        Left,
+        Right,
+    }
+
+    const CORRIDOR_LENGTH: f64 = 10.0;
+
+    // Corridor [0, 10) with random start, where the agent moves by 1 to the left or right, with
+    // reward -1 per step, until it steps out on the right.
+    fn corridor_env() -> impl Environment<State = f64, Action = CorridorAction> {
+        ClosureEnvironment::new(
+            |rng: &mut dyn RngCore| rng.gen_range(0..10) as f64,
+            |_s: &f64| vec![CorridorAction::Left, CorridorAction::Right],
+            |s: &f64, a: &CorridorAction, _rng: &mut dyn RngCore| match a {
+                CorridorAction::Left => (Some((s - 1.0).max(0.0)), -1.0),
+                CorridorAction::Right if *s + 1.0 >= CORRIDOR_LENGTH => (None, -1.0),
+                CorridorAction::Right => (Some(s + 1.0), -1.0),
+            },
+        )
+        .with_max_steps(1000)
+    }
+
+    // Learns the action values on the corridor with the features from the extractor, following
+    // the greedy policy (the zero initial values are optimistic enough to explore), and checks
+    // that the values of the states are close to the optimal -(10 - s), and that going right is
+    // better everywhere.
+    fn check_corridor<E: FeatureExtractor>(extractor: &E, alpha: f64) {
+        let actions = [CorridorAction::Left, CorridorAction::Right];
+        let features = state_action_features(extractor, &actions, |s: &f64| vec![*s]);
+        let w = find_action_values_episodic_semi_gradient_sarsa(
+            &corridor_env(),
+            &features,
+            1.0,
+            0.0,
+            alpha,
+            1000,
+            &mut StdRng::seed_from_u64(0),
+        );
+
+        for s in 0..10 {
+            let s = s as f64;
+            let value = |a| w.dot(&DVector::from_vec(features(&s, a)));
+            let (right, left) = (value(&CorridorAction::Right), value(&CorridorAction::Left));
+            assert!(
+                right > left && (right - (s - CORRIDOR_LENGTH)).abs() < 1.5,
+                "For state {}: right {}, left {}",
+                s,
+                right,
+                left
+            );
+        }
+    }
+
+    #[test]
+    fn state_action_features_test() {
+        let basis = PolynomialBasis::new(vec![Bounds::new(0.0, 10.0)], 1);
+        let actions = [CorridorAction::Left, CorridorAction::Right];
+        let features = state_action_features(&basis, &actions, |s: &f64| vec![*s]);
+        assert_eq!(
+            features(&5.0, &CorridorAction::Left),
+            vec![1.0, 0.5, 0.0, 0.0]
+        );
+        assert_eq!(
+            features(&5.0, &CorridorAction::Right),
+            vec![0.0, 0.0, 1.0, 0.5]
+        );
+    }
+
+    #[test]
+    fn extractors_corridor_test() {
+        let bounds = || vec![Bounds::new(0.0, CORRIDOR_LENGTH)];
+
+        let tilings = TilingSet::from_dimensions(
+            &vec![ContinuousDimension::new(0.0, CORRIDOR_LENGTH, 5)],
+            &Vec::new(),
+            4,
+        );
+        check_corridor(&tilings, 0.1 / 4.0);
+        check_corridor(&PolynomialBasis::new(bounds(), 3), 0.01);
+        check_corridor(
+            &FourierBasis::new(bounds(), 5).with_scaled_step_sizes(),
+            0.02,
+        );
+        check_corridor(&RadialBasis::new(bounds(), 10, 0.1), 0.05);
+        check_corridor(&CoarseCoding::new(bounds(), 10, 0.15), 0.05);
+    }
+}
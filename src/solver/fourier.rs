@@ -0,0 +1,121 @@
+use std::f64::consts::PI;
+
+use crate::solver::features::*;
+use crate::solver::tile::Bounds;
+
+// Fourier cosine basis from section 9.5.2: for the state s normalized into [0, 1] in each of its
+// k dimensions, the features are
+//   x_i(s) = cos(π∙s∙cⁱ),
+// for all the coefficient vectors cⁱ with components in 0..=order, i.e. (order + 1)ᵏ features.
+pub struct FourierBasis {
+    bounds: Vec<Bounds<f64>>,
+    coefficients: Vec<Vec<u32>>,
+    // Whether the features are scaled to get the per-feature step sizes (see
+    // with_scaled_step_sizes()).
+    scaled: bool,
+}
+
+impl FourierBasis {
+    pub fn new(bounds: Vec<Bounds<f64>>, order: u32) -> Self {
+        let coefficients = integer_vectors(bounds.len(), order);
+        FourierBasis {
+            bounds,
+            coefficients,
+            scaled: false,
+        }
+    }
+
+    // Returns the recommended step size of each feature relative to the base step size α:
+    //   α_i = α / ‖cⁱ‖,
+    // (α_i = α if all the coefficients are 0), so that the high frequency features change slower.
+    pub fn step_size_scales(&self) -> Vec<f64> {
+        self.coefficients
+            .iter()
+            .map(|c| {
+                let norm = c.iter().map(|x| (x * x) as f64).sum::<f64>().sqrt();
+                if norm == 0.0 {
+                    1.0
+                } else {
+                    1.0 / norm
+                }
+            })
+            .collect()
+    }
+
+    // Multiplies each feature by the square root of its step size scale. For a linear method,
+    // this is the same as using the per-feature step sizes (with the weights scaled by the same
+    // square roots), so the step sizes work with any of the approximate methods, which only have
+    // a single α.
+    pub fn with_scaled_step_sizes(mut self) -> Self {
+        self.scaled = true;
+        self
+    }
+}
+
+impl FeatureExtractor for FourierBasis {
+    fn feature_count(&self) -> usize {
+        self.coefficients.len()
+    }
+
+    fn features(&self, point: &[f64]) -> Vec<f64> {
+        let s = normalize(&self.bounds, point);
+        let features = self.coefficients.iter().map(|c| {
+            let product: f64 = s.iter().zip(c).map(|(x, c)| x * *c as f64).sum();
+            (PI * product).cos()
+        });
+        if self.scaled {
+            features
+                .zip(self.step_size_scales())
+                .map(|(x, scale)| x * scale.sqrt())
+                .collect()
+        } else {
+            features.collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nalgebra::DVector;
+
+    #[test]
+    fn fourier_basis_test() {
+        let basis = FourierBasis::new(vec![Bounds::new(0.0, 1.0), Bounds::new(0.0, 1.0)], 1);
+        assert_eq!(basis.feature_count(), 4);
+
+        // Coefficients (0, 0), (1, 0), (0, 1), (1, 1).
+        let features = basis.features(&[0.5, 1.0 / 3.0]);
+        assert!((features[0] - 1.0).abs() < 1e-12);
+        assert!(features[1].abs() < 1e-12);
+        assert!((features[2] - 0.5).abs() < 1e-12);
+        assert!((features[3] + 3.0f64.sqrt() / 2.0).abs() < 1e-12);
+
+        let scales = basis.step_size_scales();
+        assert_eq!(scales[..3], [1.0, 1.0, 1.0]);
+        assert!((scales[3] - 0.5f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn scaled_step_sizes_test() {
+        // One update w ← w + α∙δ∙x with the scaled features must change the value of any point
+        // like the update w_i ← w_i + α_i∙δ∙x_i with the per-feature step sizes.
+        let bounds = || vec![Bounds::new(-1.0, 1.0), Bounds::new(0.0, 4.0)];
+        let basis = FourierBasis::new(bounds(), 3);
+        let scaled_basis = FourierBasis::new(bounds(), 3).with_scaled_step_sizes();
+        let scales = DVector::from_vec(basis.step_size_scales());
+        let (alpha, td_error) = (0.1, 2.0);
+
+        let x = DVector::from_vec(basis.features(&[0.3, 1.7]));
+        let w = alpha * td_error * scales.component_mul(&x);
+        let scaled_x = DVector::from_vec(scaled_basis.features(&[0.3, 1.7]));
+        let scaled_w = alpha * td_error * &scaled_x;
+
+        for point in [[-0.9, 0.1], [0.0, 2.0], [0.8, 3.9]].iter() {
+            let value = w.dot(&DVector::from_vec(basis.features(point)));
+            let scaled_value = scaled_w.dot(&DVector::from_vec(scaled_basis.features(point)));
+            assert!((value - scaled_value).abs() < 1e-12);
+        }
+    }
+}
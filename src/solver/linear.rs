@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use nalgebra::{DMatrix, DVector};
+
+use crate::solver::explicit::*;
+use crate::solver::*;
+
+// Systems with more unknowns than this are solved iteratively rather than by LU decomposition.
+const DENSE_SIZE_LIMIT: usize = 2000;
+
+// Square sparse matrix in compressed sparse row format.
+pub struct SparseMatrix {
+    size: usize,
+    // Row i occupies entries row_starts[i]..row_starts[i + 1] of columns and values.
+    row_starts: Vec<usize>,
+    columns: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl SparseMatrix {
+    // Creates a matrix from a list of (column, value) pairs for each row.
+    // Entries with the same column are summed up.
+    pub fn from_rows(rows: Vec<Vec<(usize, f64)>>) -> Self {
+        let size = rows.len();
+        let mut row_starts = Vec::with_capacity(size + 1);
+        let mut columns = Vec::new();
+        let mut values = Vec::new();
+
+        row_starts.push(0);
+        for mut row in rows {
+            row.sort_by_key(|(column, _)| *column);
+            for (column, value) in row {
+                assert!(column < size);
+                let row_start = *row_starts.last().unwrap();
+                if columns.len() > row_start && *columns.last().unwrap() == column {
+                    *values.last_mut().unwrap() += value;
+                } else {
+                    columns.push(column);
+                    values.push(value);
+                }
+            }
+            row_starts.push(columns.len());
+        }
+
+        SparseMatrix {
+            size,
+            row_starts,
+            columns,
+            values,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Returns the product of this matrix and the vector.
+    pub fn multiply(&self, x: &DVector<f64>) -> DVector<f64> {
+        assert_eq!(x.len(), self.size);
+        DVector::from_iterator(
+            self.size,
+            (0..self.size).map(|i| {
+                (self.row_starts[i]..self.row_starts[i + 1])
+                    .map(|k| self.values[k] * x[self.columns[k]])
+                    .sum::<f64>()
+            }),
+        )
+    }
+
+    pub fn diagonal(&self) -> DVector<f64> {
+        DVector::from_iterator(
+            self.size,
+            (0..self.size).map(|i| {
+                (self.row_starts[i]..self.row_starts[i + 1])
+                    .filter(|k| self.columns[*k] == i)
+                    .map(|k| self.values[k])
+                    .sum::<f64>()
+            }),
+        )
+    }
+
+    pub fn to_dense(&self) -> DMatrix<f64> {
+        let mut m = DMatrix::zeros(self.size, self.size);
+        for i in 0..self.size {
+            for k in self.row_starts[i]..self.row_starts[i + 1] {
+                m[(i, self.columns[k])] += self.values[k];
+            }
+        }
+        m
+    }
+}
+
+// Solves A∙x = b using the stabilized biconjugate gradient method (BiCGSTAB) with a diagonal
+// (Jacobi) preconditioner.
+// Stops when the residual norm drops below `tolerance`∙|b|. Returns None if the method breaks
+// down or doesn't converge within `max_iterations`.
+pub fn solve_bicgstab(
+    a: &SparseMatrix,
+    b: &DVector<f64>,
+    tolerance: f64,
+    max_iterations: usize,
+) -> Option<DVector<f64>> {
+    let n = a.size();
+    assert_eq!(b.len(), n);
+
+    let inverse_diagonal = a
+        .diagonal()
+        .map(|d| if d.abs() > f64::EPSILON { 1.0 / d } else { 1.0 });
+    let threshold = tolerance * b.norm().max(f64::MIN_POSITIVE);
+
+    let mut x = DVector::zeros(n);
+    let mut r = b.clone();
+    let r_hat = r.clone();
+    let mut p = DVector::zeros(n);
+    let mut v = DVector::zeros(n);
+    let (mut rho, mut alpha, mut omega) = (1.0, 1.0, 1.0);
+
+    for _ in 0..max_iterations {
+        if r.norm() < threshold {
+            return Some(x);
+        }
+
+        let rho_next = r_hat.dot(&r);
+        if rho_next == 0.0 || omega == 0.0 {
+            return None;
+        }
+        let beta = (rho_next / rho) * (alpha / omega);
+        rho = rho_next;
+
+        p = &r + beta * (p - omega * &v);
+        let y = p.component_mul(&inverse_diagonal);
+        v = a.multiply(&y);
+        let r_hat_v = r_hat.dot(&v);
+        if r_hat_v == 0.0 {
+            return None;
+        }
+        alpha = rho / r_hat_v;
+
+        let s = &r - alpha * &v;
+        if s.norm() < threshold {
+            return Some(x + alpha * y);
+        }
+
+        let z = s.component_mul(&inverse_diagonal);
+        let t = a.multiply(&z);
+        let t_t = t.dot(&t);
+        if t_t == 0.0 {
+            return None;
+        }
+        omega = t.dot(&s) / t_t;
+
+        x += alpha * y + omega * z;
+        r = s - omega * t;
+    }
+
+    if r.norm() < threshold {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+// Solves A∙x = b: directly for small systems and with BiCGSTAB for large ones.
+pub fn solve_linear_system(a: &SparseMatrix, b: &DVector<f64>) -> DVector<f64> {
+    if a.size() <= DENSE_SIZE_LIMIT {
+        a.to_dense()
+            .lu()
+            .solve(b)
+            .expect("The linear system is singular")
+    } else {
+        solve_bicgstab(a, b, 1e-12, 10 * a.size()).expect("BiCGSTAB didn't converge")
+    }
+}
+
+// Builds the Bellman equation for the policy as a linear system (I - γ∙P_π)∙v = r_π.
+// Only non-final states are included into the system (value of final states is 0).
+// Returns the states in the order of unknowns, the matrix and the right-hand side.
+fn bellman_system<S, A>(
+    env: &Env<S, A>,
+    policy: &Policy<S, A>,
+    discount: f64,
+) -> (Vec<S>, SparseMatrix, DVector<f64>)
+where
+    S: Copy + Eq + Hash + Debug,
+    A: Eq + Hash,
+{
+    let states: Vec<S> = env
+        .states
+        .iter()
+        .filter(|(_, state_actions)| !state_actions.actions.is_empty())
+        .map(|(state, _)| *state)
+        .collect();
+    let state_indices: HashMap<S, usize> =
+        states.iter().enumerate().map(|(i, s)| (*s, i)).collect();
+
+    let mut rows = Vec::with_capacity(states.len());
+    let mut rewards = DVector::zeros(states.len());
+    for (i, state) in states.iter().enumerate() {
+        let state_policy = policy
+            .states
+            .get(state)
+            .unwrap_or_else(|| panic!("No policy for state {:?}", state));
+
+        let mut row = vec![(i, 1.0)];
+        for (action, action_result) in env.states[state].actions.iter() {
+            let action_prob = *state_policy.actions.get(action).unwrap_or(&0.0);
+            if action_prob == 0.0 {
+                continue;
+            }
+
+            for (dest_state, dest) in action_result.dest_states.iter() {
+                let probability = action_prob * dest.probability;
+                rewards[i] += probability * dest.reward;
+
+                // Final (or unknown) destination states have value 0 and don't need a column.
+                if let Some(j) = state_indices.get(dest_state) {
+                    row.push((*j, -discount * probability));
+                }
+            }
+        }
+        rows.push(row);
+    }
+
+    (states, SparseMatrix::from_rows(rows), rewards)
+}
+
+// Computes the state-value function of the policy exactly, by solving the Bellman equation
+// (I - γ∙P_π)∙v = r_π.
+// Gives the same result as iterating evaluate_policy_iteration() until convergence.
+pub fn evaluate_policy_exact<S, A>(
+    env: &Env<S, A>,
+    policy: &Policy<S, A>,
+    discount: f64,
+) -> HashMap<S, f64>
+where
+    S: Copy + Eq + Hash + Debug,
+    A: Eq + Hash,
+{
+    let (states, matrix, rewards) = bellman_system(env, policy, discount);
+    let values = solve_linear_system(&matrix, &rewards);
+    states.into_iter().zip(values.iter().copied()).collect()
+}
+
+// Same as evaluate_policy_exact(), but always solves the system iteratively with BiCGSTAB.
+// Returns None if the solver doesn't converge to the given relative tolerance.
+pub fn evaluate_policy_krylov<S, A>(
+    env: &Env<S, A>,
+    policy: &Policy<S, A>,
+    discount: f64,
+    tolerance: f64,
+    max_iterations: usize,
+) -> Option<HashMap<S, f64>>
+where
+    S: Copy + Eq + Hash + Debug,
+    A: Eq + Hash,
+{
+    let (states, matrix, rewards) = bellman_system(env, policy, discount);
+    let values = solve_bicgstab(&matrix, &rewards, tolerance, max_iterations)?;
+    Some(states.into_iter().zip(values.iter().copied()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // Random MDP where every action leads to a few random states (possibly the final state 0).
+    fn random_env(state_count: i32, rng: &mut StdRng) -> Env<i32, i32> {
+        let mut states = HashMap::new();
+        for s in 1..state_count {
+            let mut actions = HashMap::new();
+            for a in 0..3 {
+                let mut dest_states = HashMap::new();
+                let weights: Vec<f64> = (0..3).map(|_| rng.gen::<f64>()).collect();
+                let total: f64 = weights.iter().sum();
+                for w in weights {
+                    let dest = dest_states
+                        .entry(rng.gen_range(0..state_count))
+                        .or_insert_with(ActionDestination::default);
+                    dest.probability += w / total;
+                    dest.reward = rng.gen_range(-1.0..1.0);
+                }
+                actions.insert(a, ActionResult { dest_states });
+            }
+            states.insert(s, StateActions { actions });
+        }
+        states.insert(0, StateActions::default());
+        Env { states }
+    }
+
+    fn sweep_state_values(env: &Env<i32, i32>, policy: &Policy<i32, i32>) -> HashMap<i32, f64> {
+        let mut state_values = HashMap::new();
+        loop {
+            let (new_state_values, delta) =
+                evaluate_policy_iteration(env, policy, &state_values, 0.9);
+            state_values = new_state_values;
+            if delta < 1e-12 {
+                return state_values;
+            }
+        }
+    }
+
+    #[test]
+    fn exact_matches_sweeps_test() {
+        let env = random_env(50, &mut StdRng::seed_from_u64(0));
+        let policy = make_uniform_policy(&env);
+
+        let expected = sweep_state_values(&env, &policy);
+        let exact = evaluate_policy_exact(&env, &policy, 0.9);
+        let krylov = evaluate_policy_krylov(&env, &policy, 0.9, 1e-12, 1000).unwrap();
+
+        assert_eq!(exact.len(), expected.len());
+        for (state, value) in expected.iter() {
+            assert!((exact[state] - value).abs() < 1e-8, "state {}", state);
+            assert!((krylov[state] - value).abs() < 1e-8, "state {}", state);
+        }
+    }
+
+    #[test]
+    fn exact_final_states_test() {
+        // 0 → 1 → 2 (final), reward 1 on each step, no discount.
+        let mut states = HashMap::new();
+        states.insert(0, StateActions::default());
+        states
+            .get_mut(&0)
+            .unwrap()
+            .actions
+            .insert(0, deterministic_action(1, 1.0));
+        states.insert(1, StateActions::default());
+        states
+            .get_mut(&1)
+            .unwrap()
+            .actions
+            .insert(0, deterministic_action(2, 1.0));
+        states.insert(2, StateActions::default());
+        let env = Env { states };
+
+        let state_values = evaluate_policy_exact(&env, &make_uniform_policy(&env), 1.0);
+
+        assert_eq!(state_values.len(), 2);
+        assert!((state_values[&0] - 2.0).abs() < 1e-12);
+        assert!((state_values[&1] - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::iter::once;
+
+use crate::solver::explicit::*;
+use crate::solver::*;
+
+// Pivot elements and reduced costs smaller than this are treated as zero.
+const EPSILON: f64 = 1e-10;
+
+// Optimal solution of a linear program in standard form (see simplex_maximize()).
+#[derive(Debug, Clone)]
+pub struct SimplexSolution {
+    pub x: Vec<f64>,
+    // Multipliers of the constraints, i.e. the solution of the dual program.
+    pub duals: Vec<f64>,
+    pub objective: f64,
+    // Number of pivots made in both phases.
+    pub pivots: usize,
+}
+
+// Simplex tableau: one row per constraint, and the reduced costs row. The last column is the
+// right-hand side.
+struct Tableau {
+    rows: Vec<Vec<f64>>,
+    reduced_costs: Vec<f64>,
+    // Basic variable of each row.
+    basis: Vec<usize>,
+    pivots: usize,
+}
+
+impl Tableau {
+    fn rhs(&self, row: usize) -> f64 {
+        *self.rows[row].last().unwrap()
+    }
+
+    // Sets the reduced costs c_j - c_B∙B⁻¹∙A_j for the given costs, and the objective value.
+    fn set_costs(&mut self, costs: &[f64]) {
+        let width = self.rows[0].len();
+        self.reduced_costs = (0..width)
+            .map(|j| {
+                let c = if j < width - 1 { costs[j] } else { 0.0 };
+                c - self
+                    .rows
+                    .iter()
+                    .zip(self.basis.iter())
+                    .map(|(row, basic)| costs[*basic] * row[j])
+                    .sum::<f64>()
+            })
+            .collect();
+    }
+
+    // The right-hand side entry of the reduced costs row is -c_B∙B⁻¹∙b.
+    fn objective(&self) -> f64 {
+        -*self.reduced_costs.last().unwrap()
+    }
+
+    fn pivot(&mut self, row: usize, column: usize) {
+        let pivot = self.rows[row][column];
+        for value in self.rows[row].iter_mut() {
+            *value /= pivot;
+        }
+
+        let pivot_row = self.rows[row].clone();
+        let eliminate = |target: &mut Vec<f64>| {
+            let factor = target[column];
+            if factor != 0.0 {
+                for (value, pivot_value) in target.iter_mut().zip(pivot_row.iter()) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        };
+        for (i, target) in self.rows.iter_mut().enumerate() {
+            if i != row {
+                eliminate(target);
+            }
+        }
+        eliminate(&mut self.reduced_costs);
+
+        self.basis[row] = column;
+        self.pivots += 1;
+    }
+
+    // Maximizes the objective with the first `columns` variables allowed to enter the basis, using
+    // Bland's rule (the first improving column, and the row with the smallest basic variable among
+    // the ties), which can't cycle on degenerate programs.
+    // Returns false if the objective is unbounded.
+    fn maximize(&mut self, columns: usize, max_pivots: usize) -> bool {
+        while self.pivots < max_pivots {
+            let column = match (0..columns).find(|j| self.reduced_costs[*j] > EPSILON) {
+                Some(column) => column,
+                None => return true,
+            };
+
+            let mut best_row: Option<(usize, f64)> = None;
+            for (i, row) in self.rows.iter().enumerate() {
+                if row[column] <= EPSILON {
+                    continue;
+                }
+                let ratio = self.rhs(i) / row[column];
+                best_row = match best_row {
+                    Some((best, best_ratio))
+                        if best_ratio < ratio - EPSILON
+                            || (ratio - best_ratio).abs() <= EPSILON
+                                && self.basis[best] < self.basis[i] =>
+                    {
+                        Some((best, best_ratio))
+                    }
+                    _ => Some((i, ratio)),
+                };
+            }
+
+            match best_row {
+                Some((row, _)) => self.pivot(row, column),
+                None => return false,
+            }
+        }
+        panic!("Simplex didn't converge in {} pivots", max_pivots);
+    }
+}
+
+// Maximizes c∙x subject to A∙x = b, x ≥ 0 with the two-phase simplex method, where A is given by
+// rows. Returns None if the program is infeasible or unbounded. Panics after `max_pivots` pivots.
+pub fn simplex_maximize(
+    a: &[Vec<f64>],
+    b: &[f64],
+    c: &[f64],
+    max_pivots: usize,
+) -> Option<SimplexSolution> {
+    let (m, n) = (a.len(), c.len());
+    assert_eq!(b.len(), m);
+
+    // Flip the constraints with negative right-hand side, and add an artificial variable to each
+    // constraint, so that they form the initial basis.
+    let signs: Vec<f64> = b
+        .iter()
+        .map(|b| if *b < 0.0 { -1.0 } else { 1.0 })
+        .collect();
+    let rows = (0..m)
+        .map(|i| {
+            assert_eq!(a[i].len(), n);
+            a[i].iter()
+                .map(|a| a * signs[i])
+                .chain((0..m).map(|k| if k == i { 1.0 } else { 0.0 }))
+                .chain(once(b[i] * signs[i]))
+                .collect()
+        })
+        .collect();
+    let mut tableau = Tableau {
+        rows,
+        reduced_costs: Vec::new(),
+        basis: (n..n + m).collect(),
+        pivots: 0,
+    };
+
+    // Phase 1: minimize the sum of artificial variables to find a feasible basis.
+    let phase_1_costs: Vec<f64> = (0..n + m).map(|j| if j < n { 0.0 } else { -1.0 }).collect();
+    tableau.set_costs(&phase_1_costs);
+    tableau.maximize(n + m, max_pivots);
+    if tableau.objective() < -1e-8 {
+        return None;
+    }
+
+    // Drive the remaining (zero) artificial variables out of the basis. If it's impossible, the
+    // constraint is redundant, and the artificial variable stays at zero.
+    for i in 0..m {
+        if tableau.basis[i] >= n {
+            if let Some(column) = (0..n).find(|j| tableau.rows[i][*j].abs() > 1e-8) {
+                tableau.pivot(i, column);
+            }
+        }
+    }
+
+    // Phase 2: optimize the original objective, without letting the artificial variables back.
+    let phase_2_costs: Vec<f64> = (0..n + m).map(|j| if j < n { c[j] } else { 0.0 }).collect();
+    tableau.set_costs(&phase_2_costs);
+    if !tableau.maximize(n, max_pivots) {
+        return None;
+    }
+
+    let mut x = vec![0.0; n];
+    for (i, basic) in tableau.basis.iter().enumerate() {
+        if *basic < n {
+            x[*basic] = tableau.rhs(i);
+        }
+    }
+    // The multiplier of constraint i is c_B∙B⁻¹∙e_i, which is minus the reduced cost of its
+    // artificial variable.
+    let duals = (0..m)
+        .map(|i| -tableau.reduced_costs[n + i] * signs[i])
+        .collect();
+
+    Some(SimplexSolution {
+        x,
+        duals,
+        objective: tableau.objective(),
+        pivots: tableau.pivots,
+    })
+}
+
+// The primal linear program of an MDP: minimize Σv(s) subject to
+// v(s) - γ∙Σp(s'|s, a)∙v(s') ≥ Σp(s'|s, a)∙r(s, a, s')
+// for every non-final state s and its action a.
+// Only non-final states have variables (value of final states is 0). The states and the actions
+// are sorted, so that the solution doesn't depend on the hash map order.
+pub struct MdpLinearProgram<S, A> {
+    pub states: Vec<S>,
+    // The state-action pair of each constraint.
+    pub state_actions: Vec<(S, A)>,
+    // Coefficients of the state values in each constraint.
+    pub constraints: Vec<Vec<f64>>,
+    // Expected reward of each constraint.
+    pub rewards: Vec<f64>,
+}
+
+impl<S, A> MdpLinearProgram<S, A>
+where
+    S: Copy + Eq + Hash + Ord,
+    A: Copy + Eq + Hash + Ord,
+{
+    pub fn from_env(env: &Env<S, A>, discount: f64) -> Self {
+        let mut states: Vec<S> = env
+            .states
+            .iter()
+            .filter(|(_, state_actions)| !state_actions.actions.is_empty())
+            .map(|(state, _)| *state)
+            .collect();
+        states.sort_unstable();
+        let state_indices: HashMap<S, usize> =
+            states.iter().enumerate().map(|(i, s)| (*s, i)).collect();
+
+        let mut state_actions = Vec::new();
+        let mut constraints = Vec::new();
+        let mut rewards = Vec::new();
+        for (i, state) in states.iter().enumerate() {
+            let mut actions: Vec<&A> = env.states[state].actions.keys().collect();
+            actions.sort_unstable();
+            for action in actions {
+                let mut constraint = vec![0.0; states.len()];
+                constraint[i] = 1.0;
+                let mut reward = 0.0;
+                for (dest_state, dest) in env.states[state].actions[action].dest_states.iter() {
+                    reward += dest.probability * dest.reward;
+                    if let Some(j) = state_indices.get(dest_state) {
+                        constraint[*j] -= discount * dest.probability;
+                    }
+                }
+                state_actions.push((*state, *action));
+                constraints.push(constraint);
+                rewards.push(reward);
+            }
+        }
+
+        MdpLinearProgram {
+            states,
+            state_actions,
+            constraints,
+            rewards,
+        }
+    }
+
+    // Solves the dual program: maximize Σx(s, a)∙r(s, a) subject to
+    // Σx(s', a) - γ∙Σp(s'|s, a)∙x(s, a) = 1 for every state s', x ≥ 0.
+    // x(s, a) is the expected (discounted) number of times action a is taken in state s, when
+    // starting from every state once, and the multipliers of the constraints are the optimal state
+    // values.
+    // Returns the policy that takes the action with the largest x in every state, and the values.
+    pub fn solve(&self, max_pivots: usize) -> Option<(Policy<S, A>, HashMap<S, f64>)> {
+        let state_indices: HashMap<S, usize> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (*s, i))
+            .collect();
+        let dual_constraints: Vec<Vec<f64>> = (0..self.states.len())
+            .map(|i| self.constraints.iter().map(|c| c[i]).collect())
+            .collect();
+        let solution = simplex_maximize(
+            &dual_constraints,
+            &vec![1.0; self.states.len()],
+            &self.rewards,
+            max_pivots,
+        )?;
+
+        // Every state is visited at least once, so it has an action with positive x.
+        let mut best_actions: Vec<Option<(A, f64)>> = vec![None; self.states.len()];
+        for ((state, action), x) in self.state_actions.iter().zip(solution.x.iter()) {
+            let best_action = &mut best_actions[state_indices[state]];
+            match best_action {
+                Some((_, best_x)) if *best_x >= *x => {}
+                _ => *best_action = Some((*action, *x)),
+            }
+        }
+
+        let policy = Policy {
+            states: self
+                .states
+                .iter()
+                .zip(best_actions)
+                .map(|(state, best_action)| {
+                    let actions = once((best_action.unwrap().0, 1.0)).collect();
+                    (*state, PolicyState { actions })
+                })
+                .collect(),
+        };
+        let state_values = self.states.iter().copied().zip(solution.duals).collect();
+        Some((policy, state_values))
+    }
+}
+
+// Finds the optimal policy and state values by linear programming.
+// Panics if the program can't be solved, which only happens if no policy has finite values.
+pub fn linear_programming<S, A>(env: &Env<S, A>, discount: f64) -> (Policy<S, A>, HashMap<S, f64>)
+where
+    S: Copy + Eq + Hash + Ord,
+    A: Copy + Eq + Hash + Ord,
+{
+    let program = MdpLinearProgram::from_env(env, discount);
+    let max_pivots = 100 * (program.states.len() + program.state_actions.len());
+    program
+        .solve(max_pivots)
+        .expect("The linear program is infeasible or unbounded")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplex_test() {
+        // Maximize 3x + 2y subject to x + y ≤ 4, x + 3y ≤ 6, x ≤ 3 (with slack variables).
+        let a = vec![
+            vec![1.0, 1.0, 1.0, 0.0, 0.0],
+            vec![1.0, 3.0, 0.0, 1.0, 0.0],
+            vec![1.0, 0.0, 0.0, 0.0, 1.0],
+        ];
+        let solution =
+            simplex_maximize(&a, &[4.0, 6.0, 3.0], &[3.0, 2.0, 0.0, 0.0, 0.0], 100).unwrap();
+
+        assert!((solution.objective - 11.0).abs() < 1e-9);
+        assert!((solution.x[0] - 3.0).abs() < 1e-9);
+        assert!((solution.x[1] - 1.0).abs() < 1e-9);
+        // The dual: minimize 4u + 6v + 3w subject to u + v + w ≥ 3, u + 3v ≥ 2, u, v, w ≥ 0.
+        let expected_duals = [2.0, 0.0, 1.0];
+        for (dual, expected) in solution.duals.iter().zip(expected_duals.iter()) {
+            assert!((dual - expected).abs() < 1e-9);
+        }
+
+        // x + y = 1 and x + y = 2 can't both hold.
+        let infeasible = simplex_maximize(
+            &[vec![1.0, 1.0], vec![1.0, 1.0]],
+            &[1.0, 2.0],
+            &[1.0, 1.0],
+            100,
+        );
+        assert!(infeasible.is_none());
+        // x - y = 1 with maximized y.
+        let unbounded = simplex_maximize(&[vec![1.0, -1.0]], &[1.0], &[0.0, 1.0], 100);
+        assert!(unbounded.is_none());
+    }
+
+    #[test]
+    fn linear_programming_test() {
+        // Two states: in state 0 action 0 yields 1 and stays, action 1 yields 5 and ends the
+        // episode. With γ = 0.9 staying is worth 10, so it's better.
+        let mut states = HashMap::new();
+        let mut actions = HashMap::new();
+        actions.insert(0, deterministic_action(0, 1.0));
+        actions.insert(1, deterministic_action(1, 5.0));
+        states.insert(0, StateActions { actions });
+        states.insert(1, StateActions::default());
+        let env = Env { states };
+
+        let (policy, state_values) = linear_programming(&env, 0.9);
+        assert_eq!(state_values.len(), 1);
+        assert!((state_values[&0] - 10.0).abs() < 1e-9);
+        assert_eq!(policy.states[&0].actions[&0], 1.0);
+
+        // With γ = 0.7 staying is only worth 3.33.
+        let (policy, state_values) = linear_programming(&env, 0.7);
+        assert!((state_values[&0] - 5.0).abs() < 1e-9);
+        assert_eq!(policy.states[&0].actions[&1], 1.0);
+    }
+}
@@ -1,6 +1,18 @@
 pub mod approximate;
+pub mod coarse;
+pub mod dense;
+pub mod dyna;
+pub mod environment;
 pub mod explicit;
+pub mod features;
+pub mod fourier;
+pub mod linear;
+pub mod lp;
 pub mod monte_carlo;
+pub mod policy_gradient;
+pub mod polynomial;
+mod priority_queue;
+pub mod rbf;
 pub mod td;
 pub mod tile;
 
@@ -42,9 +54,12 @@ impl From<ValueEstimate> for f64 {
     }
 }
 
-fn choose_random_key<K, V, F>(map: &HashMap<K, V>, mut f: F) -> K
+// Chooses a key from the map at random, with probabilities proportional to `f(value)`.
+// Keys are visited in sorted order, so the choice only depends on the state of `rng`.
+fn choose_random_key<K, V, R, F>(map: &HashMap<K, V>, rng: &mut R, mut f: F) -> K
 where
     K: Clone + Ord + Hash + Eq,
+    R: Rng,
     F: FnMut(&V) -> f64,
 {
     let total_probablity: f64 = map.iter().map(|(k, v)| f(v)).sum();
@@ -52,7 +67,7 @@ where
     let mut keys: Vec<&K> = map.keys().collect();
     keys.sort();
 
-    let mut remaining_probability = rand::random::<f64>() * total_probablity;
+    let mut remaining_probability = rng.gen::<f64>() * total_probablity;
     for k in keys.iter() {
         let probability = f(map.get(k).unwrap());
         if remaining_probability <= probability {
@@ -66,25 +81,35 @@ where
     panic!();
 }
 
+// Samples a normally distributed value (Box-Muller transform).
+pub fn sample_normal<R: Rng>(mean: f64, std_dev: f64, rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen::<f64>();
+    mean + std_dev * (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+// Builds a deterministic greedy policy from the action values.
 fn policy_from_state_action_values<S, A, V>(
     action_values: HashMap<S, HashMap<A, V>>,
 ) -> Policy<S, A>
 where
     S: Eq + Hash,
-    A: Eq + Hash,
+    A: Eq + Hash + Ord,
     V: Clone + Into<f64>,
 {
     Policy {
         states: action_values
             .into_iter()
             .map(|(state, actions)| {
+                // Ties are broken deterministically in favor of the largest action, so the
+                // returned policy doesn't depend on the hash map order. Callers that need all
+                // tied actions should use explicit::make_greedy_policy() instead.
                 let best_action = actions
                     .into_iter()
-                    .map(|(a, v)| {
-                        let f: f64 = v.into();
-                        (a, f)
+                    .map(|(a, v)| (a, v.into()))
+                    .max_by(|(a1, v1): &(A, f64), (a2, v2)| {
+                        v1.partial_cmp(v2).unwrap().then(a1.cmp(a2))
                     })
-                    .max_by(|(_, v1), (_, v2)| v1.partial_cmp(v2).unwrap())
                     .unwrap()
                     .0;
                 let policy_state_actions: HashMap<A, f64> = once((best_action, 1.0)).collect();
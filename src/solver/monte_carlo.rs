@@ -3,91 +3,221 @@ use std::default::Default;
 use std::fmt::Debug;
 use std::hash::Hash;
 
+use crate::solver::environment::*;
 use crate::solver::*;
 
-pub fn policy_from_explicit<S, A>(explicit_policy: Policy<S, A>) -> Box<dyn Fn(&S) -> A>
+// A sampled policy: returns an action for the given state, using the random generator if the
+// policy is stochastic.
+pub type PolicyFn<S, A, R> = Box<dyn Fn(&S, &mut R) -> A>;
+
+pub fn policy_from_explicit<S, A, R>(explicit_policy: Policy<S, A>) -> PolicyFn<S, A, R>
 where
     S: Eq + Hash + 'static,
     A: Eq + Hash + Clone + Ord + 'static,
+    R: Rng,
 {
-    Box::new(move |s| {
+    Box::new(move |s, rng| {
         // Choose action stochastically.
         let policy_state = explicit_policy.states.get(s).unwrap();
-        choose_random_key(&policy_state.actions, |v| *v)
+        choose_random_key(&policy_state.actions, rng, |v| *v)
     })
 }
 
-pub fn evaluate_policy<S, A, StartState, Policy, NextState>(
-    start_state: &StartState,
+// Generates a single episode following the given policy.
+// Returns the list of (state, action, reward) triples.
+fn generate_episode<E, R, Policy>(
+    env: &E,
     policy: &Policy,
-    next_state: &NextState,
+    rng: &mut R,
+) -> Vec<(E::State, E::Action, f64)>
+where
+    E: Environment,
+    R: Rng,
+    Policy: Fn(&E::State, &mut R) -> E::Action,
+{
+    let state = env.reset(rng);
+    let action = policy(&state, rng);
+    generate_episode_from(env, state, action, policy, rng)
+}
+
+// Generates a single episode starting with the given state and action, and then following the
+// given policy.
+fn generate_episode_from<E, R, Policy>(
+    env: &E,
+    mut state: E::State,
+    mut action: E::Action,
+    policy: &Policy,
+    rng: &mut R,
+) -> Vec<(E::State, E::Action, f64)>
+where
+    E: Environment,
+    R: Rng,
+    Policy: Fn(&E::State, &mut R) -> E::Action,
+{
+    let mut episode = Vec::new();
+    loop {
+        let (new_state, reward) = env.step(&state, &action, rng);
+        episode.push((state, action, reward));
+        if new_state.is_none() || is_truncated(env, episode.len() as u64) {
+            break;
+        }
+        state = new_state.unwrap();
+        action = policy(&state, rng);
+    }
+    episode
+}
+
+// Which visits to a state (or state-action pair) in an episode provide returns for its value.
+#[derive(Clone, Copy, Debug)]
+pub enum Visits {
+    // Only the first visit in the episode. The estimate is unbiased.
+    First,
+    // All visits in the episode. The estimate is biased, but consistent, i.e. the bias goes to 0
+    // as the number of episodes grows. It isn't faster: returns from the visits in the same
+    // episode are correlated, so the estimates can vary more between runs than with First.
+    Every,
+}
+
+// How the value estimate is updated with new returns.
+#[derive(Clone, Copy, Debug)]
+pub enum UpdateRule {
+    // Average of all the returns so far.
+    SampleAverage,
+    // V ← V + α∙(G - V). Gives more weight to recent returns, for nonstationary problems.
+    ConstantStepSize(f64),
+}
+
+impl UpdateRule {
+    fn update(&self, estimate: &mut ValueEstimate, returns: f64) {
+        match self {
+            UpdateRule::SampleAverage => estimate.update(returns),
+            UpdateRule::ConstantStepSize(alpha) => {
+                estimate.avg += alpha * (returns - estimate.avg);
+                estimate.count += 1;
+            }
+        }
+    }
+}
+
+// Returns whether each step of the episode provides returns under `visits`, where the visited
+// state (or state-action pair) is identified by `key`.
+fn visited_steps<S, A, K, Key>(episode: &[(S, A, f64)], visits: &Visits, key: Key) -> Vec<bool>
+where
+    K: Eq + Hash,
+    Key: Fn(&S, &A) -> K,
+{
+    let mut visited = HashSet::new();
+    episode
+        .iter()
+        .map(|(state, action, _)| match visits {
+            Visits::First => visited.insert(key(state, action)),
+            Visits::Every => true,
+        })
+        .collect()
+}
+
+// Updates the action values with the returns from the episode.
+fn update_action_values<S, A>(
+    action_values: &mut HashMap<S, HashMap<A, ValueEstimate>>,
+    episode: Vec<(S, A, f64)>,
+    visits: &Visits,
+    update_rule: &UpdateRule,
+    discount: f64,
+) where
+    S: Eq + Hash + Clone,
+    A: Eq + Hash + Clone,
+{
+    let visited_steps = visited_steps(&episode, visits, |s, a| (s.clone(), a.clone()));
+
+    // Go backwards, accumulating the returns.
+    let mut returns = 0.0;
+    for ((state, action, reward), visited) in episode.into_iter().zip(visited_steps).rev() {
+        returns = returns * discount + reward;
+        if visited {
+            update_rule.update(
+                action_values
+                    .entry(state)
+                    .or_default()
+                    .entry(action)
+                    .or_default(),
+                returns,
+            );
+        }
+    }
+}
+
+// Returns the action with the best value. Ties are resolved in favor of the largest action,
+// independent of the hash map order.
+fn best_action<A: Clone + Ord>(state_action_values: &HashMap<A, ValueEstimate>) -> A {
+    state_action_values
+        .iter()
+        .max_by(|(a1, e1), (a2, e2)| e1.avg.partial_cmp(&e2.avg).unwrap().then(a1.cmp(a2)))
+        .unwrap()
+        .0
+        .clone()
+}
+
+pub fn evaluate_policy<S, A, E, R, Policy>(
+    env: &E,
+    policy: &Policy,
+    visits: &Visits,
+    update_rule: &UpdateRule,
     discount: f64,
     iterations: u64,
+    rng: &mut R,
 ) -> HashMap<S, f64>
 where
     S: Eq + Hash + Debug + Clone,
-    A: Eq + Hash,
-    StartState: Fn() -> S,
-    Policy: Fn(&S) -> A,
-    NextState: Fn(&S, &A) -> (Option<S>, f64),
+    A: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Policy: Fn(&S, &mut R) -> A,
 {
     let mut state_values = HashMap::new();
 
     for _ in 0..iterations {
         // Generate a single episode.
-        let mut state = start_state();
-        let mut episode = Vec::new();
-        loop {
-            let action = policy(&state);
-            let (new_state, reward) = next_state(&state, &action);
-            episode.push((state, action, reward));
-            if new_state.is_none() {
-                break;
-            }
-            state = new_state.unwrap();
-        }
+        let episode = generate_episode(env, policy, rng);
+        let visited_steps = visited_steps(&episode, visits, |s, _| s.clone());
 
-        // Update state values from this episode.
-        let mut updated_states = HashSet::new();
+        // Update state values from this episode, going backwards and accumulating the returns.
         let mut returns = 0.0;
-        while !episode.is_empty() {
-            let (state, _action, reward) = episode.pop().unwrap();
+        for ((state, _action, reward), visited) in episode.into_iter().zip(visited_steps).rev() {
             returns = returns * discount + reward;
-            if updated_states.insert(state.clone()) {
-                state_values
-                    .entry(state)
-                    .or_insert_with(|| ValueEstimate::default())
-                    .update(returns);
+            if visited {
+                update_rule.update(state_values.entry(state).or_default(), returns);
             }
         }
     }
 
-    return state_values
+    state_values
         .into_iter()
         .map(|(state, estimation)| (state, estimation.avg))
-        .collect();
+        .collect()
 }
 
-pub fn find_policy<S, A, StartState, RandomAction, NextState>(
-    start_state: &StartState,
-    random_action: &RandomAction,
-    next_state: &NextState,
+// Monte Carlo control with ε-greedy exploration. The greedy action, both during the episodes and
+// in the returned policy, breaks ties in favor of the largest action.
+pub fn find_policy<S, A, E, R>(
+    env: &E,
+    visits: &Visits,
+    update_rule: &UpdateRule,
     discount: f64,
     exploration_fraction: f64,
     iterations: u64,
+    rng: &mut R,
 ) -> Policy<S, A>
 where
     S: Eq + Hash + Debug + Clone,
-    A: Eq + Hash + Debug + Clone,
-    StartState: Fn() -> S,
-    RandomAction: Fn(&S) -> A,
-    NextState: Fn(&S, &A) -> (Option<S>, f64),
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
 {
     let mut action_values: HashMap<S, HashMap<A, ValueEstimate>> = HashMap::new();
 
     for _ in 0..iterations {
         // Generate a single episode.
-        let mut state = start_state();
+        let mut state = env.reset(rng);
         let mut episode: Vec<(S, A, f64)> = Vec::new();
         loop {
             // Determine the next action.
@@ -95,71 +225,321 @@ where
                 // This state has already been visited -- choose best known action with
                 // (1 - exploration_fraction) probability or othewise choose random one.
                 Some(state_action_values) => {
-                    if rand::random::<f64>() <= exploration_fraction {
-                        random_action(&state)
+                    if rng.gen::<f64>() <= exploration_fraction {
+                        env.random_action(&state, rng)
                     } else {
-                        state_action_values
-                            .iter()
-                            .max_by(|(_, e1), (_, e2)| e1.avg.partial_cmp(&e2.avg).unwrap())
-                            .unwrap()
-                            .0
-                            .clone()
+                        best_action(state_action_values)
                     }
                 }
                 // No actions explored for this state -- choose action at random.
-                None => random_action(&state),
+                None => env.random_action(&state, rng),
             };
 
-            let (new_state, reward) = next_state(&state, &action);
+            let (new_state, reward) = env.step(&state, &action, rng);
             episode.push((state, action, reward));
-            if new_state.is_none() {
+            if new_state.is_none() || is_truncated(env, episode.len() as u64) {
                 break;
             }
             state = new_state.unwrap();
         }
 
         // Update state values from this episode.
+        update_action_values(&mut action_values, episode, visits, update_rule, discount);
+    }
+
+    policy_from_state_action_values(action_values)
+}
+
+// Monte Carlo control with exploring starts: every episode starts with the (state, action) pair
+// sampled by `exploring_start`, and then follows the greedy policy. The generator must give every
+// pair a non-zero probability. States that were never visited get a random action.
+pub fn find_policy_exploring_starts<S, A, E, R, ExploringStart>(
+    env: &E,
+    exploring_start: &ExploringStart,
+    visits: &Visits,
+    update_rule: &UpdateRule,
+    discount: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> Policy<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    ExploringStart: Fn(&mut R) -> (S, A),
+{
+    let mut action_values: HashMap<S, HashMap<A, ValueEstimate>> = HashMap::new();
+
+    for _ in 0..iterations {
+        // Generate a single episode, following the current greedy policy.
+        let (state, action) = exploring_start(rng);
+        let episode = generate_episode_from(
+            env,
+            state,
+            action,
+            &|s: &S, rng: &mut R| match action_values.get(s) {
+                Some(state_action_values) => best_action(state_action_values),
+                None => env.random_action(s, rng),
+            },
+            rng,
+        );
+
+        update_action_values(&mut action_values, episode, visits, update_rule, discount);
+    }
+
+    policy_from_state_action_values(action_values)
+}
+
+// On-policy first-visit Monte Carlo control for ε-soft policies: episodes follow ε-greedy policy
+// derived from the action values, and this ε-soft policy is returned, i.e. each visited state
+// gets probability ε/|A| for all actions plus 1 - ε for the best one.
+pub fn find_policy_epsilon_soft<S, A, E, R>(
+    env: &E,
+    visits: &Visits,
+    update_rule: &UpdateRule,
+    discount: f64,
+    exploration_fraction: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> Policy<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    let mut action_values: HashMap<S, HashMap<A, ValueEstimate>> = HashMap::new();
+
+    for _ in 0..iterations {
+        // Generate a single episode, following the current ε-greedy policy.
+        let episode = generate_episode(
+            env,
+            &|s: &S, rng: &mut R| match action_values.get(s) {
+                Some(state_action_values) if rng.gen::<f64>() > exploration_fraction => {
+                    best_action(state_action_values)
+                }
+                _ => env.random_action(s, rng),
+            },
+            rng,
+        );
+
+        update_action_values(&mut action_values, episode, visits, update_rule, discount);
+    }
+
+    Policy {
+        states: action_values
+            .into_iter()
+            .map(|(state, state_action_values)| {
+                let best_action = best_action(&state_action_values);
+                let actions = env.actions(&state);
+                let others_probability = exploration_fraction / actions.len() as f64;
+                let policy_state_actions = actions
+                    .into_iter()
+                    .map(|a| {
+                        let probability = if a == best_action {
+                            others_probability + 1.0 - exploration_fraction
+                        } else {
+                            others_probability
+                        };
+                        (a, probability)
+                    })
+                    .collect();
+                (
+                    state,
+                    PolicyState {
+                        actions: policy_state_actions,
+                    },
+                )
+            })
+            .collect(),
+    }
+}
+
+// Importance sampling estimator used by the off-policy methods. Returns G observed following the
+// behaviour policy b are scaled by the importance sampling ratio ρ = ∏π(A|S)/b(A|S) of the rest
+// of the episode, and the value is estimated as:
+pub enum ImportanceSampling {
+    // ∑ρ∙G / n. Unbiased, but can have very large (even infinite) variance.
+    Ordinary,
+    // ∑ρ∙G / ∑ρ. Biased (though the bias converges to zero), but with much lower variance.
+    Weighted,
+}
+
+// Incrementally updated importance sampling estimate.
+#[derive(Clone, Debug, Default)]
+struct ImportanceSamplingEstimate {
+    value: f64,
+    // Number of returns for ordinary importance sampling, sum of ratios for weighted.
+    weight: f64,
+}
+
+impl ImportanceSamplingEstimate {
+    fn update(&mut self, sampling: &ImportanceSampling, returns: f64, ratio: f64) {
+        match sampling {
+            ImportanceSampling::Ordinary => {
+                self.weight += 1.0;
+                self.value += (ratio * returns - self.value) / self.weight;
+            }
+            ImportanceSampling::Weighted => {
+                if ratio == 0.0 {
+                    return;
+                }
+                self.weight += ratio;
+                self.value += ratio / self.weight * (returns - self.value);
+            }
+        }
+    }
+}
+
+impl From<ImportanceSamplingEstimate> for f64 {
+    fn from(estimate: ImportanceSamplingEstimate) -> f64 {
+        estimate.value
+    }
+}
+
+// Estimates the state values of the target policy π from episodes generated by the behaviour
+// policy b. `behaviour_probability` and `target_probability` return b(a|s) and π(a|s). b must
+// cover π, i.e. b(a|s) > 0 wherever π(a|s) > 0. The returns are averaged as defined by
+// `sampling`, so there is no update rule.
+pub fn evaluate_policy_off_policy<S, A, E, R, Behaviour, BehaviourProbability, TargetProbability>(
+    env: &E,
+    behaviour: &Behaviour,
+    behaviour_probability: &BehaviourProbability,
+    target_probability: &TargetProbability,
+    visits: &Visits,
+    sampling: &ImportanceSampling,
+    discount: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> HashMap<S, f64>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Behaviour: Fn(&S, &mut R) -> A,
+    BehaviourProbability: Fn(&S, &A) -> f64,
+    TargetProbability: Fn(&S, &A) -> f64,
+{
+    let mut state_values: HashMap<S, ImportanceSamplingEstimate> = HashMap::new();
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let episode = generate_episode(env, behaviour, rng);
+        let visited_steps = visited_steps(&episode, visits, |s, _| s.clone());
+
+        // Update state values from this episode, going backwards and accumulating the returns
+        // and the importance sampling ratio.
         let mut returns = 0.0;
-        let mut observed_state_actions = HashMap::new();
-        while !episode.is_empty() {
-            let (state, action, reward) = episode.pop().unwrap();
+        let mut ratio = 1.0;
+        for ((state, action, reward), visited) in episode.into_iter().zip(visited_steps).rev() {
             returns = returns * discount + reward;
-            observed_state_actions.insert((state, action), returns);
-        }
-        for ((state, action), returns) in observed_state_actions {
-            action_values
-                .entry(state)
-                .or_insert_with(|| HashMap::default())
-                .entry(action)
-                .or_insert_with(|| ValueEstimate::default())
-                .update(returns);
+            ratio *= target_probability(&state, &action) / behaviour_probability(&state, &action);
+            if visited {
+                state_values
+                    .entry(state)
+                    .or_default()
+                    .update(sampling, returns, ratio);
+            }
         }
     }
 
-    policy_from_state_action_values(action_values)
+    state_values
+        .into_iter()
+        .map(|(state, estimate)| (state, estimate.value))
+        .collect()
 }
 
-pub fn run_simulation<S, A, StartState, Policy, NextState>(
-    start_state: &StartState,
-    policy: &Policy,
-    next_state: &NextState,
-) -> f64
+// Finds the optimal policy from episodes generated by the behaviour policy b, learning the action
+// values of the greedy target policy. Since the target policy is deterministic, the importance
+// sampling ratio for the earlier steps of the episode becomes zero as soon as the behaviour policy
+// takes an action different from the greedy one. b must have b(a|s) > 0 for all actions.
+// As in evaluate_policy_off_policy(), the returns are averaged as defined by `sampling`.
+pub fn find_policy_off_policy<S, A, E, R, Behaviour, BehaviourProbability>(
+    env: &E,
+    behaviour: &Behaviour,
+    behaviour_probability: &BehaviourProbability,
+    visits: &Visits,
+    sampling: &ImportanceSampling,
+    discount: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> Policy<S, A>
 where
     S: Eq + Hash + Debug + Clone,
     A: Eq + Hash + Debug + Clone + Ord,
-    StartState: Fn() -> S,
-    Policy: Fn(&S) -> A,
-    NextState: Fn(&S, &A) -> (Option<S>, f64),
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Behaviour: Fn(&S, &mut R) -> A,
+    BehaviourProbability: Fn(&S, &A) -> f64,
+{
+    let mut action_values: HashMap<S, HashMap<A, ImportanceSamplingEstimate>> = HashMap::new();
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let episode = generate_episode(env, behaviour, rng);
+        let visited_steps = visited_steps(&episode, visits, |s, a| (s.clone(), a.clone()));
+
+        // Update action values from this episode, going backwards. The ratio for Q(S, A) doesn't
+        // include the action A itself, as it's given.
+        let mut returns = 0.0;
+        let mut ratio = 1.0;
+        for ((state, action, reward), visited) in episode.into_iter().zip(visited_steps).rev() {
+            returns = returns * discount + reward;
+            if visited {
+                action_values
+                    .entry(state.clone())
+                    .or_default()
+                    .entry(action.clone())
+                    .or_default()
+                    .update(sampling, returns, ratio);
+            }
+
+            // Ties are resolved in favor of the largest action, same as in the returned policy.
+            let mut greedy_actions: Vec<(&A, f64)> = action_values
+                .get(&state)
+                .into_iter()
+                .flatten()
+                .map(|(a, estimate)| (a, estimate.value))
+                .collect();
+            greedy_actions.sort_by_key(|(a, _)| *a);
+            let greedy_action = greedy_actions
+                .into_iter()
+                .max_by(|(_, v1), (_, v2)| v1.partial_cmp(v2).unwrap())
+                .map(|(a, _)| a);
+            if greedy_action != Some(&action) {
+                ratio = 0.0;
+                if let ImportanceSampling::Weighted = sampling {
+                    // Zero ratio doesn't change weighted estimates, no need to go further.
+                    break;
+                }
+            } else {
+                ratio /= behaviour_probability(&state, &action);
+            }
+        }
+    }
+
+    policy_from_state_action_values(action_values)
+}
+
+pub fn run_simulation<E, R, Policy>(env: &E, policy: &Policy, rng: &mut R) -> f64
+where
+    E: Environment,
+    R: Rng,
+    Policy: Fn(&E::State, &mut R) -> E::Action,
 {
     let mut returns = 0.0;
-    let mut state = start_state();
+    let mut state = env.reset(rng);
+    let mut steps = 0;
     loop {
-        let action = policy(&state);
+        let action = policy(&state, rng);
 
         // Get to the next state and collect reward.
-        let (new_state, reward) = next_state(&state, &action);
+        let (new_state, reward) = env.step(&state, &action, rng);
         returns += reward;
-        if new_state.is_none() {
+        steps += 1;
+        if new_state.is_none() || is_truncated(env, steps) {
             break;
         }
         state = new_state.unwrap();
@@ -0,0 +1,850 @@
+use nalgebra::DVector;
+
+use crate::solver::approximate::feature_count;
+use crate::solver::environment::*;
+use crate::solver::*;
+
+// Computes the probabilities of the actions under the softmax policy with linear preferences
+//   π(a|s, θ) = exp(θ∙x(s, a)) / Σexp(θ∙x(s, b)).
+pub fn action_probabilities<S, A, StateActionFeatures>(
+    actions: &[A],
+    theta: &DVector<f64>,
+    state_action_features: &StateActionFeatures,
+    state: &S,
+) -> Vec<f64>
+where
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    assert!(!actions.is_empty());
+
+    let preferences: Vec<f64> = actions
+        .iter()
+        .map(|a| theta.dot(&DVector::from_vec(state_action_features(state, a))))
+        .collect();
+
+    // Subtract the max preference to avoid overflow, it doesn't change the probabilities.
+    let max_preference = preferences.iter().fold(f64::NEG_INFINITY, |a, b| a.max(*b));
+    let exps: Vec<f64> = preferences
+        .iter()
+        .map(|h| (h - max_preference).exp())
+        .collect();
+    let total: f64 = exps.iter().sum();
+    exps.into_iter().map(|e| e / total).collect()
+}
+
+// Chooses the action following the softmax policy (see action_probabilities()).
+pub fn softmax_action<S, A, R, StateActionFeatures>(
+    actions: &[A],
+    theta: &DVector<f64>,
+    state_action_features: &StateActionFeatures,
+    state: &S,
+    rng: &mut R,
+) -> A
+where
+    A: Clone,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let probabilities = action_probabilities(actions, theta, state_action_features, state);
+
+    let mut remaining_probability = rng.gen::<f64>();
+    for (action, probability) in actions.iter().zip(probabilities.iter()) {
+        if remaining_probability < *probability {
+            return action.clone();
+        }
+        remaining_probability -= probability;
+    }
+
+    // Can only get here because of rounding errors.
+    actions.last().unwrap().clone()
+}
+
+// Computes the eligibility vector of the softmax policy:
+//   ∇ln π(a|s, θ) = x(s, a) - Σπ(b|s, θ)∙x(s, b).
+fn eligibility<S, A, StateActionFeatures>(
+    actions: &[A],
+    theta: &DVector<f64>,
+    state_action_features: &StateActionFeatures,
+    state: &S,
+    action: &A,
+) -> DVector<f64>
+where
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let probabilities = action_probabilities(actions, theta, state_action_features, state);
+    let mut eligibility = DVector::from_vec(state_action_features(state, action));
+    for (b, probability) in actions.iter().zip(probabilities) {
+        eligibility -= probability * DVector::from_vec(state_action_features(state, b));
+    }
+    eligibility
+}
+
+// A single step of an episode.
+struct Step<S, A> {
+    state: S,
+    action: A,
+    reward: f64,
+}
+
+// Generates an episode following the softmax policy.
+fn generate_episode<S, A, E, R, StateActionFeatures>(
+    env: &E,
+    theta: &DVector<f64>,
+    state_action_features: &StateActionFeatures,
+    rng: &mut R,
+) -> Vec<Step<S, A>>
+where
+    S: Clone,
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let mut episode = Vec::new();
+    let mut state = env.reset(rng);
+    loop {
+        let action = softmax_action(
+            &env.actions(&state),
+            theta,
+            state_action_features,
+            &state,
+            rng,
+        );
+        let (maybe_next_state, reward) = env.step(&state, &action, rng);
+        episode.push(Step {
+            state,
+            action,
+            reward,
+        });
+
+        match maybe_next_state {
+            Some(next_state) if !is_truncated(env, episode.len() as u64) => state = next_state,
+            _ => return episode,
+        }
+    }
+}
+
+// Computes the returns G(t) from every step of the episode.
+fn episode_returns<S, A>(episode: &[Step<S, A>], discount: f64) -> Vec<f64> {
+    let mut returns = vec![0.0; episode.len()];
+    let mut g = 0.0;
+    for (t, step) in episode.iter().enumerate().rev() {
+        g = step.reward + discount * g;
+        returns[t] = g;
+    }
+    returns
+}
+
+// REINFORCE: Monte Carlo policy gradient. Learns the parameters θ of the softmax policy by
+// generating episodes following it, and updating after each episode for every step t:
+//   θ ← θ + α∙γᵗ∙G(t)∙∇ln π(A(t)|S(t), θ).
+pub fn reinforce<S, A, E, R, StateActionFeatures>(
+    env: &E,
+    state_action_features: &StateActionFeatures,
+    discount: f64,
+    alpha: f64,
+    iterations: usize,
+    rng: &mut R,
+) -> DVector<f64>
+where
+    S: Clone,
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    let mut theta = DVector::repeat(feature_count(env, state_action_features, rng), 0.0);
+
+    for _ in 0..iterations {
+        let episode = generate_episode(env, &theta, state_action_features, rng);
+        let returns = episode_returns(&episode, discount);
+
+        let mut discount_power = 1.0;
+        for (step, g) in episode.iter().zip(returns) {
+            let eligibility = eligibility(
+                &env.actions(&step.state),
+                &theta,
+                state_action_features,
+                &step.state,
+                &step.action,
+            );
+            theta += alpha * discount_power * g * eligibility;
+            discount_power *= discount;
+        }
+    }
+
+    theta
+}
+
+// REINFORCE with baseline: same as REINFORCE, but the returns are compared to the learned linear
+// state value v̂(S, w) = w∙x(S), which reduces the variance of the updates:
+//   δ = G(t) - v̂(S(t), w),
+//   w ← w + α_w∙δ∙∇v̂(S(t), w),
+//   θ ← θ + α_θ∙γᵗ∙δ∙∇ln π(A(t)|S(t), θ).
+// Returns the policy parameters θ and the state value weights w.
+pub fn reinforce_with_baseline<S, A, E, R, StateActionFeatures, StateFeatures>(
+    env: &E,
+    state_action_features: &StateActionFeatures,
+    state_features: &StateFeatures,
+    discount: f64,
+    alpha_theta: f64,
+    alpha_w: f64,
+    iterations: usize,
+    rng: &mut R,
+) -> (DVector<f64>, DVector<f64>)
+where
+    S: Clone,
+    A: Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+    StateFeatures: Fn(&S) -> Vec<f64>,
+{
+    let mut theta = DVector::repeat(feature_count(env, state_action_features, rng), 0.0);
+    let mut w = DVector::repeat(state_features(&env.reset(rng)).len(), 0.0);
+
+    for _ in 0..iterations {
+        let episode = generate_episode(env, &theta, state_action_features, rng);
+        let returns = episode_returns(&episode, discount);
+
+        let mut discount_power = 1.0;
+        for (step, g) in episode.iter().zip(returns) {
+            // For linear approximation, ∇v̂(S, w) = x(S).
+            let features = DVector::from_vec(state_features(&step.state));
+            let delta = g - w.dot(&features);
+            w += alpha_w * delta * features;
+
+            let eligibility = eligibility(
+                &env.actions(&step.state),
+                &theta,
+                state_action_features,
+                &step.state,
+                &step.action,
+            );
+            theta += alpha_theta * discount_power * delta * eligibility;
+            discount_power *= discount;
+        }
+    }
+
+    (theta, w)
+}
+
+// Stochastic policy π(a|s, θ) with parameters θ, learned by the actor-critic methods.
+pub trait ParameterizedPolicy<S, A> {
+    // Returns the number of parameters, given one of the states and the actions possible from it.
+    fn parameter_count(&self, state: &S, actions: &[A]) -> usize;
+
+    // Chooses one of the actions possible from the state.
+    fn choose_action<R: Rng>(
+        &self,
+        theta: &DVector<f64>,
+        state: &S,
+        actions: &[A],
+        rng: &mut R,
+    ) -> A;
+
+    // Returns the eligibility vector ∇ln π(a|s, θ).
+    fn eligibility(
+        &self,
+        theta: &DVector<f64>,
+        state: &S,
+        actions: &[A],
+        action: &A,
+    ) -> DVector<f64>;
+}
+
+// Softmax policy over discrete actions with linear preferences θ∙x(s, a) (see
+// action_probabilities()).
+pub struct SoftmaxPolicy<StateActionFeatures> {
+    state_action_features: StateActionFeatures,
+}
+
+impl<StateActionFeatures> SoftmaxPolicy<StateActionFeatures> {
+    pub fn new(state_action_features: StateActionFeatures) -> Self {
+        SoftmaxPolicy {
+            state_action_features,
+        }
+    }
+}
+
+impl<S, A, StateActionFeatures> ParameterizedPolicy<S, A> for SoftmaxPolicy<StateActionFeatures>
+where
+    A: Clone,
+    StateActionFeatures: Fn(&S, &A) -> Vec<f64>,
+{
+    fn parameter_count(&self, state: &S, actions: &[A]) -> usize {
+        (self.state_action_features)(state, &actions[0]).len()
+    }
+
+    fn choose_action<R: Rng>(
+        &self,
+        theta: &DVector<f64>,
+        state: &S,
+        actions: &[A],
+        rng: &mut R,
+    ) -> A {
+        softmax_action(actions, theta, &self.state_action_features, state, rng)
+    }
+
+    fn eligibility(
+        &self,
+        theta: &DVector<f64>,
+        state: &S,
+        actions: &[A],
+        action: &A,
+    ) -> DVector<f64> {
+        eligibility(actions, theta, &self.state_action_features, state, action)
+    }
+}
+
+// Gaussian policy over a continuous action, with linear mean and log standard deviation:
+//   μ(s, θ) = θ_μ∙x(s), σ(s, θ) = exp(θ_σ∙x(s)),
+// where θ = [θ_μ, θ_σ]. The list of possible actions is ignored.
+pub struct GaussianPolicy<StateFeatures> {
+    state_features: StateFeatures,
+}
+
+impl<StateFeatures> GaussianPolicy<StateFeatures> {
+    pub fn new(state_features: StateFeatures) -> Self {
+        GaussianPolicy { state_features }
+    }
+
+    // Returns the mean and the standard deviation of the action in the state.
+    pub fn mean_and_std_dev<S>(&self, theta: &DVector<f64>, state: &S) -> (f64, f64)
+    where
+        StateFeatures: Fn(&S) -> Vec<f64>,
+    {
+        let features = DVector::from_vec((self.state_features)(state));
+        let n = features.len();
+        assert_eq!(theta.len(), 2 * n);
+        let mean = theta.rows(0, n).dot(&features);
+        let std_dev = theta.rows(n, n).dot(&features).exp();
+        (mean, std_dev)
+    }
+}
+
+impl<S, StateFeatures> ParameterizedPolicy<S, f64> for GaussianPolicy<StateFeatures>
+where
+    StateFeatures: Fn(&S) -> Vec<f64>,
+{
+    fn parameter_count(&self, state: &S, _actions: &[f64]) -> usize {
+        2 * (self.state_features)(state).len()
+    }
+
+    fn choose_action<R: Rng>(
+        &self,
+        theta: &DVector<f64>,
+        state: &S,
+        _actions: &[f64],
+        rng: &mut R,
+    ) -> f64 {
+        let (mean, std_dev) = self.mean_and_std_dev(theta, state);
+        sample_normal(mean, std_dev, rng)
+    }
+
+    // ∇_μ ln π(a|s, θ) = (a - μ)/σ²∙x(s),
+    // ∇_σ ln π(a|s, θ) = [(a - μ)²/σ² - 1]∙x(s).
+    fn eligibility(
+        &self,
+        theta: &DVector<f64>,
+        state: &S,
+        _actions: &[f64],
+        action: &f64,
+    ) -> DVector<f64> {
+        let features = DVector::from_vec((self.state_features)(state));
+        let (mean, std_dev) = self.mean_and_std_dev(theta, state);
+        let variance = std_dev * std_dev;
+        let difference = action - mean;
+        let mean_factor = difference / variance;
+        let std_dev_factor = difference * difference / variance - 1.0;
+
+        let n = features.len();
+        DVector::from_iterator(
+            2 * n,
+            features
+                .iter()
+                .map(|x| mean_factor * x)
+                .chain(features.iter().map(|x| std_dev_factor * x)),
+        )
+    }
+}
+
+// Returns zero policy parameters θ and state value weights w.
+fn initial_parameters<S, A, E, R, P, StateFeatures>(
+    env: &E,
+    policy: &P,
+    state_features: &StateFeatures,
+    rng: &mut R,
+) -> (DVector<f64>, DVector<f64>)
+where
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    P: ParameterizedPolicy<S, A>,
+    StateFeatures: Fn(&S) -> Vec<f64>,
+{
+    let state = env.reset(rng);
+    let theta = DVector::repeat(policy.parameter_count(&state, &env.actions(&state)), 0.0);
+    let w = DVector::repeat(state_features(&state).len(), 0.0);
+    (theta, w)
+}
+
+// One-step actor-critic for episodic tasks: the policy (actor) is updated toward the one-step TD
+// error of the learned linear state value v̂(S, w) = w∙x(S) (critic):
+//   δ = R + γ∙v̂(S₊₁, w) - v̂(S, w),
+//   w ← w + α_w∙δ∙∇v̂(S, w),
+//   θ ← θ + α_θ∙γᵗ∙δ∙∇ln π(A|S, θ).
+// Returns the policy parameters θ and the state value weights w.
+pub fn actor_critic<S, A, E, R, P, StateFeatures>(
+    env: &E,
+    policy: &P,
+    state_features: &StateFeatures,
+    discount: f64,
+    alpha_theta: f64,
+    alpha_w: f64,
+    iterations: usize,
+    rng: &mut R,
+) -> (DVector<f64>, DVector<f64>)
+where
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    P: ParameterizedPolicy<S, A>,
+    StateFeatures: Fn(&S) -> Vec<f64>,
+{
+    actor_critic_lambda(
+        env,
+        policy,
+        state_features,
+        0.0,
+        0.0,
+        discount,
+        alpha_theta,
+        alpha_w,
+        iterations,
+        rng,
+    )
+}
+
+// Actor-critic with eligibility traces for episodic tasks: same as actor_critic(), but both the
+// actor and the critic are updated along their traces:
+//   z_w ← γ∙λ_w∙z_w + ∇v̂(S, w),
+//   z_θ ← γ∙λ_θ∙z_θ + γᵗ∙∇ln π(A|S, θ),
+//   w ← w + α_w∙δ∙z_w,
+//   θ ← θ + α_θ∙δ∙z_θ.
+// For λ_θ = λ_w = 0 this is one-step actor-critic.
+pub fn actor_critic_lambda<S, A, E, R, P, StateFeatures>(
+    env: &E,
+    policy: &P,
+    state_features: &StateFeatures,
+    lambda_theta: f64,
+    lambda_w: f64,
+    discount: f64,
+    alpha_theta: f64,
+    alpha_w: f64,
+    iterations: usize,
+    rng: &mut R,
+) -> (DVector<f64>, DVector<f64>)
+where
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    P: ParameterizedPolicy<S, A>,
+    StateFeatures: Fn(&S) -> Vec<f64>,
+{
+    let (mut theta, mut w) = initial_parameters(env, policy, state_features, rng);
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut features = DVector::from_vec(state_features(&state));
+        let mut z_theta = DVector::repeat(theta.len(), 0.0);
+        let mut z_w = DVector::repeat(w.len(), 0.0);
+        let mut discount_power = 1.0;
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            let actions = env.actions(&state);
+            let action = policy.choose_action(&theta, &state, &actions, rng);
+            let (maybe_next_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+
+            // Value of the final state is 0.
+            let next_features = maybe_next_state
+                .as_ref()
+                .map(|next_state| DVector::from_vec(state_features(next_state)));
+            let next_state_value = next_features.as_ref().map_or(0.0, |x| w.dot(x));
+            let td_error = reward + discount * next_state_value - w.dot(&features);
+
+            // For linear approximation, ∇v̂(S, w) = x(S).
+            z_w = discount * lambda_w * z_w + &features;
+            z_theta = discount * lambda_theta * z_theta
+                + discount_power * policy.eligibility(&theta, &state, &actions, &action);
+            w += alpha_w * td_error * &z_w;
+            theta += alpha_theta * td_error * &z_theta;
+            discount_power *= discount;
+
+            match (maybe_next_state, next_features) {
+                (Some(next_state), Some(next_features)) if !is_truncated(env, steps) => {
+                    state = next_state;
+                    features = next_features;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    (theta, w)
+}
+
+// One-step actor-critic for continuing tasks: same as actor_critic(), but with the differential
+// TD error, estimating the average reward R̄ too:
+//   δ = R - R̄ + v̂(S₊₁, w) - v̂(S, w),
+//   R̄ ← R̄ + β∙δ.
+// Runs for the given number of steps, starting a new episode only if a final state is reached.
+// Returns the policy parameters θ, the state value weights w and the average reward.
+pub fn continuing_actor_critic<S, A, E, R, P, StateFeatures>(
+    env: &E,
+    policy: &P,
+    state_features: &StateFeatures,
+    beta: f64,
+    alpha_theta: f64,
+    alpha_w: f64,
+    steps: u64,
+    rng: &mut R,
+) -> (DVector<f64>, DVector<f64>, f64)
+where
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    P: ParameterizedPolicy<S, A>,
+    StateFeatures: Fn(&S) -> Vec<f64>,
+{
+    continuing_actor_critic_lambda(
+        env,
+        policy,
+        state_features,
+        0.0,
+        0.0,
+        beta,
+        alpha_theta,
+        alpha_w,
+        steps,
+        rng,
+    )
+}
+
+// Actor-critic with eligibility traces for continuing tasks:
+//   δ = R - R̄ + v̂(S₊₁, w) - v̂(S, w),
+//   R̄ ← R̄ + β∙δ,
+//   z_w ← λ_w∙z_w + ∇v̂(S, w),
+//   z_θ ← λ_θ∙z_θ + ∇ln π(A|S, θ),
+//   w ← w + α_w∙δ∙z_w,
+//   θ ← θ + α_θ∙δ∙z_θ.
+pub fn continuing_actor_critic_lambda<S, A, E, R, P, StateFeatures>(
+    env: &E,
+    policy: &P,
+    state_features: &StateFeatures,
+    lambda_theta: f64,
+    lambda_w: f64,
+    beta: f64,
+    alpha_theta: f64,
+    alpha_w: f64,
+    steps: u64,
+    rng: &mut R,
+) -> (DVector<f64>, DVector<f64>, f64)
+where
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    P: ParameterizedPolicy<S, A>,
+    StateFeatures: Fn(&S) -> Vec<f64>,
+{
+    let (mut theta, mut w) = initial_parameters(env, policy, state_features, rng);
+    let mut average_reward = 0.0;
+    let mut z_theta = DVector::repeat(theta.len(), 0.0);
+    let mut z_w = DVector::repeat(w.len(), 0.0);
+
+    let mut state = env.reset(rng);
+    let mut features = DVector::from_vec(state_features(&state));
+    for _ in 0..steps {
+        let actions = env.actions(&state);
+        let action = policy.choose_action(&theta, &state, &actions, rng);
+        let (maybe_next_state, reward) = env.step(&state, &action, rng);
+
+        // If the final state is reached, its value is 0 and the next step is taken from a new
+        // episode.
+        let (next_state, next_value_factor) = match maybe_next_state {
+            Some(next_state) => (next_state, 1.0),
+            None => (env.reset(rng), 0.0),
+        };
+        let next_features = DVector::from_vec(state_features(&next_state));
+
+        let td_error =
+            reward - average_reward + next_value_factor * w.dot(&next_features) - w.dot(&features);
+        average_reward += beta * td_error;
+
+        // For linear approximation, ∇v̂(S, w) = x(S).
+        z_w = lambda_w * z_w + &features;
+        z_theta = lambda_theta * z_theta + policy.eligibility(&theta, &state, &actions, &action);
+        w += alpha_w * td_error * &z_w;
+        theta += alpha_theta * td_error * &z_theta;
+
+        state = next_state;
+        features = next_features;
+    }
+
+    (theta, w, average_reward)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::RngCore;
+
+    #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+    enum CorridorAction {
+        Left,
+        Right,
+    }
+
+    // Short corridor with switched actions from Example 13.1: states 0, 1, 2 and the goal 3,
+    // reward -1 per step. In state 1 the actions are reversed, and going left from state 0 leaves
+    // the agent in place. The features don't depend on the state, so the agent can't tell the
+    // states apart, and the best it can do is a stochastic policy.
+    fn short_corridor_env() -> impl Environment<State = i32, Action = CorridorAction> {
+        use CorridorAction as A;
+
+        ClosureEnvironment::new(
+            |_rng: &mut dyn RngCore| 0,
+            |_s: &i32| vec![A::Left, A::Right],
+            |s: &i32, a: &A, _rng: &mut dyn RngCore| {
+                let right = matches!((s, a), (1, A::Left) | (0, A::Right) | (2, A::Right));
+                let next_state = if right { s + 1 } else { (s - 1).max(0) };
+                if next_state == 3 {
+                    (None, -1.0)
+                } else {
+                    (Some(next_state), -1.0)
+                }
+            },
+        )
+        .with_max_steps(1000)
+    }
+
+    fn short_corridor_features(_s: &i32, a: &CorridorAction) -> Vec<f64> {
+        match a {
+            CorridorAction::Left => vec![0.0, 1.0],
+            CorridorAction::Right => vec![1.0, 0.0],
+        }
+    }
+
+    // Value of the start state when going right with probability p.
+    fn short_corridor_value(p: f64) -> f64 {
+        (2.0 * p - 4.0) / (p * (1.0 - p))
+    }
+
+    fn right_probability(theta: &DVector<f64>) -> f64 {
+        action_probabilities(
+            &[CorridorAction::Left, CorridorAction::Right],
+            theta,
+            &short_corridor_features,
+            &0,
+        )[1]
+    }
+
+    #[test]
+    fn softmax_policy_test() {
+        let actions = [CorridorAction::Left, CorridorAction::Right];
+        let theta = DVector::from_vec(vec![1.0, 0.0]);
+        let probabilities = action_probabilities(&actions, &theta, &short_corridor_features, &0);
+        let expected = 1.0 / (1.0 + (-1.0f64).exp());
+        assert!((probabilities[1] - expected).abs() < 1e-12);
+        assert!((probabilities[0] + probabilities[1] - 1.0).abs() < 1e-12);
+
+        // Eligibility is x(s, a) minus the expected features.
+        let e = eligibility(
+            &actions,
+            &theta,
+            &short_corridor_features,
+            &0,
+            &CorridorAction::Right,
+        );
+        assert!((e[0] - (1.0 - expected)).abs() < 1e-12);
+        assert!((e[1] + (1.0 - expected)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reinforce_short_corridor_test() {
+        let env = short_corridor_env();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Returns the value of the learned policy, averaged over several runs, since a single one
+        // is noisy.
+        let runs = 20;
+        let mut average_value = |learn: &dyn Fn(&mut StdRng) -> DVector<f64>| {
+            (0..runs)
+                .map(|_| short_corridor_value(right_probability(&learn(&mut rng))))
+                .sum::<f64>()
+                / runs as f64
+        };
+
+        // The initial policy goes right with probability 0.5, with value -12, while the optimal
+        // probability is about 0.59, with value -11.6.
+        let value =
+            average_value(&|rng| reinforce(&env, &short_corridor_features, 1.0, 2e-4, 500, rng));
+
+        // With a larger step size, plain REINFORCE is too noisy and can even end up with an
+        // (almost) deterministic policy that never reaches the goal, while the baseline keeps the
+        // updates small enough.
+        let alpha = 1e-3;
+        let large_step_value =
+            average_value(&|rng| reinforce(&env, &short_corridor_features, 1.0, alpha, 500, rng));
+        let baseline_value = average_value(&|rng| {
+            reinforce_with_baseline(
+                &env,
+                &short_corridor_features,
+                &|_s: &i32| vec![1.0],
+                1.0,
+                alpha,
+                1e-2,
+                500,
+                rng,
+            )
+            .0
+        });
+
+        println!(
+            "REINFORCE: {:.3}, with large step: {:.3}, with baseline: {:.3}",
+            value, large_step_value, baseline_value
+        );
+        assert!(value > -12.0);
+        assert!(baseline_value > -12.0);
+        assert!(baseline_value > large_step_value);
+    }
+
+    // One-step bandit with a continuous action and reward -(a - 2)², so the best action is 2.
+    fn continuous_bandit_env() -> impl Environment<State = (), Action = f64> {
+        ClosureEnvironment::new(
+            |_rng: &mut dyn RngCore| (),
+            |_s: &()| Vec::new(),
+            |_s: &(), a: &f64, _rng: &mut dyn RngCore| (None, -(a - 2.0) * (a - 2.0)),
+        )
+    }
+
+    #[test]
+    fn gaussian_policy_test() {
+        let policy = GaussianPolicy::new(|_s: &()| vec![1.0]);
+        let theta = DVector::from_vec(vec![1.0, 2.0f64.ln()]);
+        assert_eq!(
+            ParameterizedPolicy::<(), f64>::parameter_count(&policy, &(), &[]),
+            2
+        );
+
+        let (mean, std_dev) = policy.mean_and_std_dev(&theta, &());
+        assert!((mean - 1.0).abs() < 1e-12);
+        assert!((std_dev - 2.0).abs() < 1e-12);
+
+        // At a = μ + σ the mean is pushed by 1/σ and the standard deviation is unchanged.
+        let e = policy.eligibility(&theta, &(), &[], &3.0);
+        assert!((e[0] - 0.5).abs() < 1e-12);
+        assert!(e[1].abs() < 1e-12);
+    }
+
+    #[test]
+    fn actor_critic_continuous_bandit_test() {
+        let env = continuous_bandit_env();
+        let policy = GaussianPolicy::new(|_s: &()| vec![1.0]);
+        let state_features = |_s: &()| vec![1.0];
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let (theta, _) = actor_critic(
+            &env,
+            &policy,
+            &state_features,
+            1.0,
+            0.01,
+            0.1,
+            2000,
+            &mut rng,
+        );
+        let (mean, std_dev) = policy.mean_and_std_dev(&theta, &());
+        println!("Episodic: μ = {:.3}, σ = {:.3}", mean, std_dev);
+        assert!((mean - 2.0).abs() < 0.2);
+        assert!(std_dev < 1.0);
+
+        let (theta, _, average_reward) = continuing_actor_critic(
+            &env,
+            &policy,
+            &state_features,
+            0.1,
+            0.01,
+            0.1,
+            2000,
+            &mut rng,
+        );
+        let (mean, std_dev) = policy.mean_and_std_dev(&theta, &());
+        println!(
+            "Continuing: μ = {:.3}, σ = {:.3}, R̄ = {:.3}",
+            mean, std_dev, average_reward
+        );
+        assert!((mean - 2.0).abs() < 0.2);
+        assert!(std_dev < 1.0);
+        assert!(average_reward > -1.0);
+    }
+
+    // Continuing task where the choice in state 0 is rewarded 3 steps later: the agent walks
+    // through states 1 and 2 with a single action and gets reward 1 in state 3 only if it went
+    // right in state 0, and then starts over from state 0. The state remembers the choice.
+    fn delayed_reward_env() -> impl Environment<State = (i32, bool), Action = CorridorAction> {
+        ClosureEnvironment::new(
+            |_rng: &mut dyn RngCore| (0, false),
+            |s: &(i32, bool)| match s.0 {
+                0 => vec![CorridorAction::Left, CorridorAction::Right],
+                _ => vec![CorridorAction::Right],
+            },
+            |s: &(i32, bool), a: &CorridorAction, _rng: &mut dyn RngCore| match s {
+                (0, _) => (Some((1, *a == CorridorAction::Right)), 0.0),
+                (3, right) => (Some((0, false)), if *right { 1.0 } else { 0.0 }),
+                (position, right) => (Some((position + 1, *right)), 0.0),
+            },
+        )
+    }
+
+    #[test]
+    fn continuing_actor_critic_lambda_test() {
+        let env = delayed_reward_env();
+        let policy = SoftmaxPolicy::new(|_s: &(i32, bool), a: &CorridorAction| {
+            short_corridor_features(&0, a)
+        });
+
+        // The critic can't tell the states apart, so the TD error at the choice doesn't depend on
+        // the action, and only the actor traces can carry the delayed reward back to it.
+        let learn = |lambda_theta: f64, rng: &mut StdRng| {
+            let (theta, _, average_reward) = continuing_actor_critic_lambda(
+                &env,
+                &policy,
+                &|_s: &(i32, bool)| vec![1.0],
+                lambda_theta,
+                0.5,
+                0.01,
+                0.1,
+                0.01,
+                20000,
+                rng,
+            );
+            (right_probability(&theta), average_reward)
+        };
+
+        // Without the traces the policy just drifts, so average it over several runs.
+        let mut rng = StdRng::seed_from_u64(0);
+        let runs = 10;
+        let one_step_right = (0..runs).map(|_| learn(0.0, &mut rng).0).sum::<f64>() / runs as f64;
+        let (right, average_reward) = learn(0.9, &mut rng);
+        println!(
+            "Right probability: λ = 0: {:.3}, λ = 0.9: {:.3}, R̄ = {:.3}",
+            one_step_right, right, average_reward
+        );
+        assert!((one_step_right - 0.5).abs() < 0.2);
+        assert!(right > 0.9);
+        // Always going right gives reward 1 every 4 steps.
+        assert!((average_reward - 0.25).abs() < 0.05);
+    }
+}
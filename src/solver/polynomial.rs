@@ -0,0 +1,52 @@
+use crate::solver::features::*;
+use crate::solver::tile::Bounds;
+
+// Polynomial basis from section 9.5.1: for the state s normalized into [0, 1] in each of its k
+// dimensions, the features are the products
+//   x_i(s) = Π s_jᶜⁱʲ,
+// for all the exponent vectors cⁱ with components in 0..=order, i.e. (order + 1)ᵏ features.
+pub struct PolynomialBasis {
+    bounds: Vec<Bounds<f64>>,
+    exponents: Vec<Vec<u32>>,
+}
+
+impl PolynomialBasis {
+    pub fn new(bounds: Vec<Bounds<f64>>, order: u32) -> Self {
+        let exponents = integer_vectors(bounds.len(), order);
+        PolynomialBasis { bounds, exponents }
+    }
+}
+
+impl FeatureExtractor for PolynomialBasis {
+    fn feature_count(&self) -> usize {
+        self.exponents.len()
+    }
+
+    fn features(&self, point: &[f64]) -> Vec<f64> {
+        let s = normalize(&self.bounds, point);
+        self.exponents
+            .iter()
+            .map(|c| s.iter().zip(c).map(|(x, e)| x.powi(*e as i32)).product())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polynomial_basis_test() {
+        let basis = PolynomialBasis::new(vec![Bounds::new(0.0, 2.0), Bounds::new(-1.0, 1.0)], 2);
+        assert_eq!(basis.feature_count(), 9);
+
+        // s = (0.5, 0.25).
+        let features = basis.features(&[1.0, -0.5]);
+        assert_eq!(features[0], 1.0);
+        assert_eq!(features[1], 0.5);
+        assert_eq!(features[2], 0.25);
+        assert_eq!(features[3], 0.25);
+        assert_eq!(features[4], 0.125);
+        assert_eq!(features[8], 0.25 * 0.0625);
+    }
+}
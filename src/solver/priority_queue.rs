@@ -0,0 +1,113 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+struct Entry<K> {
+    priority: f64,
+    // Order of insertion: among equal priorities, earlier entries come first.
+    order: usize,
+    key: K,
+}
+
+impl<K> PartialEq for Entry<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K> Eq for Entry<K> {}
+
+impl<K> PartialOrd for Entry<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> Ord for Entry<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .partial_cmp(&other.priority)
+            .unwrap()
+            .then(other.order.cmp(&self.order))
+    }
+}
+
+// Max-priority queue of keys, used by prioritized sweeping. Each key is in the queue at most
+// once: pushing a key that is already there only raises its priority.
+pub struct PriorityQueue<K> {
+    heap: BinaryHeap<Entry<K>>,
+    // Current priorities of the queued keys. Heap entries with other priorities are stale.
+    priorities: HashMap<K, f64>,
+    order: usize,
+}
+
+impl<K: Eq + Hash + Clone> PriorityQueue<K> {
+    pub fn new() -> Self {
+        PriorityQueue {
+            heap: BinaryHeap::new(),
+            priorities: HashMap::new(),
+            order: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.priorities.is_empty()
+    }
+
+    pub fn push(&mut self, key: K, priority: f64) {
+        assert!(!priority.is_nan());
+        match self.priorities.get(&key) {
+            Some(p) if *p >= priority => return,
+            _ => {}
+        }
+
+        self.priorities.insert(key.clone(), priority);
+        self.heap.push(Entry {
+            priority,
+            order: self.order,
+            key,
+        });
+        self.order += 1;
+    }
+
+    // Removes the key with the highest priority and returns it with the priority.
+    pub fn pop(&mut self) -> Option<(K, f64)> {
+        while let Some(entry) = self.heap.pop() {
+            if self.priorities.get(&entry.key) == Some(&entry.priority) {
+                self.priorities.remove(&entry.key);
+                return Some((entry.key, entry.priority));
+            }
+        }
+        None
+    }
+}
+
+impl<K: Eq + Hash + Clone> Default for PriorityQueue<K> {
+    fn default() -> Self {
+        PriorityQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_queue_test() {
+        let mut queue = PriorityQueue::new();
+        queue.push("a", 1.0);
+        queue.push("b", 3.0);
+        queue.push("c", 2.0);
+        // Lower priority doesn't change anything, higher one moves the key up.
+        queue.push("b", 0.5);
+        queue.push("a", 4.0);
+        queue.push("d", 2.0);
+
+        assert_eq!(queue.pop(), Some(("a", 4.0)));
+        assert_eq!(queue.pop(), Some(("b", 3.0)));
+        assert_eq!(queue.pop(), Some(("c", 2.0)));
+        assert_eq!(queue.pop(), Some(("d", 2.0)));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+}
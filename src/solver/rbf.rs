@@ -0,0 +1,61 @@
+use crate::solver::features::*;
+use crate::solver::tile::Bounds;
+
+// Gaussian radial basis functions from section 9.5.5: for the state s normalized into [0, 1] in
+// each dimension, the features are
+//   x_i(s) = exp(-‖s - cⁱ‖² / (2∙σ²)),
+// where the centers cⁱ form a regular grid over [0, 1] in each dimension, including the bounds.
+pub struct RadialBasis {
+    bounds: Vec<Bounds<f64>>,
+    centers: Vec<Vec<f64>>,
+    width: f64,
+}
+
+impl RadialBasis {
+    // Creates `centers_per_dimension` centers in each dimension, with the width σ relative to the
+    // normalized range.
+    pub fn new(bounds: Vec<Bounds<f64>>, centers_per_dimension: usize, width: f64) -> Self {
+        let centers = grid_centers(bounds.len(), centers_per_dimension);
+        RadialBasis {
+            bounds,
+            centers,
+            width,
+        }
+    }
+}
+
+impl FeatureExtractor for RadialBasis {
+    fn feature_count(&self) -> usize {
+        self.centers.len()
+    }
+
+    fn features(&self, point: &[f64]) -> Vec<f64> {
+        let s = normalize(&self.bounds, point);
+        self.centers
+            .iter()
+            .map(|c| {
+                let distance2: f64 = s.iter().zip(c).map(|(x, c)| (x - c) * (x - c)).sum();
+                (-distance2 / (2.0 * self.width * self.width)).exp()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radial_basis_test() {
+        let basis = RadialBasis::new(vec![Bounds::new(0.0, 10.0), Bounds::new(0.0, 1.0)], 3, 0.5);
+        assert_eq!(basis.feature_count(), 9);
+
+        // The point is at the center (0.5, 0.5), and half way to the centers next to it.
+        let features = basis.features(&[5.0, 0.5]);
+        assert_eq!(features[4], 1.0);
+        let neighbour = (-0.25 / 0.5f64).exp();
+        assert!((features[1] - neighbour).abs() < 1e-12);
+        assert!((features[3] - neighbour).abs() < 1e-12);
+        assert!(features[0] < neighbour);
+    }
+}
@@ -1,59 +1,108 @@
+use crate::solver::environment::*;
 use crate::solver::*;
 
+// State-action values Q(S, A) learned by the TD solvers.
+pub type ActionValues<S, A> = HashMap<S, HashMap<A, f64>>;
+
+// Returns the values of all actions possible from the given state. Actions that were never taken
+// from this state have value 0.
+pub(crate) fn all_action_values<S, A, E>(
+    env: &E,
+    action_values: &HashMap<S, HashMap<A, f64>>,
+    state: &S,
+) -> HashMap<A, f64>
+where
+    S: Eq + Hash,
+    A: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+{
+    let state_action_values = action_values.get(state);
+    env.actions(state)
+        .into_iter()
+        .map(|a| {
+            let value = state_action_values.map_or(0.0, |av| *av.get(&a).unwrap_or(&0.0));
+            (a, value)
+        })
+        .collect()
+}
+
 // Determines the next action from given state following an ε-greedy policy derived from given
 // state-action values.
-fn soft_greedy_action<S, A, RandomAction>(
-    random_action: &RandomAction,
+pub(crate) fn soft_greedy_action<S, A, E, R>(
+    env: &E,
     action_values: &HashMap<S, HashMap<A, f64>>,
     state: &S,
     exploration_fraction: f64,
+    rng: &mut R,
 ) -> A
 where
     S: Eq + Hash,
-    A: Eq + Hash + Clone,
-    RandomAction: Fn(&S) -> A,
+    A: Eq + Hash + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
 {
-    let maybe_state_action_values = action_values.get(&state);
-
-    // If we never explored this state before, or if we pass the exploration check, choose the
-    // action at random.
-    if maybe_state_action_values.is_none() || rand::random::<f64>() <= exploration_fraction {
-        return random_action(&state);
+    // If we pass the exploration check, choose the action at random.
+    if rng.gen::<f64>() <= exploration_fraction {
+        return env.random_action(state, rng);
     }
 
-    // We have already explored this state -- pick the action with maximum value.
-    let state_action_values = maybe_state_action_values.unwrap();
+    // Otherwise pick the action with maximum value.
+    greedy_action(&all_action_values(env, action_values, state), rng)
+}
 
+// Chooses the action with maximum value. If there are multiple, chooses at random among them.
+fn greedy_action<A, R>(state_action_values: &HashMap<A, f64>, rng: &mut R) -> A
+where
+    A: Eq + Hash + Clone + Ord,
+    R: Rng,
+{
     // Find the maximum action value.
     let max_value = state_action_values
         .iter()
         .map(|(_, v)| v)
         .fold(f64::NEG_INFINITY, |a, b| a.max(*b));
 
-    // Find the actions with max action value (can be multiple!).
-    let greedy_actions: Vec<A> = state_action_values
+    // Find the actions with max action value (can be multiple!). Sort them, so that the random
+    // choice below doesn't depend on the hash map order.
+    let mut greedy_actions: Vec<A> = state_action_values
         .iter()
         .filter(|(_, v)| (*v - max_value).abs() < 1e-6)
-        .map(|(a, v)| a.clone())
+        .map(|(a, _)| a.clone())
         .collect();
+    greedy_actions.sort();
 
     // If there is only one "best" action, pick it. Otherwise, choose at random among all "best".
     assert!(greedy_actions.len() > 0);
     if greedy_actions.len() == 1 {
         greedy_actions[0].clone()
     } else {
-        greedy_actions[rand::random::<usize>() % greedy_actions.len()].clone()
+        greedy_actions[rng.gen_range(0..greedy_actions.len())].clone()
     }
 }
 
 // Computes the expected returns from a given state if following an ε-greedy policy derived from
 // given state-action values. State itself is not passed, only the values of the actions from this
 // state is given.
-fn expected_returns<A: Eq + Hash>(
+fn expected_returns<A: Eq + Hash + Ord>(
+    state_action_values: &HashMap<A, f64>,
+    exploration_fraction: f64,
+) -> f64 {
+    double_expected_returns(
+        state_action_values,
+        state_action_values,
+        exploration_fraction,
+    )
+}
+
+// Same as expected_returns(), but the ε-greedy policy is derived from `policy_action_values`,
+// while the returns are estimated from `state_action_values`. Both must have the same actions.
+fn double_expected_returns<A: Eq + Hash + Ord>(
+    policy_action_values: &HashMap<A, f64>,
     state_action_values: &HashMap<A, f64>,
     exploration_fraction: f64,
 ) -> f64 {
     assert!(!state_action_values.is_empty());
+    assert_eq!(policy_action_values.len(), state_action_values.len());
 
     // If there is just a single action, then it's probability is 1.
     if state_action_values.len() == 1 {
@@ -61,16 +110,14 @@ fn expected_returns<A: Eq + Hash>(
     }
 
     // Find the maximum action value.
-    let max_value = state_action_values
+    let max_value = policy_action_values
         .iter()
         .map(|(_, v)| v)
         .fold(f64::NEG_INFINITY, |a, b| a.max(*b));
+    let is_greedy = |a: &A| (policy_action_values[a] - max_value).abs() < 1e-6;
 
     // Find the number of actions with max action value (can be multiple!).
-    let greedy_actions_count = state_action_values
-        .iter()
-        .filter(|(_, v)| (*v - max_value).abs() < 1e-6)
-        .count();
+    let greedy_actions_count = policy_action_values.keys().filter(|a| is_greedy(a)).count();
 
     assert!(greedy_actions_count > 0);
 
@@ -78,53 +125,97 @@ fn expected_returns<A: Eq + Hash>(
     let greedy_probability =
         others_probability + (1.0 - exploration_fraction) / (greedy_actions_count as f64);
 
-    state_action_values
+    // Sum in the action order, so that the result doesn't depend on the hash map order.
+    let mut sorted_action_values: Vec<(&A, &f64)> = state_action_values.iter().collect();
+    sorted_action_values.sort_by_key(|(a, _)| *a);
+    sorted_action_values
         .iter()
-        .map(|(_, v)| match (*v - max_value).abs() < 1e-6 {
-            true => greedy_probability * v,
-            false => others_probability * v,
+        .map(|(a, v)| match is_greedy(a) {
+            true => greedy_probability * *v,
+            false => others_probability * *v,
         })
         .sum()
 }
 
-pub fn find_action_values_expected_sarsa<S, A, StartState, RandomAction, NextState>(
-    start_state: &StartState,
-    random_action: &RandomAction,
-    next_state: &NextState,
+// Computes the returns from a given state if following the greedy policy derived from given
+// state-action values.
+fn max_returns<A>(state_action_values: &HashMap<A, f64>) -> f64 {
+    assert!(!state_action_values.is_empty());
+
+    state_action_values
+        .values()
+        .fold(f64::NEG_INFINITY, |a, b| a.max(*b))
+}
+
+// Computes the returns from a given state if following the greedy policy derived from
+// `policy_action_values`, while the returns are estimated from `state_action_values`.
+// If there are multiple greedy actions, their values are averaged.
+fn double_max_returns<A: Eq + Hash + Ord>(
+    policy_action_values: &HashMap<A, f64>,
+    state_action_values: &HashMap<A, f64>,
+) -> f64 {
+    assert!(!state_action_values.is_empty());
+
+    let max_value = policy_action_values
+        .values()
+        .fold(f64::NEG_INFINITY, |a, b| a.max(*b));
+
+    // Sum in the action order, so that the result doesn't depend on the hash map order.
+    let mut greedy_actions: Vec<&A> = policy_action_values
+        .iter()
+        .filter(|(_, v)| (*v - max_value).abs() < 1e-6)
+        .map(|(a, _)| a)
+        .collect();
+    greedy_actions.sort();
+
+    greedy_actions
+        .iter()
+        .map(|a| state_action_values[*a])
+        .sum::<f64>()
+        / greedy_actions.len() as f64
+}
+
+// Learns action values following ε-greedy policy, where the value of the next state is estimated
+// from its action values by `returns` (independent of the action actually taken next).
+fn find_action_values_one_step<S, A, E, R, Returns>(
+    env: &E,
+    returns: &Returns,
     discount: f64,
     exploration_fraction: f64,
     alpha: f64,
     iterations: u64,
+    rng: &mut R,
 ) -> HashMap<S, HashMap<A, f64>>
 where
     S: Eq + Hash + Debug + Clone,
-    A: Eq + Hash + Debug + Clone,
-    StartState: Fn() -> S,
-    RandomAction: Fn(&S) -> A,
-    NextState: Fn(&S, &A) -> (Option<S>, f64),
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Returns: Fn(&HashMap<A, f64>) -> f64,
 {
     let mut action_values: HashMap<S, HashMap<A, f64>> = HashMap::new();
 
     for _ in 0..iterations {
         // Generate a single episode.
-        let mut state = start_state();
+        let mut state = env.reset(rng);
+        let mut steps = 0;
 
         // Go to the next state until a final state is reached.
         loop {
             // Determine the next action using ε-greedy policy from Q.
-            let action =
-                soft_greedy_action(random_action, &action_values, &state, exploration_fraction);
+            let action = soft_greedy_action(env, &action_values, &state, exploration_fraction, rng);
 
             let state_action_value = *action_values
                 .get(&state)
                 .map_or(&0.0, |av| av.get(&action).unwrap_or(&0.0));
 
             // Take the action and determine the next state and the reward.
-            let (maybe_new_state, reward) = next_state(&state, &action);
+            let (maybe_new_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
 
             // Update the state action value Q(S, A):
-            //   Q(S, A) ← Q(S, A) + α∙[R + γ∙∑π(a|S)∙Q(S₊₁, a) - Q(S, A)],
-            // where π(a|S) is the probability of taking action a under ε-greedy policy from Q.
+            //   Q(S, A) ← Q(S, A) + α∙[R + γ∙G(S₊₁) - Q(S, A)],
+            // where G(S₊₁) are the returns from S₊₁ estimated from Q(S₊₁, ∙).
 
             // If this is a final state, then formula above simplifies to:
             //   Q(S, A) ← Q(S, A) + α∙[R - Q(S, A)]
@@ -141,108 +232,925 @@ where
             let new_state = maybe_new_state.unwrap();
 
             // Compute the returns from state S₊₁.
-            let returns = action_values
-                .get(&new_state)
-                .map(|av| expected_returns(&av, exploration_fraction))
-                .unwrap_or(0.0);
+            let new_state_returns = returns(&all_action_values(env, &action_values, &new_state));
 
             // Now update Q(S, A).
-            let new_state_action_value =
-                state_action_value + alpha * (reward + discount * returns - state_action_value);
+            let new_state_action_value = state_action_value
+                + alpha * (reward + discount * new_state_returns - state_action_value);
             action_values
                 .entry(state)
                 .or_default()
                 .insert(action, new_state_action_value);
 
+            if is_truncated(env, steps) {
+                break;
+            }
+            state = new_state;
+        }
+    }
+
+    action_values
+}
+
+// Expected SARSA: the returns from S₊₁ are ∑π(a|S₊₁)∙Q(S₊₁, a), where π(a|S₊₁) is the probability
+// of taking action a under ε-greedy policy from Q.
+pub fn find_action_values_expected_sarsa<S, A, E, R>(
+    env: &E,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> HashMap<S, HashMap<A, f64>>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    find_action_values_one_step(
+        env,
+        &|av: &HashMap<A, f64>| expected_returns(av, exploration_fraction),
+        discount,
+        exploration_fraction,
+        alpha,
+        iterations,
+        rng,
+    )
+}
+
+// Q-learning: the returns from S₊₁ are max Q(S₊₁, a), i.e. the learned values are those of the
+// greedy policy, while the actions are taken following ε-greedy policy.
+pub fn find_action_values_q_learning<S, A, E, R>(
+    env: &E,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> HashMap<S, HashMap<A, f64>>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    find_action_values_one_step(
+        env,
+        &max_returns,
+        discount,
+        exploration_fraction,
+        alpha,
+        iterations,
+        rng,
+    )
+}
+
+// SARSA: the returns from S₊₁ are Q(S₊₁, A₊₁), where A₊₁ is the action that is taken next.
+pub fn find_action_values_sarsa<S, A, E, R>(
+    env: &E,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> HashMap<S, HashMap<A, f64>>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    let mut action_values: HashMap<S, HashMap<A, f64>> = HashMap::new();
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut action = soft_greedy_action(env, &action_values, &state, exploration_fraction, rng);
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            let state_action_value = *action_values
+                .get(&state)
+                .map_or(&0.0, |av| av.get(&action).unwrap_or(&0.0));
+
+            // Take the action and determine the next state and the reward.
+            let (maybe_new_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+
+            // Update the state action value Q(S, A):
+            //   Q(S, A) ← Q(S, A) + α∙[R + γ∙Q(S₊₁, A₊₁) - Q(S, A)].
+
+            // If this is a final state, then formula above simplifies to:
+            //   Q(S, A) ← Q(S, A) + α∙[R - Q(S, A)]
+            if maybe_new_state.is_none() {
+                let new_state_action_value =
+                    state_action_value + alpha * (reward - state_action_value);
+                action_values
+                    .entry(state)
+                    .or_default()
+                    .insert(action, new_state_action_value);
+                break;
+            }
+
+            let new_state = maybe_new_state.unwrap();
+
+            // Determine the next action using ε-greedy policy from Q.
+            let new_action =
+                soft_greedy_action(env, &action_values, &new_state, exploration_fraction, rng);
+            let new_state_action_value = *action_values
+                .get(&new_state)
+                .map_or(&0.0, |av| av.get(&new_action).unwrap_or(&0.0));
+
+            // Now update Q(S, A).
+            let updated_state_action_value = state_action_value
+                + alpha * (reward + discount * new_state_action_value - state_action_value);
+            action_values
+                .entry(state)
+                .or_default()
+                .insert(action, updated_state_action_value);
+
+            if is_truncated(env, steps) {
+                break;
+            }
             state = new_state;
+            action = new_action;
         }
     }
 
     action_values
 }
 
+// Learns two independent action value tables Q₁ and Q₂, following ε-greedy policy derived from
+// Q₁ + Q₂. On each step, one of the tables is chosen at random and updated, with the returns
+// from S₊₁ computed by `returns`(Qᵢ(S₊₁, ∙), Qⱼ(S₊₁, ∙)), where Qᵢ is the table being updated
+// (that determines the policy) and Qⱼ is the other table (that estimates the values).
+fn find_action_values_double<S, A, E, R, Returns>(
+    env: &E,
+    returns: &Returns,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> (ActionValues<S, A>, ActionValues<S, A>)
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Returns: Fn(&HashMap<A, f64>, &HashMap<A, f64>) -> f64,
+{
+    let mut action_values: [ActionValues<S, A>; 2] = [HashMap::new(), HashMap::new()];
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            // Determine the next action using ε-greedy policy from Q₁ + Q₂.
+            let action = if rng.gen::<f64>() <= exploration_fraction {
+                env.random_action(&state, rng)
+            } else {
+                let mut sum_action_values = all_action_values(env, &action_values[0], &state);
+                for (a, v) in all_action_values(env, &action_values[1], &state) {
+                    *sum_action_values.get_mut(&a).unwrap() += v;
+                }
+                greedy_action(&sum_action_values, rng)
+            };
+
+            // Take the action and determine the next state and the reward.
+            let (maybe_new_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+
+            // Choose the table to update.
+            let i = rng.gen_range(0..2);
+            let j = 1 - i;
+
+            let state_action_value = *action_values[i]
+                .get(&state)
+                .map_or(&0.0, |av| av.get(&action).unwrap_or(&0.0));
+
+            // Update the state action value Qᵢ(S, A):
+            //   Qᵢ(S, A) ← Qᵢ(S, A) + α∙[R + γ∙G(S₊₁) - Qᵢ(S, A)],
+            // where G(S₊₁) are the returns from S₊₁ estimated from Qⱼ(S₊₁, ∙) following the
+            // policy derived from Qᵢ(S₊₁, ∙). For final state, G(S₊₁) = 0.
+            let new_state_returns = match maybe_new_state {
+                Some(ref new_state) => returns(
+                    &all_action_values(env, &action_values[i], new_state),
+                    &all_action_values(env, &action_values[j], new_state),
+                ),
+                None => 0.0,
+            };
+            let new_state_action_value = state_action_value
+                + alpha * (reward + discount * new_state_returns - state_action_value);
+            action_values[i]
+                .entry(state)
+                .or_default()
+                .insert(action, new_state_action_value);
+
+            match maybe_new_state {
+                Some(new_state) if !is_truncated(env, steps) => state = new_state,
+                _ => break,
+            }
+        }
+    }
+
+    let [action_values_1, action_values_2] = action_values;
+    (action_values_1, action_values_2)
+}
+
+// Double Q-learning: Qᵢ(S₊₁, ∙) determines the greedy action, Qⱼ(S₊₁, ∙) estimates its value.
+// Returns both action value tables.
+pub fn find_action_values_double_q_learning<S, A, E, R>(
+    env: &E,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> (ActionValues<S, A>, ActionValues<S, A>)
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    find_action_values_double(
+        env,
+        &double_max_returns,
+        discount,
+        exploration_fraction,
+        alpha,
+        iterations,
+        rng,
+    )
+}
+
+// Double Expected SARSA: Qᵢ(S₊₁, ∙) determines the ε-greedy policy, Qⱼ(S₊₁, ∙) estimates the
+// values of the actions. Returns both action value tables.
+pub fn find_action_values_double_expected_sarsa<S, A, E, R>(
+    env: &E,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> (ActionValues<S, A>, ActionValues<S, A>)
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    find_action_values_double(
+        env,
+        &|policy_av: &HashMap<A, f64>, av: &HashMap<A, f64>| {
+            double_expected_returns(policy_av, av, exploration_fraction)
+        },
+        discount,
+        exploration_fraction,
+        alpha,
+        iterations,
+        rng,
+    )
+}
+
+// Computes the n-step returns from time τ:
+//   G(τ:τ+n) = R(τ+1) + γ∙R(τ+2) + ... + γⁿ⁻¹∙R(τ+n) + γⁿ∙V(S(τ+n)),
+// where rewards[i] is R(i+1). If the episode ends before τ+n, the sum stops at the last reward,
+// and `bootstrap` gets the index of the state whose value V(∙) should be added. It's not called
+// if the last reward led to a final state, i.e. there's no such state in `state_count`.
+fn n_step_returns<Bootstrap>(
+    rewards: &[f64],
+    state_count: usize,
+    tau: usize,
+    n: usize,
+    discount: f64,
+    bootstrap: Bootstrap,
+) -> f64
+where
+    Bootstrap: FnOnce(usize) -> f64,
+{
+    let horizon = rewards.len().min(tau + n);
+    let mut returns = 0.0;
+    let mut factor = 1.0;
+    for reward in rewards[tau..horizon].iter() {
+        returns += factor * reward;
+        factor *= discount;
+    }
+
+    if horizon < state_count {
+        returns += factor * bootstrap(horizon);
+    }
+
+    returns
+}
+
+// Estimates the state values of the policy using n-step TD: after each step, the value of the
+// state visited n steps ago is updated toward the n-step returns from it:
+//   V(S(τ)) ← V(S(τ)) + α∙[G(τ:τ+n) - V(S(τ))].
+// For n = 1 this is TD(0); for n larger than the episode length it's constant-α Monte Carlo.
+pub fn evaluate_policy_n_step<S, A, E, R, Policy>(
+    env: &E,
+    policy: &Policy,
+    n: usize,
+    discount: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> HashMap<S, f64>
+where
+    S: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Policy: Fn(&S, &mut R) -> A,
+{
+    let mut state_values = HashMap::new();
+    for _ in 0..iterations {
+        evaluate_episode_n_step(env, policy, &mut state_values, n, discount, alpha, rng);
+    }
+    state_values
+}
+
+// Generates a single episode following the policy and updates the state values with n-step TD
+// (see evaluate_policy_n_step()). States missing from `state_values` have value 0.
+pub fn evaluate_episode_n_step<S, A, E, R, Policy>(
+    env: &E,
+    policy: &Policy,
+    state_values: &mut HashMap<S, f64>,
+    n: usize,
+    discount: f64,
+    alpha: f64,
+    rng: &mut R,
+) where
+    S: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Policy: Fn(&S, &mut R) -> A,
+{
+    assert!(n > 0);
+
+    // States S(0), S(1), ... and rewards R(1), R(2), ... of the episode. The final state is not
+    // stored, so for the truncated episodes there's one more state than rewards.
+    let mut states = vec![env.reset(rng)];
+    let mut rewards = Vec::new();
+    let mut ended = false;
+
+    for t in 0.. {
+        if !ended {
+            let action = policy(&states[t], rng);
+            let (maybe_new_state, reward) = env.step(&states[t], &action, rng);
+            rewards.push(reward);
+            match maybe_new_state {
+                Some(new_state) => {
+                    states.push(new_state);
+                    ended = is_truncated(env, t as u64 + 1);
+                }
+                None => ended = true,
+            }
+        }
+
+        // Update the value of the state visited n steps ago: τ = t - n + 1.
+        if t + 1 < n {
+            continue;
+        }
+        let tau = t + 1 - n;
+        let returns = n_step_returns(&rewards, states.len(), tau, n, discount, |i| {
+            *state_values.get(&states[i]).unwrap_or(&0.0)
+        });
+        let state_value = state_values.entry(states[tau].clone()).or_default();
+        *state_value += alpha * (returns - *state_value);
+
+        if ended && tau + 1 == rewards.len() {
+            break;
+        }
+    }
+}
+
+// Learns action values following ε-greedy policy, updating the value of the state-action pair
+// taken n steps ago toward the n-step returns from it:
+//   Q(S(τ), A(τ)) ← Q(S(τ), A(τ)) + α∙[G(τ:τ+n) - Q(S(τ), A(τ))],
+// where the value of S(τ+n) is estimated by `returns`(Q(S(τ+n), ∙), A(τ+n)).
+fn find_action_values_n_step<S, A, E, R, Returns>(
+    env: &E,
+    returns: &Returns,
+    n: usize,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> ActionValues<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Returns: Fn(&HashMap<A, f64>, &A) -> f64,
+{
+    assert!(n > 0);
+
+    let mut action_values: ActionValues<S, A> = HashMap::new();
+
+    for _ in 0..iterations {
+        // States, actions and rewards of the episode. An action is chosen for every stored
+        // state, including the last state of a truncated episode.
+        let mut states = vec![env.reset(rng)];
+        let mut actions = vec![soft_greedy_action(
+            env,
+            &action_values,
+            &states[0],
+            exploration_fraction,
+            rng,
+        )];
+        let mut rewards = Vec::new();
+        let mut ended = false;
+
+        for t in 0.. {
+            if !ended {
+                let (maybe_new_state, reward) = env.step(&states[t], &actions[t], rng);
+                rewards.push(reward);
+                match maybe_new_state {
+                    Some(new_state) => {
+                        // Determine the next action using ε-greedy policy from Q.
+                        let new_action = soft_greedy_action(
+                            env,
+                            &action_values,
+                            &new_state,
+                            exploration_fraction,
+                            rng,
+                        );
+                        states.push(new_state);
+                        actions.push(new_action);
+                        ended = is_truncated(env, t as u64 + 1);
+                    }
+                    None => ended = true,
+                }
+            }
+
+            // Update the value of the state-action pair taken n steps ago: τ = t - n + 1.
+            if t + 1 < n {
+                continue;
+            }
+            let tau = t + 1 - n;
+            let new_state_action_value =
+                n_step_returns(&rewards, states.len(), tau, n, discount, |i| {
+                    returns(
+                        &all_action_values(env, &action_values, &states[i]),
+                        &actions[i],
+                    )
+                });
+            let state_action_value = action_values
+                .entry(states[tau].clone())
+                .or_default()
+                .entry(actions[tau].clone())
+                .or_default();
+            *state_action_value += alpha * (new_state_action_value - *state_action_value);
+
+            if ended && tau + 1 == rewards.len() {
+                break;
+            }
+        }
+    }
+
+    action_values
+}
+
+// n-step SARSA: the value of S(τ+n) is Q(S(τ+n), A(τ+n)), where A(τ+n) is the action taken.
+pub fn find_action_values_n_step_sarsa<S, A, E, R>(
+    env: &E,
+    n: usize,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> ActionValues<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    find_action_values_n_step(
+        env,
+        &|av: &HashMap<A, f64>, a: &A| av[a],
+        n,
+        discount,
+        exploration_fraction,
+        alpha,
+        iterations,
+        rng,
+    )
+}
+
+// n-step Expected SARSA: the value of S(τ+n) is ∑π(a|S(τ+n))∙Q(S(τ+n), a) under ε-greedy
+// policy from Q.
+pub fn find_action_values_n_step_expected_sarsa<S, A, E, R>(
+    env: &E,
+    n: usize,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> ActionValues<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    find_action_values_n_step(
+        env,
+        &|av: &HashMap<A, f64>, _: &A| expected_returns(av, exploration_fraction),
+        n,
+        discount,
+        exploration_fraction,
+        alpha,
+        iterations,
+        rng,
+    )
+}
+
+// Kind of eligibility traces used by TD(λ) and SARSA(λ). On each step all traces decay by γ∙λ,
+// and then the trace z of the visited state (or state-action pair) is increased:
+pub enum Trace {
+    // z ← z + 1.
+    Accumulating,
+    // z ← 1.
+    Replacing,
+    // z ← (1 - α)∙z + 1.
+    Dutch,
+}
+
+impl Trace {
+    // Decays all the traces by `decay` and increases the trace of the visited `key`.
+    fn visit<K: Eq + Hash>(&self, traces: &mut HashMap<K, f64>, key: K, decay: f64, alpha: f64) {
+        traces.values_mut().for_each(|z| *z *= decay);
+
+        let z = traces.entry(key).or_default();
+        *z = match self {
+            Trace::Accumulating => *z + 1.0,
+            Trace::Replacing => 1.0,
+            Trace::Dutch => (1.0 - alpha) * *z + 1.0,
+        };
+    }
+}
+
+// Estimates the state values of the policy using TD(λ) with eligibility traces: after each step,
+// all the states are updated proportionally to their traces:
+//   δ = R + γ∙V(S₊₁) - V(S),
+//   V(s) ← V(s) + α∙δ∙z(s).
+// For λ = 0 this is TD(0); for λ = 1 and accumulating traces it's close to constant-α Monte
+// Carlo.
+pub fn evaluate_policy_td_lambda<S, A, E, R, Policy>(
+    env: &E,
+    policy: &Policy,
+    trace: &Trace,
+    lambda: f64,
+    discount: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> HashMap<S, f64>
+where
+    S: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Policy: Fn(&S, &mut R) -> A,
+{
+    let mut state_values: HashMap<S, f64> = HashMap::new();
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut traces: HashMap<S, f64> = HashMap::new();
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            let action = policy(&state, rng);
+            let (maybe_new_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+
+            // Compute the TD error. Value of the final state is 0.
+            let state_value = *state_values.get(&state).unwrap_or(&0.0);
+            let new_state_value = maybe_new_state
+                .as_ref()
+                .map_or(0.0, |s| *state_values.get(s).unwrap_or(&0.0));
+            let td_error = reward + discount * new_state_value - state_value;
+
+            // Update the traces and all the states that have them.
+            trace.visit(&mut traces, state.clone(), discount * lambda, alpha);
+            for (s, z) in traces.iter() {
+                *state_values.entry(s.clone()).or_default() += alpha * td_error * z;
+            }
+
+            match maybe_new_state {
+                Some(new_state) if !is_truncated(env, steps) => state = new_state,
+                _ => break,
+            }
+        }
+    }
+
+    state_values
+}
+
+// SARSA(λ): learns action values following ε-greedy policy, updating all state-action pairs
+// proportionally to their eligibility traces:
+//   δ = R + γ∙Q(S₊₁, A₊₁) - Q(S, A),
+//   Q(s, a) ← Q(s, a) + α∙δ∙z(s, a).
+pub fn find_action_values_sarsa_lambda<S, A, E, R>(
+    env: &E,
+    trace: &Trace,
+    lambda: f64,
+    discount: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    iterations: u64,
+    rng: &mut R,
+) -> ActionValues<S, A>
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    let mut action_values: ActionValues<S, A> = HashMap::new();
+
+    for _ in 0..iterations {
+        // Generate a single episode.
+        let mut state = env.reset(rng);
+        let mut action = soft_greedy_action(env, &action_values, &state, exploration_fraction, rng);
+        let mut traces: HashMap<(S, A), f64> = HashMap::new();
+        let mut steps = 0;
+
+        // Go to the next state until a final state is reached.
+        loop {
+            let (maybe_new_state, reward) = env.step(&state, &action, rng);
+            steps += 1;
+
+            let state_action_value = *action_values
+                .get(&state)
+                .map_or(&0.0, |av| av.get(&action).unwrap_or(&0.0));
+
+            // Determine the next action using ε-greedy policy from Q, and compute the TD error.
+            // Value of the final state is 0.
+            let (td_error, next) = match maybe_new_state {
+                Some(new_state) => {
+                    let new_action = soft_greedy_action(
+                        env,
+                        &action_values,
+                        &new_state,
+                        exploration_fraction,
+                        rng,
+                    );
+                    let new_state_action_value = *action_values
+                        .get(&new_state)
+                        .map_or(&0.0, |av| av.get(&new_action).unwrap_or(&0.0));
+                    (
+                        reward + discount * new_state_action_value - state_action_value,
+                        Some((new_state, new_action)),
+                    )
+                }
+                None => (reward - state_action_value, None),
+            };
+
+            // Update the traces and all the state-action pairs that have them.
+            trace.visit(&mut traces, (state, action), discount * lambda, alpha);
+            for ((s, a), z) in traces.iter() {
+                *action_values
+                    .entry(s.clone())
+                    .or_default()
+                    .entry(a.clone())
+                    .or_default() += alpha * td_error * z;
+            }
+
+            match next {
+                Some((new_state, new_action)) if !is_truncated(env, steps) => {
+                    state = new_state;
+                    action = new_action;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    action_values
+}
+
+// Estimates the differential state values of the policy in a continuing task, together with the
+// average reward R̄, with differential TD(0):
+//   δ = R - R̄ + V(S₊₁) - V(S),
+//   R̄ ← R̄ + β∙δ,
+//   V(S) ← V(S) + α∙δ.
+// Runs for the given number of steps, starting a new episode only if a final state is reached.
+// Returns the state values and the average reward.
+pub fn evaluate_policy_differential_td<S, A, E, R, Policy>(
+    env: &E,
+    policy: &Policy,
+    beta: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> (HashMap<S, f64>, f64)
+where
+    S: Eq + Hash + Clone,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+    Policy: Fn(&S, &mut R) -> A,
+{
+    let mut state_values: HashMap<S, f64> = HashMap::new();
+    let mut average_reward = 0.0;
+
+    let mut state = env.reset(rng);
+    for _ in 0..steps {
+        let action = policy(&state, rng);
+        let (maybe_new_state, reward) = env.step(&state, &action, rng);
+
+        // Value of the final state is 0.
+        let state_value = *state_values.get(&state).unwrap_or(&0.0);
+        let new_state_value = maybe_new_state
+            .as_ref()
+            .map_or(0.0, |s| *state_values.get(s).unwrap_or(&0.0));
+        let td_error = reward - average_reward + new_state_value - state_value;
+        average_reward += beta * td_error;
+        *state_values.entry(state).or_default() += alpha * td_error;
+
+        state = maybe_new_state.unwrap_or_else(|| env.reset(rng));
+    }
+
+    (state_values, average_reward)
+}
+
+// Differential SARSA: learns differential action values of a continuing task following ε-greedy
+// policy, together with the average reward R̄:
+//   δ = R - R̄ + Q(S₊₁, A₊₁) - Q(S, A),
+//   R̄ ← R̄ + β∙δ,
+//   Q(S, A) ← Q(S, A) + α∙δ.
+// Runs for the given number of steps, starting a new episode only if a final state is reached.
+// Returns the action values and the average reward.
+pub fn find_action_values_differential_sarsa<S, A, E, R>(
+    env: &E,
+    beta: f64,
+    exploration_fraction: f64,
+    alpha: f64,
+    steps: u64,
+    rng: &mut R,
+) -> (ActionValues<S, A>, f64)
+where
+    S: Eq + Hash + Debug + Clone,
+    A: Eq + Hash + Debug + Clone + Ord,
+    E: Environment<State = S, Action = A>,
+    R: Rng,
+{
+    let mut action_values: ActionValues<S, A> = HashMap::new();
+    let mut average_reward = 0.0;
+
+    let mut state = env.reset(rng);
+    let mut action = soft_greedy_action(env, &action_values, &state, exploration_fraction, rng);
+    for _ in 0..steps {
+        let (maybe_new_state, reward) = env.step(&state, &action, rng);
+
+        let state_action_value = *action_values
+            .get(&state)
+            .map_or(&0.0, |av| av.get(&action).unwrap_or(&0.0));
+
+        // Determine the next action using ε-greedy policy from Q. If the final state is reached,
+        // its value is 0 and the next action is taken from a new episode.
+        let (new_state, new_action, new_state_action_value) = match maybe_new_state {
+            Some(new_state) => {
+                let new_action =
+                    soft_greedy_action(env, &action_values, &new_state, exploration_fraction, rng);
+                let new_state_action_value = *action_values
+                    .get(&new_state)
+                    .map_or(&0.0, |av| av.get(&new_action).unwrap_or(&0.0));
+                (new_state, new_action, new_state_action_value)
+            }
+            None => {
+                let new_state = env.reset(rng);
+                let new_action =
+                    soft_greedy_action(env, &action_values, &new_state, exploration_fraction, rng);
+                (new_state, new_action, 0.0)
+            }
+        };
+
+        let td_error = reward - average_reward + new_state_action_value - state_action_value;
+        average_reward += beta * td_error;
+        *action_values
+            .entry(state)
+            .or_default()
+            .entry(action)
+            .or_default() += alpha * td_error;
+
+        state = new_state;
+        action = new_action;
+    }
+
+    (action_values, average_reward)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
-    enum RandomWalkState {
-        A,
-        B,
-        C,
-        D,
-        E,
-    }
+    use rand::rngs::StdRng;
 
-    #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+    #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, PartialOrd, Ord)]
     enum RandomWalkAction {
         Left,
         Right,
     }
 
-    fn random_walk_start_state() -> RandomWalkState {
-        RandomWalkState::C
+    // Random walk over states 1..=state_count, starting in the middle. Stepping off the right end
+    // gives reward 1, stepping off the left end gives `left_reward`.
+    struct RandomWalk {
+        state_count: i32,
+        left_reward: f64,
     }
 
-    fn random_walk_random_action(state: &RandomWalkState) -> RandomWalkAction {
-        if rand::random::<f64>() < 0.5 {
-            RandomWalkAction::Left
-        } else {
-            RandomWalkAction::Right
+    impl RandomWalk {
+        // The 5-state walk from Example 6.2.
+        fn small() -> Self {
+            RandomWalk {
+                state_count: 5,
+                left_reward: 0.0,
+            }
+        }
+
+        // The 19-state walk from Example 7.1.
+        fn large() -> Self {
+            RandomWalk {
+                state_count: 19,
+                left_reward: -1.0,
+            }
+        }
+
+        fn states(&self) -> std::ops::RangeInclusive<i32> {
+            1..=self.state_count
+        }
+
+        // Value of the state under the random policy.
+        fn true_value(&self, state: i32) -> f64 {
+            self.left_reward
+                + (1.0 - self.left_reward) * state as f64 / (self.state_count + 1) as f64
         }
     }
 
-    fn random_walk_next_state(
-        state: &RandomWalkState,
-        action: &RandomWalkAction,
-    ) -> (Option<RandomWalkState>, f64) {
-        match action {
-            RandomWalkAction::Left => match state {
-                RandomWalkState::A => (None, 0.0),
-                RandomWalkState::B => (Some(RandomWalkState::A), 0.0),
-                RandomWalkState::C => (Some(RandomWalkState::B), 0.0),
-                RandomWalkState::D => (Some(RandomWalkState::C), 0.0),
-                RandomWalkState::E => (Some(RandomWalkState::D), 0.0),
-            },
-            RandomWalkAction::Right => match state {
-                RandomWalkState::A => (Some(RandomWalkState::B), 0.0),
-                RandomWalkState::B => (Some(RandomWalkState::C), 0.0),
-                RandomWalkState::C => (Some(RandomWalkState::D), 0.0),
-                RandomWalkState::D => (Some(RandomWalkState::E), 0.0),
-                RandomWalkState::E => (None, 1.0),
-            },
+    impl Environment for RandomWalk {
+        type State = i32;
+        type Action = RandomWalkAction;
+
+        fn reset<R: Rng>(&self, _rng: &mut R) -> i32 {
+            (self.state_count + 1) / 2
+        }
+
+        fn actions(&self, _state: &i32) -> Vec<RandomWalkAction> {
+            vec![RandomWalkAction::Left, RandomWalkAction::Right]
+        }
+
+        fn step<R: Rng>(
+            &self,
+            state: &i32,
+            action: &RandomWalkAction,
+            _rng: &mut R,
+        ) -> (Option<i32>, f64) {
+            match action {
+                RandomWalkAction::Left if *state == 1 => (None, self.left_reward),
+                RandomWalkAction::Left => (Some(state - 1), 0.0),
+                RandomWalkAction::Right if *state == self.state_count => (None, 1.0),
+                RandomWalkAction::Right => (Some(state + 1), 0.0),
+            }
         }
     }
 
     #[test]
     fn expected_sarsa_random_walk_test() {
         use RandomWalkAction as A;
-        use RandomWalkState as S;
 
+        let env = RandomWalk::small();
         let discount = 1.0;
         let exploration_fraction = 1.0; // Make it a random policy.
         let alpha = 0.1;
         let iterations = 1000;
         let action_values = find_action_values_expected_sarsa(
-            &random_walk_start_state,
-            &random_walk_random_action,
-            &random_walk_next_state,
+            &env,
             discount,
             exploration_fraction,
             alpha,
             iterations,
+            &mut StdRng::seed_from_u64(0),
         );
 
-        // Expected state values under random policy.
-        let expected_state_values = [
-            (S::A, 1.0 / 6.0),
-            (S::B, 2.0 / 6.0),
-            (S::C, 3.0 / 6.0),
-            (S::D, 4.0 / 6.0),
-            (S::E, 5.0 / 6.0),
-        ];
-        for (state, expected_state_value) in expected_state_values.iter() {
-            let state_action_values = action_values.get(state).unwrap();
+        // Expected state values under random policy are 1/6, 2/6, ..., 5/6.
+        for state in env.states() {
+            let expected_state_value = env.true_value(state);
+            let state_action_values = action_values.get(&state).unwrap();
             let left_value = state_action_values.get(&A::Left).unwrap_or(&0.0);
             let right_value = state_action_values.get(&A::Right).unwrap_or(&0.0);
             let avg = (left_value + right_value) * 0.5;
@@ -253,4 +1161,235 @@ mod tests {
             assert!((avg - expected_state_value).abs() < 1e-3);
         }
     }
+
+    #[test]
+    fn expected_sarsa_same_seed_test() {
+        let run = |seed| {
+            find_action_values_expected_sarsa(
+                &RandomWalk::small(),
+                1.0,
+                0.1,
+                0.1,
+                100,
+                &mut StdRng::seed_from_u64(seed),
+            )
+        };
+
+        // Same seed must produce bit-identical action values.
+        assert_eq!(run(1), run(1));
+    }
+
+    #[test]
+    fn q_learning_random_walk_test() {
+        use RandomWalkAction as A;
+
+        // Even with random behaviour, Q-learning learns the values of the greedy policy, which
+        // always goes right.
+        let env = RandomWalk::small();
+        let action_values =
+            find_action_values_q_learning(&env, 1.0, 1.0, 0.1, 1000, &mut StdRng::seed_from_u64(0));
+
+        for state in env.states() {
+            let right_value = action_values[&state][&A::Right];
+            assert!((right_value - 1.0).abs() < 1e-3, "{:?}", state);
+        }
+        assert!(action_values[&1][&A::Left].abs() < 1e-3);
+    }
+
+    #[test]
+    fn sarsa_random_walk_test() {
+        use RandomWalkAction as A;
+
+        // With random policy, SARSA learns the same values as Expected SARSA, but with more
+        // variance.
+        let env = RandomWalk::small();
+        let action_values =
+            find_action_values_sarsa(&env, 1.0, 1.0, 0.01, 10000, &mut StdRng::seed_from_u64(0));
+
+        for state in env.states() {
+            let expected_state_value = env.true_value(state);
+            let state_action_values = action_values.get(&state).unwrap();
+            let avg = (state_action_values[&A::Left] + state_action_values[&A::Right]) * 0.5;
+            assert!(
+                (avg - expected_state_value).abs() < 0.05,
+                "State {:?}: {:.03} (expected {:.03})",
+                state,
+                avg,
+                expected_state_value
+            );
+        }
+    }
+
+    // Average RMS error of n-step TD prediction on the 19-state random walk over the first 10
+    // episodes and 100 runs, as in Figure 7.2.
+    fn n_step_rms_error(n: usize, alpha: f64) -> f64 {
+        let env = RandomWalk::large();
+        let mut rng = StdRng::seed_from_u64(0);
+        let runs = 100;
+        let episodes = 10;
+
+        let mut total_error = 0.0;
+        for _ in 0..runs {
+            // Evaluate one episode at a time, to measure the error after each of them.
+            let mut state_values: HashMap<i32, f64> = HashMap::new();
+            for _ in 0..episodes {
+                evaluate_episode_n_step(
+                    &env,
+                    &|s: &i32, rng: &mut StdRng| env.random_action(s, rng),
+                    &mut state_values,
+                    n,
+                    1.0,
+                    alpha,
+                    &mut rng,
+                );
+                let squared_error: f64 = env
+                    .states()
+                    .map(|s| (state_values.get(&s).unwrap_or(&0.0) - env.true_value(s)).powi(2))
+                    .sum();
+                total_error += (squared_error / env.state_count as f64).sqrt();
+            }
+        }
+
+        total_error / (runs * episodes) as f64
+    }
+
+    #[test]
+    fn n_step_td_rms_error_test() {
+        let alphas: Vec<f64> = (1..=10).map(|i| i as f64 * 0.1).collect();
+
+        // Best (alpha, error) for each n.
+        let best: Vec<(f64, f64)> = [1, 2, 4, 8]
+            .iter()
+            .map(|n| {
+                let errors: Vec<f64> = alphas.iter().map(|a| n_step_rms_error(*n, *a)).collect();
+                println!("n = {}: {:.3?}", n, errors);
+                alphas
+                    .iter()
+                    .copied()
+                    .zip(errors)
+                    .min_by(|(_, e1), (_, e2)| e1.partial_cmp(e2).unwrap())
+                    .unwrap()
+            })
+            .collect();
+
+        // Intermediate n work best, and the larger n, the smaller the best alpha.
+        let (alpha_1, error_1) = best[0];
+        let (alpha_4, error_4) = best[2];
+        let (alpha_8, _) = best[3];
+        assert!(error_4 < error_1 - 0.05);
+        assert!((0.25..0.35).contains(&error_4), "{}", error_4);
+        assert!(alpha_4 < alpha_1);
+        assert!(alpha_8 <= alpha_4);
+    }
+
+    #[test]
+    fn n_step_sarsa_random_walk_test() {
+        use RandomWalkAction as A;
+
+        // With random policy, both n-step SARSA and n-step Expected SARSA learn the values of
+        // the random policy.
+        let env = RandomWalk::large();
+        let mut rng = StdRng::seed_from_u64(0);
+        let sarsa = find_action_values_n_step_sarsa(&env, 4, 1.0, 1.0, 0.02, 2000, &mut rng);
+        let expected_sarsa =
+            find_action_values_n_step_expected_sarsa(&env, 4, 1.0, 1.0, 0.02, 2000, &mut rng);
+
+        for action_values in [sarsa, expected_sarsa].iter() {
+            for state in env.states() {
+                let expected_state_value = env.true_value(state);
+                let avg =
+                    (action_values[&state][&A::Left] + action_values[&state][&A::Right]) * 0.5;
+                assert!(
+                    (avg - expected_state_value).abs() < 0.1,
+                    "State {:?}: {:.03} (expected {:.03})",
+                    state,
+                    avg,
+                    expected_state_value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn td_lambda_random_walk_test() {
+        let env = RandomWalk::large();
+        let policy = |s: &i32, rng: &mut StdRng| env.random_action(s, rng);
+
+        // With λ = 0, all kinds of traces give exactly TD(0).
+        let td_0 = evaluate_policy_n_step(
+            &env,
+            &policy,
+            1,
+            1.0,
+            0.1,
+            100,
+            &mut StdRng::seed_from_u64(0),
+        );
+        for trace in [Trace::Accumulating, Trace::Replacing, Trace::Dutch].iter() {
+            let td_lambda = evaluate_policy_td_lambda(
+                &env,
+                &policy,
+                trace,
+                0.0,
+                1.0,
+                0.1,
+                100,
+                &mut StdRng::seed_from_u64(0),
+            );
+            assert_eq!(td_lambda, td_0);
+        }
+
+        // With λ > 0, the values converge to the values of the random policy.
+        for trace in [Trace::Accumulating, Trace::Replacing, Trace::Dutch].iter() {
+            let state_values = evaluate_policy_td_lambda(
+                &env,
+                &policy,
+                trace,
+                0.8,
+                1.0,
+                0.01,
+                2000,
+                &mut StdRng::seed_from_u64(0),
+            );
+            let squared_error: f64 = env
+                .states()
+                .map(|s| (state_values[&s] - env.true_value(s)).powi(2))
+                .sum();
+            let rms_error = (squared_error / env.state_count as f64).sqrt();
+            assert!(rms_error < 0.05, "{}", rms_error);
+        }
+    }
+
+    #[test]
+    fn sarsa_lambda_random_walk_test() {
+        use RandomWalkAction as A;
+
+        // With random policy, SARSA(λ) learns the values of the random policy.
+        let env = RandomWalk::small();
+        for trace in [Trace::Accumulating, Trace::Replacing, Trace::Dutch].iter() {
+            let action_values = find_action_values_sarsa_lambda(
+                &env,
+                trace,
+                0.8,
+                1.0,
+                1.0,
+                0.01,
+                5000,
+                &mut StdRng::seed_from_u64(0),
+            );
+
+            for state in env.states() {
+                let expected_state_value = env.true_value(state);
+                let avg =
+                    (action_values[&state][&A::Left] + action_values[&state][&A::Right]) * 0.5;
+                assert!(
+                    (avg - expected_state_value).abs() < 0.05,
+                    "State {:?}: {:.03} (expected {:.03})",
+                    state,
+                    avg,
+                    expected_state_value
+                );
+            }
+        }
+    }
 }
@@ -1,3 +1,12 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::solver::approximate::SparseFeatures;
+use crate::solver::features::FeatureExtractor;
+
 // Left and right bound for an interval.
 // By convention, the right boundary is excluded, i.e. [min, max).
 pub struct Bounds<T> {
@@ -9,6 +18,8 @@ pub struct Bounds<T> {
 pub struct ContinuousDimension {
     bounds: Bounds<f64>,
     step_count: usize,
+    // Whether the coordinates wrap around modulo the range (e.g. for angles).
+    periodic: bool,
 }
 
 // Describes a tile partition of a continuous dimension.
@@ -16,6 +27,7 @@ struct ContinuousPartition {
     origin: f64,
     step_size: f64,
     step_count: usize,
+    periodic: bool,
 }
 
 // Describes a tile partition of an integer dimension.
@@ -24,16 +36,72 @@ struct IntegerPartition {
     step_count: usize,
 }
 
+impl ContinuousPartition {
+    // Returns the index of the tile containing the coordinate.
+    fn index(&self, x: f64) -> usize {
+        let step = (x - self.origin) / self.step_size;
+        if self.periodic {
+            // The period is exactly step_count tiles.
+            (step.floor() as i64).rem_euclid(self.step_count as i64) as usize
+        } else {
+            (step.max(0.0) as usize).min(self.step_count - 1)
+        }
+    }
+}
+
+impl IntegerPartition {
+    // Returns the index of the tile containing the coordinate.
+    fn index(&self, x: i32) -> usize {
+        ((x - self.origin).max(0) as usize).min(self.step_count - 1)
+    }
+}
+
 // One tiling of a state space.
 struct Tiling {
     continuous_partitions: Vec<ContinuousPartition>,
     integer_partitions: Vec<IntegerPartition>,
-    tile_count: usize,
+    // None if the number of tiles doesn't fit in usize.
+    tile_count: Option<usize>,
+}
+
+// How the tilings are offset from each other in the continuous dimensions.
+// The offsets are in [0, tile size) in each dimension.
+pub enum TilingOffsets {
+    // Tiling k is offset by k / count of the tile size in every dimension, i.e. the tilings are
+    // shifted along the diagonal. This produces diagonal artefacts in the generalization.
+    Uniform,
+    // Tiling k is offset by k∙(2i + 1) / count of the tile size (modulo the tile size) in the
+    // dimension i, i.e. along the displacement vector (1, 3, 5, ...), as recommended in section
+    // 9.5.4. `count` should be a power of 2, not less than 4 times the number of dimensions.
+    Asymmetric,
+    // Offsets are drawn uniformly at random, using the generator seeded with the given value.
+    Random(u64),
 }
 
 // Set of tilings of a state space.
 pub struct TilingSet {
     tilings: Vec<Tiling>,
+    // For each tiling, the offset of the origin from the lower bound in each continuous
+    // dimension.
+    offsets: Vec<Vec<f64>>,
+}
+
+// Fixed-size table that maps tile coordinates to feature indices, like the index hash table (IHT)
+// from Sutton's tile coding software. New coordinates get consecutive indices until the table is
+// full. After that, coordinates that are not in the table yet are hashed to one of the existing
+// indices, and the collision is counted.
+pub struct IndexHashTable {
+    size: usize,
+    indices: RefCell<HashMap<Vec<i64>, usize>>,
+    collision_count: Cell<usize>,
+}
+
+// Set of tilings of a state space, where the tiles are mapped to a fixed number of features by
+// an IndexHashTable, instead of numbering all the tiles. Used when the number of tiles is too
+// large to have a feature for each, e.g. for state spaces with many dimensions.
+pub struct HashedTilingSet {
+    tilings: TilingSet,
+    table: IndexHashTable,
 }
 
 impl<T> Bounds<T> {
@@ -42,11 +110,30 @@ impl<T> Bounds<T> {
     }
 }
 
+impl Bounds<f64> {
+    // Maps the value linearly so that min becomes 0 and max becomes 1.
+    pub fn normalize(&self, x: f64) -> f64 {
+        (x - self.min) / (self.max - self.min)
+    }
+}
+
 impl ContinuousDimension {
+    // Coordinates outside of [min, max) fall into the edge tiles.
     pub fn new(min: f64, max: f64, step_count: usize) -> Self {
         ContinuousDimension {
             bounds: Bounds::new(min, max),
             step_count: step_count,
+            periodic: false,
+        }
+    }
+
+    // Coordinates wrap around modulo max - min, i.e. min and max are the same point. The tiles of
+    // offset tilings that stick out past max wrap around to min as well.
+    pub fn periodic(min: f64, max: f64, step_count: usize) -> Self {
+        ContinuousDimension {
+            bounds: Bounds::new(min, max),
+            step_count,
+            periodic: true,
         }
     }
 }
@@ -70,6 +157,7 @@ impl Tiling {
                 origin: origin[i],
                 step_size: step_size[i],
                 step_count: continuous_dimensions[i].step_count,
+                periodic: continuous_dimensions[i].periodic,
             })
             .collect();
         let integer_partitions: Vec<IntegerPartition> = (0..i_len)
@@ -78,12 +166,11 @@ impl Tiling {
                 step_count: (integer_dimensions[i].max - integer_dimensions[i].min) as usize,
             })
             .collect();
-        let tile_count: usize = continuous_partitions
+        let tile_count = continuous_partitions
             .iter()
-            .fold(1, |acc, p| acc * p.step_count)
-            * integer_partitions
-                .iter()
-                .fold(1, |acc, p| acc * p.step_count);
+            .map(|p| p.step_count)
+            .chain(integer_partitions.iter().map(|p| p.step_count))
+            .try_fold(1usize, |acc, step_count| acc.checked_mul(step_count));
 
         Tiling {
             continuous_partitions: continuous_partitions,
@@ -92,72 +179,134 @@ impl Tiling {
         }
     }
 
+    // Returns the coordinates of a tile containing the given point in the state space: the tile
+    // index in each continuous dimension, followed by the index in each integer dimension.
+    fn get_coordinates(&self, pc: &[f64], pi: &[i32]) -> Vec<usize> {
+        assert_eq!(pc.len(), self.continuous_partitions.len());
+        assert_eq!(pi.len(), self.integer_partitions.len());
+
+        let continuous = self
+            .continuous_partitions
+            .iter()
+            .zip(pc)
+            .map(|(p, x)| p.index(*x));
+        let integer = self
+            .integer_partitions
+            .iter()
+            .zip(pi)
+            .map(|(p, x)| p.index(*x));
+        continuous.chain(integer).collect()
+    }
+
     // Returns an index of a tile containing the given point in the state space.
     fn get_tile(&self, pc: &[f64], pi: &[i32]) -> usize {
         assert_eq!(pc.len(), self.continuous_partitions.len());
         assert_eq!(pi.len(), self.integer_partitions.len());
-
-        let mut offset = 0;
+        assert!(
+            self.tile_count.is_some(),
+            "Number of tiles overflows usize, use HashedTilingSet instead"
+        );
 
         // By convention, assume feature layout to be such that when we iterate
         // over features, the coordinate in the first dimension (which is first
         // continuous dimension) changes the fastest and the cordinate in the
         // last dimension (which is the last integer dimension) changes the
-        // slowest.
-
-        for i in (0..pi.len()).rev() {
-            let p = &self.integer_partitions[i];
-            let x = pi[i];
-            let index = ((x - p.origin).max(0) as usize).min(p.step_count - 1);
-            offset = offset * p.step_count + index;
-        }
-
-        for i in (0..pc.len()).rev() {
-            let p = &self.continuous_partitions[i];
-            let x = pc[i];
-            let step = ((x - p.origin) / p.step_size).max(0.0);
-            let index = (step as usize).min(p.step_count - 1);
-            offset = offset * p.step_count + index;
-        }
+        // slowest. Since the total number of tiles fits in usize, so does the
+        // offset.
+        let offset = self
+            .integer_partitions
+            .iter()
+            .zip(pi)
+            .rev()
+            .fold(0, |offset, (p, x)| offset * p.step_count + p.index(*x));
+        self.continuous_partitions
+            .iter()
+            .zip(pc)
+            .rev()
+            .fold(offset, |offset, (p, x)| offset * p.step_count + p.index(*x))
+    }
+}
 
-        offset
+impl TilingOffsets {
+    // Returns the offsets of each of `count` tilings, for the given tile sizes.
+    fn offsets(&self, tile_size: &[f64], count: usize) -> Vec<Vec<f64>> {
+        let mut rng = match self {
+            TilingOffsets::Random(seed) => Some(StdRng::seed_from_u64(*seed)),
+            _ => None,
+        };
+        (0..count)
+            .map(|k| {
+                tile_size
+                    .iter()
+                    .enumerate()
+                    .map(|(i, size)| match self {
+                        TilingOffsets::Uniform => size * k as f64 / count as f64,
+                        TilingOffsets::Asymmetric => {
+                            size * ((k * (2 * i + 1)) % count) as f64 / count as f64
+                        }
+                        TilingOffsets::Random(_) => size * rng.as_mut().unwrap().gen::<f64>(),
+                    })
+                    .collect()
+            })
+            .collect()
     }
 }
 
 impl TilingSet {
     // Creates a tiling set for a N + M dimension space, where N is the number
     // of continuous dimensions and M is the number of integer ones.
+    // The tilings are offset uniformly (see TilingOffsets::Uniform).
     pub fn from_dimensions(
         continuous_dimensions: &Vec<ContinuousDimension>,
         integer_dimensions: &Vec<Bounds<i32>>,
         count: usize,
     ) -> Self {
-        let c_len = continuous_dimensions.len();
-
-        let mut tilings = Vec::new();
+        Self::from_dimensions_with_offsets(
+            continuous_dimensions,
+            integer_dimensions,
+            count,
+            &TilingOffsets::Uniform,
+        )
+    }
 
+    // Same as from_dimensions(), but with the given strategy of offsetting the tilings.
+    pub fn from_dimensions_with_offsets(
+        continuous_dimensions: &Vec<ContinuousDimension>,
+        integer_dimensions: &Vec<Bounds<i32>>,
+        count: usize,
+        offsets: &TilingOffsets,
+    ) -> Self {
         // Tile sizes for all continuous dimension
         // (for integer dimensions, tile size is always 1).
         let tile_size: Vec<f64> = continuous_dimensions
             .iter()
             .map(|d| (d.bounds.max - d.bounds.min) / (d.step_count) as f64)
             .collect();
-        let mut origin: Vec<f64> = continuous_dimensions.iter().map(|d| d.bounds.min).collect();
-        let offset_step: Vec<f64> = (0..c_len).map(|i| tile_size[i] / (count as f64)).collect();
+        let offsets = offsets.offsets(&tile_size, count);
 
-        for _ in 0..count {
-            tilings.push(Tiling::from_dimensions_and_origin(
-                continuous_dimensions,
-                integer_dimensions,
-                &origin,
-            ));
+        let tilings = offsets
+            .iter()
+            .map(|offset| {
+                let origin: Vec<f64> = continuous_dimensions
+                    .iter()
+                    .zip(offset)
+                    .map(|(d, o)| d.bounds.min + o)
+                    .collect();
+                Tiling::from_dimensions_and_origin(
+                    continuous_dimensions,
+                    integer_dimensions,
+                    &origin,
+                )
+            })
+            .collect();
 
-            for i in 0..origin.len() {
-                origin[i] += offset_step[i];
-            }
-        }
+        TilingSet { tilings, offsets }
+    }
 
-        TilingSet { tilings: tilings }
+    // Returns, for each tiling, the offset of its origin from the lower bound in each continuous
+    // dimension.
+    pub fn offsets(&self) -> &[Vec<f64>] {
+        &self.offsets
     }
 
     // Returns number of tilings.
@@ -165,25 +314,223 @@ impl TilingSet {
         self.tilings.len()
     }
 
+    fn has_integer_dimensions(&self) -> bool {
+        self.tilings
+            .iter()
+            .any(|t| !t.integer_partitions.is_empty())
+    }
+
     // Returns the total number of features.
+    // Panics if it doesn't fit in usize.
     pub fn tile_count(&self) -> usize {
-        self.tilings.iter().map(|t| t.tile_count).sum()
+        self.checked_tile_count()
+            .expect("Number of tiles overflows usize, use HashedTilingSet instead")
+    }
+
+    // Returns the total number of features, or None if it doesn't fit in usize.
+    pub fn checked_tile_count(&self) -> Option<usize> {
+        self.tilings
+            .iter()
+            .try_fold(0usize, |acc, t| acc.checked_add(t.tile_count?))
     }
 
     // Returns the indices of tiles (across all tilings, one tile per tiling)
     // that contain the given point in the state space.
     // Dimension of the return vector is equal to the number of tilings, or count().
+    // Panics if the total number of tiles doesn't fit in usize, even if each tiling does.
     pub fn get_tiles(&self, pc: &[f64], pi: &[i32]) -> Vec<usize> {
+        // Since the total number of tiles fits in usize, so do the offsets below.
+        assert!(
+            self.checked_tile_count().is_some(),
+            "Number of tiles overflows usize, use HashedTilingSet instead"
+        );
+
         let mut feature_indices = Vec::with_capacity(self.tilings.len());
         let mut index_offset = 0;
         for t in &self.tilings {
             feature_indices.push(t.get_tile(pc, pi) + index_offset);
-            index_offset += t.tile_count;
+            index_offset += t.tile_count.unwrap();
         }
         feature_indices
     }
 }
 
+// Tile coding as a feature extractor, for the tiling sets without integer dimensions.
+impl FeatureExtractor for TilingSet {
+    fn feature_count(&self) -> usize {
+        self.tile_count()
+    }
+
+    fn features(&self, point: &[f64]) -> Vec<f64> {
+        self.sparse_features(point).to_dense(self.tile_count())
+    }
+
+    fn sparse_features(&self, point: &[f64]) -> SparseFeatures {
+        assert!(
+            !self.has_integer_dimensions(),
+            "FeatureExtractor only supports tiling sets without integer dimensions, use get_tiles()"
+        );
+        SparseFeatures::binary(self.get_tiles(point, &[]))
+    }
+}
+
+// FNV-1a hash of the coordinates. Unlike the hashers from std, it is guaranteed to be the same on
+// every run.
+fn hash_coordinates(coordinates: &[i64]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    coordinates
+        .iter()
+        .flat_map(|c| c.to_le_bytes())
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(PRIME)
+        })
+}
+
+impl IndexHashTable {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+        IndexHashTable {
+            size,
+            indices: RefCell::new(HashMap::new()),
+            collision_count: Cell::new(0),
+        }
+    }
+
+    // Returns the number of indices, i.e. the number of features.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Returns the number of coordinates that got their own index.
+    pub fn len(&self) -> usize {
+        self.indices.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.size
+    }
+
+    // Returns the number of times coordinates were hashed to an index that was already taken.
+    pub fn collision_count(&self) -> usize {
+        self.collision_count.get()
+    }
+
+    // Returns the index for the given coordinates, adding them to the table if there is room.
+    pub fn get_index(&self, coordinates: &[i64]) -> usize {
+        let mut indices = self.indices.borrow_mut();
+        if let Some(index) = indices.get(coordinates) {
+            return *index;
+        }
+
+        let count = indices.len();
+        if count >= self.size {
+            self.collision_count.set(self.collision_count.get() + 1);
+            (hash_coordinates(coordinates) % self.size as u64) as usize
+        } else {
+            indices.insert(coordinates.to_vec(), count);
+            count
+        }
+    }
+}
+
+impl HashedTilingSet {
+    // Creates a tiling set for a N + M dimension space (see TilingSet::from_dimensions), with
+    // `size` features.
+    pub fn from_dimensions(
+        continuous_dimensions: &Vec<ContinuousDimension>,
+        integer_dimensions: &Vec<Bounds<i32>>,
+        count: usize,
+        size: usize,
+    ) -> Self {
+        Self::from_dimensions_with_offsets(
+            continuous_dimensions,
+            integer_dimensions,
+            count,
+            &TilingOffsets::Uniform,
+            size,
+        )
+    }
+
+    // Same as from_dimensions(), but with the given strategy of offsetting the tilings.
+    pub fn from_dimensions_with_offsets(
+        continuous_dimensions: &Vec<ContinuousDimension>,
+        integer_dimensions: &Vec<Bounds<i32>>,
+        count: usize,
+        offsets: &TilingOffsets,
+        size: usize,
+    ) -> Self {
+        HashedTilingSet {
+            tilings: TilingSet::from_dimensions_with_offsets(
+                continuous_dimensions,
+                integer_dimensions,
+                count,
+                offsets,
+            ),
+            table: IndexHashTable::new(size),
+        }
+    }
+
+    // Returns number of tilings.
+    pub fn count(&self) -> usize {
+        self.tilings.count()
+    }
+
+    // Returns the total number of features.
+    pub fn tile_count(&self) -> usize {
+        self.table.size()
+    }
+
+    // See TilingSet::offsets().
+    pub fn offsets(&self) -> &[Vec<f64>] {
+        self.tilings.offsets()
+    }
+
+    pub fn table(&self) -> &IndexHashTable {
+        &self.table
+    }
+
+    // Returns the indices of features (one per tiling) for the tiles that contain the given point
+    // in the state space. Tiles of different tilings never share coordinates, but can collide
+    // once the table is full.
+    pub fn get_tiles(&self, pc: &[f64], pi: &[i32]) -> Vec<usize> {
+        self.tilings
+            .tilings
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let coordinates: Vec<i64> = std::iter::once(i as i64)
+                    .chain(t.get_coordinates(pc, pi).into_iter().map(|c| c as i64))
+                    .collect();
+                self.table.get_index(&coordinates)
+            })
+            .collect()
+    }
+}
+
+// Same as for TilingSet.
+impl FeatureExtractor for HashedTilingSet {
+    fn feature_count(&self) -> usize {
+        self.tile_count()
+    }
+
+    fn features(&self, point: &[f64]) -> Vec<f64> {
+        self.sparse_features(point).to_dense(self.tile_count())
+    }
+
+    fn sparse_features(&self, point: &[f64]) -> SparseFeatures {
+        assert!(
+            !self.tilings.has_integer_dimensions(),
+            "FeatureExtractor only supports tiling sets without integer dimensions, use get_tiles()"
+        );
+        SparseFeatures::binary(self.get_tiles(point, &[]))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -259,4 +606,253 @@ mod tests {
         // 0 and 1, but tile 0 on tiling 2.
         assert_eq!(tilings.get_tiles(&[1.5], &[0]), vec![1, 51, 100]);
     }
+
+    #[test]
+    #[should_panic(expected = "without integer dimensions")]
+    fn feature_extractor_integer_dimensions() {
+        let c1 = ContinuousDimension::new(0.0, 10.0, 10);
+        let tilings = TilingSet::from_dimensions(&vec![c1], &vec![Bounds::new(0, 5)], 1);
+        tilings.features(&[0.0]);
+    }
+
+    #[test]
+    fn tile_count_overflow() {
+        let dimensions = (0..8)
+            .map(|_| ContinuousDimension::new(0.0, 1.0, 1 << 10))
+            .collect();
+        let tilings = TilingSet::from_dimensions(&dimensions, &Vec::new(), 2);
+        assert_eq!(tilings.checked_tile_count(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "use HashedTilingSet instead")]
+    fn tile_count_sum_overflow() {
+        // Each tiling has 2⁶² tiles, but four of them together have 2⁶⁴.
+        let dimensions = (0..2)
+            .map(|_| ContinuousDimension::new(0.0, 1.0, 1 << 31))
+            .collect();
+        let tilings = TilingSet::from_dimensions(&dimensions, &Vec::new(), 4);
+        assert!(tilings.tilings.iter().all(|t| t.tile_count.is_some()));
+        assert_eq!(tilings.checked_tile_count(), None);
+        tilings.get_tiles(&[0.5, 0.5], &[]);
+    }
+
+    #[test]
+    fn hashed_tiling_matches_tiling() {
+        let c1 = ContinuousDimension::new(0.0, 10.0, 10);
+        let i1 = Bounds::new(0, 5);
+        let tilings = TilingSet::from_dimensions(&vec![c1], &vec![i1], 3);
+        let c1 = ContinuousDimension::new(0.0, 10.0, 10);
+        let i1 = Bounds::new(0, 5);
+        let hashed = HashedTilingSet::from_dimensions(&vec![c1], &vec![i1], 3, 150);
+        assert_eq!(hashed.count(), 3);
+        assert_eq!(hashed.tile_count(), 150);
+
+        // With enough room, the hashed tiles are a renumbering of the ordinary ones.
+        let mut renumbering = HashMap::new();
+        for x in 0..100 {
+            for i in 0..5 {
+                let pc = [x as f64 / 10.0];
+                for (tile, hashed_tile) in tilings
+                    .get_tiles(&pc, &[i])
+                    .into_iter()
+                    .zip(hashed.get_tiles(&pc, &[i]))
+                {
+                    assert_eq!(*renumbering.entry(tile).or_insert(hashed_tile), hashed_tile);
+                }
+            }
+        }
+        assert_eq!(renumbering.len(), 150);
+        assert!(hashed.table().is_full());
+        assert_eq!(hashed.table().collision_count(), 0);
+    }
+
+    #[test]
+    fn hashed_tiling_many_dimensions() {
+        let dimensions = (0..8)
+            .map(|_| ContinuousDimension::new(0.0, 1.0, 1 << 10))
+            .collect();
+        let hashed = HashedTilingSet::from_dimensions(&dimensions, &Vec::new(), 4, 64);
+
+        // The same point always gets the same features.
+        let point = [0.5; 8];
+        let tiles = hashed.get_tiles(&point, &[]);
+        assert_eq!(tiles.len(), 4);
+        assert!(tiles.iter().all(|t| *t < 64));
+        assert_eq!(hashed.get_tiles(&point, &[]), tiles);
+        assert_eq!(hashed.table().len(), 4);
+        assert_eq!(hashed.table().collision_count(), 0);
+
+        // Once the table is full, new tiles collide with the existing ones, deterministically.
+        for i in 0..100 {
+            hashed.get_tiles(&[i as f64 / 100.0; 8], &[]);
+        }
+        assert!(hashed.table().is_full());
+        assert!(hashed.table().collision_count() > 0);
+        let collision_count = hashed.table().collision_count();
+        let tiles = hashed.get_tiles(&[0.995; 8], &[]);
+        assert_eq!(hashed.get_tiles(&[0.995; 8], &[]), tiles);
+        assert_eq!(hashed.table().collision_count(), collision_count + 8);
+    }
+
+    #[test]
+    fn tiling_offsets() {
+        let dimensions = || {
+            vec![
+                ContinuousDimension::new(0.0, 8.0, 8),
+                ContinuousDimension::new(0.0, 16.0, 8),
+            ]
+        };
+        let offsets = |strategy| {
+            TilingSet::from_dimensions_with_offsets(&dimensions(), &Vec::new(), 4, &strategy)
+                .offsets()
+                .to_vec()
+        };
+
+        assert_eq!(
+            offsets(TilingOffsets::Uniform),
+            vec![
+                vec![0.0, 0.0],
+                vec![0.25, 0.5],
+                vec![0.5, 1.0],
+                vec![0.75, 1.5]
+            ]
+        );
+        assert_eq!(
+            offsets(TilingOffsets::Asymmetric),
+            vec![
+                vec![0.0, 0.0],
+                vec![0.25, 1.5],
+                vec![0.5, 1.0],
+                vec![0.75, 0.5]
+            ]
+        );
+
+        // Random offsets are within the tile and depend only on the seed.
+        let random = offsets(TilingOffsets::Random(0));
+        assert_eq!(random, offsets(TilingOffsets::Random(0)));
+        assert_ne!(random, offsets(TilingOffsets::Random(1)));
+        assert!(random.iter().all(|o| o[0] < 1.0 && o[1] < 2.0));
+    }
+
+    // Returns the number of tiles the point shares with the points moved from it by (d, d∙sign)
+    // for |d| up to the tile size, i.e. how much an update at the point generalizes along the
+    // diagonal (sign = 1) or across it (sign = -1).
+    fn shared_tiles(tilings: &TilingSet, x: f64, y: f64, sign: f64) -> usize {
+        let tiles = tilings.get_tiles(&[x, y], &[]);
+        (-9..10)
+            .filter(|i| *i != 0)
+            .map(|i| {
+                let d = i as f64 * 0.1;
+                let moved_tiles = tilings.get_tiles(&[x + d, y + d * sign], &[]);
+                tiles
+                    .iter()
+                    .zip(&moved_tiles)
+                    .filter(|(a, b)| a == b)
+                    .count()
+            })
+            .sum()
+    }
+
+    // Returns how different the generalization along and across the diagonal is, averaged over
+    // the points of a tile: 0 if it's the same for every point.
+    fn anisotropy(tilings: &TilingSet) -> f64 {
+        let mut total = 0.0;
+        for i in 0..10 {
+            for j in 0..10 {
+                let (x, y) = (5.05 + i as f64 * 0.1, 5.05 + j as f64 * 0.1);
+                let diagonal = shared_tiles(tilings, x, y, 1.0) as f64;
+                let anti_diagonal = shared_tiles(tilings, x, y, -1.0) as f64;
+                total += (diagonal - anti_diagonal).abs() / (diagonal + anti_diagonal);
+            }
+        }
+        total / 100.0
+    }
+
+    #[test]
+    fn generalization_shape() {
+        let tilings = |strategy| {
+            TilingSet::from_dimensions_with_offsets(
+                &vec![
+                    ContinuousDimension::new(0.0, 10.0, 10),
+                    ContinuousDimension::new(0.0, 10.0, 10),
+                ],
+                &Vec::new(),
+                8,
+                &strategy,
+            )
+        };
+        let uniform = tilings(TilingOffsets::Uniform);
+        let asymmetric = tilings(TilingOffsets::Asymmetric);
+        let random = tilings(TilingOffsets::Random(0));
+
+        // With uniform offsets, the tile boundaries of all the tilings lie on the same diagonals,
+        // so a point close to one of them generalizes mostly along the diagonal (Figure 9.11).
+        let diagonal = shared_tiles(&uniform, 5.05, 5.05, 1.0);
+        let anti_diagonal = shared_tiles(&uniform, 5.05, 5.05, -1.0);
+        println!("Uniform: {} along, {} across", diagonal, anti_diagonal);
+        assert!(diagonal > 2 * anti_diagonal);
+
+        // The other strategies generalize more evenly.
+        let (uniform, asymmetric, random) = (
+            anisotropy(&uniform),
+            anisotropy(&asymmetric),
+            anisotropy(&random),
+        );
+        println!(
+            "Anisotropy: uniform {:.3}, asymmetric {:.3}, random {:.3}",
+            uniform, asymmetric, random
+        );
+        assert!(asymmetric < 0.6 * uniform);
+        assert!(random < 0.8 * uniform);
+    }
+
+    #[test]
+    fn periodic_dimension() {
+        use std::f64::consts::PI;
+
+        let angle = ContinuousDimension::periodic(-PI, PI, 8);
+        let tilings = TilingSet::from_dimensions_with_offsets(
+            &vec![angle],
+            &Vec::new(),
+            4,
+            &TilingOffsets::Uniform,
+        );
+        assert_eq!(tilings.tile_count(), 32);
+
+        // -π and π are the same point, as are the angles that differ by 2π.
+        assert_eq!(
+            tilings.get_tiles(&[-PI], &[]),
+            tilings.get_tiles(&[PI], &[])
+        );
+        assert_eq!(
+            tilings.get_tiles(&[0.5], &[]),
+            tilings.get_tiles(&[0.5 + 2.0 * PI], &[])
+        );
+        assert_eq!(
+            tilings.get_tiles(&[0.5], &[]),
+            tilings.get_tiles(&[0.5 - 4.0 * PI], &[])
+        );
+
+        // The points on the two sides of ±π share the tiles of the offset tilings, just like
+        // the points on the two sides of 0.
+        let shared = |x: f64, y: f64| {
+            let tiles = tilings.get_tiles(&[x], &[]);
+            let other_tiles = tilings.get_tiles(&[y], &[]);
+            tiles
+                .iter()
+                .zip(&other_tiles)
+                .filter(|(a, b)| a == b)
+                .count()
+        };
+        assert_eq!(shared(PI - 0.01, -PI + 0.01), 3);
+        assert_eq!(shared(-0.01, 0.01), 3);
+
+        // Without wrapping, the far ends of the range never share tiles.
+        let tilings =
+            TilingSet::from_dimensions(&vec![ContinuousDimension::new(-PI, PI, 8)], &Vec::new(), 4);
+        let tiles = tilings.get_tiles(&[PI - 0.01], &[]);
+        let other_tiles = tilings.get_tiles(&[-PI + 0.01], &[]);
+        assert!(tiles.iter().zip(&other_tiles).all(|(a, b)| a != b));
+    }
 }